@@ -1,3 +1,5 @@
+use crate::eval::{Namespace, QualifiedName};
+use crate::lexer::Span;
 use indexmap::IndexMap;
 
 /// Full Molang program consisting of one or more statements.
@@ -14,10 +16,23 @@ pub enum Statement {
     Expr(Expr),
     /// Path assignment (temp./variable./context.).
     Assignment { target: Vec<String>, value: Expr },
+    /// `target[index] = value`, or `target[index] op= value` when `op` is set. The index is
+    /// evaluated once and the element is replaced inside the stored array.
+    IndexAssignment {
+        target: Vec<String>,
+        index: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+    },
     /// Nested block with its own statements.
     Block(Vec<Statement>),
-    /// `loop(count, expr_or_block)`
-    Loop { count: Expr, body: Box<Statement> },
+    /// `loop(count, expr_or_block)`, or `loop(count, index, expr_or_block)` storing the
+    /// zero-based iteration number in `index` before each pass.
+    Loop {
+        count: Expr,
+        index: Option<Vec<String>>,
+        body: Box<Statement>,
+    },
     /// `for_each(variable, collection, expr_or_block)`
     ForEach {
         variable: Vec<String>,
@@ -53,12 +68,28 @@ pub enum Expr {
     Call {
         target: Box<Expr>,
         args: Vec<Expr>,
+        /// Source range from the callee to the closing `)`, for error reporting; `None`
+        /// for calls not produced by the parser.
+        span: Option<Span>,
     },
     Flow(ControlFlowExpr),
     Index {
         target: Box<Expr>,
         index: Box<Expr>,
     },
+    /// `{ x: 1 }.x`: a field read from a struct literal (or from a field of one). Reads
+    /// through variables stay plain paths.
+    Member {
+        target: Box<Expr>,
+        field: String,
+    },
+    /// `match(subject) { label: result; default: result }`: the first case whose label
+    /// equals `subject` (evaluated once), else `default`, else `0`.
+    Match {
+        subject: Box<Expr>,
+        cases: Vec<(Expr, Expr)>,
+        default: Option<Box<Expr>>,
+    },
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -75,6 +106,10 @@ pub enum BinaryOp {
     NotEqual,
     And,
     Or,
+    /// Bitwise `&`, `|`, `^` on operands truncated to integers.
+    BitAnd,
+    BitOr,
+    BitXor,
     NullCoalesce,
 }
 
@@ -96,13 +131,78 @@ impl Program {
     pub fn as_jit_expression(&self) -> Option<&Expr> {
         if self.statements.len() == 1 {
             if let Statement::Expr(expr) = &self.statements[0] {
-                if !expr.contains_flow() && expr.is_jit_compatible() {
+                if !expr.contains_flow() && expr.is_jit_compatible() && !expr.may_concatenate() {
                     return Some(expr);
                 }
             }
         }
         None
     }
+
+    /// Explains why [`Program::as_jit_expression`] rejects this program (e.g. "contains
+    /// loop"), or `None` when it is a single cacheable expression.
+    pub fn fallback_reason(&self) -> Option<&'static str> {
+        match self.statements.as_slice() {
+            [] => Some("is empty"),
+            [Statement::Expr(expr)] => expr
+                .fallback_reason()
+                .or_else(|| expr.may_concatenate().then_some("may concatenate strings")),
+            [Statement::Assignment { .. } | Statement::IndexAssignment { .. }] => {
+                Some("contains assignment")
+            }
+            [Statement::Block(_)] => Some("contains block"),
+            [Statement::Loop { .. }] => Some("contains loop"),
+            [Statement::ForEach { .. }] => Some("contains for_each"),
+            [Statement::Return(_)] => Some("contains return"),
+            _ => Some("contains multiple statements"),
+        }
+    }
+
+    /// Canonical names of every `query.` path the program reads (`query.speed`), sorted
+    /// and without duplicates. Host function calls such as `query.double(2)` are not reads.
+    pub fn required_queries(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for statement in &self.statements {
+            statement.collect_queries(&mut names);
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+impl Statement {
+    fn collect_queries(&self, names: &mut Vec<String>) {
+        match self {
+            Statement::Expr(expr) | Statement::Assignment { value: expr, .. } => {
+                expr.collect_queries(names)
+            }
+            Statement::IndexAssignment { index, value, .. } => {
+                index.collect_queries(names);
+                value.collect_queries(names);
+            }
+            Statement::Block(statements) => {
+                for statement in statements {
+                    statement.collect_queries(names);
+                }
+            }
+            Statement::Loop { count, body, .. } => {
+                count.collect_queries(names);
+                body.collect_queries(names);
+            }
+            Statement::ForEach {
+                collection, body, ..
+            } => {
+                collection.collect_queries(names);
+                body.collect_queries(names);
+            }
+            Statement::Return(expr) => {
+                if let Some(expr) = expr {
+                    expr.collect_queries(names);
+                }
+            }
+        }
+    }
 }
 
 impl Expr {
@@ -129,14 +229,51 @@ impl Expr {
                         .map(|expr| expr.contains_flow())
                         .unwrap_or(false)
             }
-            Expr::Call { target, args } => {
+            Expr::Call { target, args, .. } => {
                 target.contains_flow() || args.iter().any(|expr| expr.contains_flow())
             }
             Expr::Index { target, index } => target.contains_flow() || index.contains_flow(),
+            Expr::Member { target, .. } => target.contains_flow(),
+            Expr::Match {
+                subject,
+                cases,
+                default,
+            } => {
+                subject.contains_flow()
+                    || cases
+                        .iter()
+                        .any(|(label, result)| label.contains_flow() || result.contains_flow())
+                    || default.as_ref().is_some_and(|expr| expr.contains_flow())
+            }
             Expr::Flow(_) => true,
         }
     }
 
+    /// True for a `+` whose operands may both hold strings at runtime (`temp.a + temp.b`),
+    /// mirroring `ir::is_string_concat`. As a script's result it may join strings, which
+    /// only the program path can store, so such expressions are not cached as pure ones.
+    pub fn may_concatenate(&self) -> bool {
+        fn may_hold_string(expr: &Expr) -> bool {
+            match expr {
+                Expr::Path(_) | Expr::String(_) => true,
+                // `math.*` builtins always yield numbers
+                Expr::Call { target, .. } => !matches!(
+                    target.as_ref(),
+                    Expr::Path(path) if path.first().is_some_and(|segment| segment.eq_ignore_ascii_case("math"))
+                ),
+                other => other.may_concatenate(),
+            }
+        }
+        match self {
+            Expr::Binary {
+                op: BinaryOp::Add,
+                left,
+                right,
+            } => may_hold_string(left) && may_hold_string(right),
+            _ => false,
+        }
+    }
+
     /// Determines if the expression is a pure expression suitable for caching.
     pub fn is_jit_compatible(&self) -> bool {
         match self {
@@ -157,14 +294,122 @@ impl Expr {
                         .map(|expr| expr.is_jit_compatible())
                         .unwrap_or(true)
             }
-            Expr::Call { target, args } => {
+            Expr::Call { target, args, .. } => {
                 target.is_jit_compatible() && args.iter().all(|expr| expr.is_jit_compatible())
             }
+            Expr::Match {
+                subject,
+                cases,
+                default,
+            } => {
+                subject.is_jit_compatible()
+                    && cases.iter().all(|(label, result)| {
+                        label.is_jit_compatible() && result.is_jit_compatible()
+                    })
+                    && default
+                        .as_ref()
+                        .map(|expr| expr.is_jit_compatible())
+                        .unwrap_or(true)
+            }
             Expr::String(_)
             | Expr::Array(_)
             | Expr::Struct(_)
             | Expr::Index { .. }
+            | Expr::Member { .. }
             | Expr::Flow(_) => false,
         }
     }
+
+    fn collect_queries(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Path(parts) => {
+                let name = QualifiedName::from_parts(parts);
+                if *name.namespace() == Namespace::Query && !name.key().is_empty() {
+                    names.push(name.to_string());
+                }
+            }
+            Expr::Number(_) | Expr::String(_) | Expr::Flow(_) => {}
+            Expr::Array(items) => items.iter().for_each(|item| item.collect_queries(names)),
+            Expr::Struct(fields) => fields
+                .values()
+                .for_each(|value| value.collect_queries(names)),
+            Expr::Unary { expr, .. } => expr.collect_queries(names),
+            Expr::Binary { left, right, .. } => {
+                left.collect_queries(names);
+                right.collect_queries(names);
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                condition.collect_queries(names);
+                then_branch.collect_queries(names);
+                if let Some(expr) = else_branch {
+                    expr.collect_queries(names);
+                }
+            }
+            // The target names the function being called, not a value being read
+            Expr::Call { args, .. } => args.iter().for_each(|arg| arg.collect_queries(names)),
+            Expr::Index { target, index } => {
+                target.collect_queries(names);
+                index.collect_queries(names);
+            }
+            Expr::Member { target, .. } => target.collect_queries(names),
+            Expr::Match {
+                subject,
+                cases,
+                default,
+            } => {
+                subject.collect_queries(names);
+                for (label, result) in cases {
+                    label.collect_queries(names);
+                    result.collect_queries(names);
+                }
+                if let Some(expr) = default {
+                    expr.collect_queries(names);
+                }
+            }
+        }
+    }
+
+    /// First reason this expression cannot be cached as a pure expression, if any.
+    pub fn fallback_reason(&self) -> Option<&'static str> {
+        match self {
+            Expr::Number(_) | Expr::Path(_) => None,
+            Expr::String(_) => Some("uses strings"),
+            Expr::Array(_) => Some("uses arrays"),
+            Expr::Struct(_) => Some("uses structs"),
+            Expr::Index { .. } => Some("uses indexing"),
+            Expr::Member { target, .. } => target.fallback_reason(),
+            Expr::Flow(_) => Some("contains break/continue"),
+            Expr::Unary { expr, .. } => expr.fallback_reason(),
+            Expr::Binary { left, right, .. } => {
+                left.fallback_reason().or_else(|| right.fallback_reason())
+            }
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => condition
+                .fallback_reason()
+                .or_else(|| then_branch.fallback_reason())
+                .or_else(|| else_branch.as_ref().and_then(|expr| expr.fallback_reason())),
+            Expr::Call { target, args, .. } => target
+                .fallback_reason()
+                .or_else(|| args.iter().find_map(|expr| expr.fallback_reason())),
+            Expr::Match {
+                subject,
+                cases,
+                default,
+            } => subject
+                .fallback_reason()
+                .or_else(|| {
+                    cases.iter().find_map(|(label, result)| {
+                        label.fallback_reason().or_else(|| result.fallback_reason())
+                    })
+                })
+                .or_else(|| default.as_ref().and_then(|expr| expr.fallback_reason())),
+        }
+    }
 }
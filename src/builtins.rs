@@ -1,14 +1,73 @@
 //! Host implementations of math helpers that mirror Molang `math.*` builtins.
+use crate::eval::NUMBER_EPSILON;
 use once_cell::sync::Lazy;
 use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
 /// Shared RNG used by all math.random helpers. Mutex guards concurrent JIT-compiled code.
 static RNG: Lazy<Mutex<SmallRng>> = Lazy::new(|| Mutex::new(SmallRng::from_entropy()));
 
+/// Seed mixed into every `math.noise` lattice value; set alongside the RNG by [`seed_rng`].
+static NOISE_SEED: AtomicU64 = AtomicU64::new(0);
+
+/// Random state owned by one `RuntimeContext` after `RuntimeContext::seed_rng`. Its seed
+/// also selects the `math.noise` field.
+#[derive(Debug, Clone)]
+pub(crate) struct ScriptRng {
+    rng: SmallRng,
+    seed: u64,
+}
+
+impl ScriptRng {
+    pub fn new(seed: u64) -> Self {
+        ScriptRng {
+            rng: SmallRng::seed_from_u64(seed),
+            seed,
+        }
+    }
+}
+
+thread_local! {
+    /// RNG of the context whose script is running on this thread, when it has its own.
+    static SCRIPT_RNG: RefCell<Option<ScriptRng>> = const { RefCell::new(None) };
+}
+
+/// Runs `run` with `rng` as the source for every random builtin on this thread, then hands
+/// the advanced state back. Without one, draws come from the shared RNG.
+pub(crate) fn with_script_rng<T>(
+    rng: Option<ScriptRng>,
+    run: impl FnOnce() -> T,
+) -> (T, Option<ScriptRng>) {
+    if rng.is_none() {
+        return (run(), None);
+    }
+    let previous = SCRIPT_RNG.with(|script| script.replace(rng));
+    let result = run();
+    (result, SCRIPT_RNG.with(|script| script.replace(previous)))
+}
+
 fn with_rng<T>(f: impl FnOnce(&mut SmallRng) -> T) -> T {
-    let mut rng = RNG.lock().expect("rng poisoned");
-    f(&mut rng)
+    SCRIPT_RNG.with(|script| match script.borrow_mut().as_mut() {
+        Some(script) => f(&mut script.rng),
+        None => f(&mut RNG.lock().expect("rng poisoned")),
+    })
+}
+
+fn noise_seed() -> u64 {
+    SCRIPT_RNG
+        .with(|script| script.borrow().as_ref().map(|script| script.seed))
+        .unwrap_or_else(|| NOISE_SEED.load(Ordering::Relaxed))
+}
+
+/// Reseeds the shared RNG so the `math.random*` and `math.die_roll*` draws that follow
+/// repeat for the same seed. The generator is process-global, so draws from other threads
+/// in between still advance it; `RuntimeContext::seed_rng` gives one context its own.
+/// `math.noise` switches to the noise field for `seed`.
+pub fn seed_rng(seed: u64) {
+    *RNG.lock().expect("rng poisoned") = SmallRng::seed_from_u64(seed);
+    NOISE_SEED.store(seed, Ordering::Relaxed);
 }
 
 fn normalize_low_high(mut low: f64, mut high: f64) -> (f64, f64) {
@@ -32,6 +91,30 @@ pub fn math_random_integer(low: f64, high: f64) -> f64 {
     with_rng(|rng| rng.gen_range(low..=high) as f64)
 }
 
+/// Index picked with probability proportional to its weight. Negative and non-finite
+/// weights count as zero; `0` when no weight is positive.
+pub fn math_weighted_index(weights: &[f64]) -> f64 {
+    let weight = |value: f64| if value.is_finite() { value.max(0.0) } else { 0.0 };
+    let total: f64 = weights.iter().copied().map(weight).sum();
+    if total <= 0.0 || !total.is_finite() {
+        return 0.0;
+    }
+    let mut remaining = with_rng(|rng| rng.gen_range(0.0..total));
+    let mut last_positive = 0;
+    for (index, value) in weights.iter().copied().map(weight).enumerate() {
+        if value <= 0.0 {
+            continue;
+        }
+        if remaining < value {
+            return index as f64;
+        }
+        remaining -= value;
+        last_positive = index;
+    }
+    // Rounding can leave a sliver past the final weight
+    last_positive as f64
+}
+
 pub fn math_clamp(value: f64, min: f64, max: f64) -> f64 {
     value.clamp(min, max)
 }
@@ -60,10 +143,29 @@ pub extern "C" fn builtin_math_clamp(value: f64, min: f64, max: f64) -> f64 {
     math_clamp(value, min, max)
 }
 
+/// Clamps `value` to `[0, 1]`, the usual range of interpolation factors.
+pub extern "C" fn builtin_math_clamp01(value: f64) -> f64 {
+    math_clamp(value, 0.0, 1.0)
+}
+
+/// Wraps `value` into `[min, max)`, for cyclic ranges such as hues or angles.
+pub extern "C" fn builtin_math_wrap(value: f64, min: f64, max: f64) -> f64 {
+    let range = max - min;
+    if range == 0.0 {
+        return min;
+    }
+    min + ((value - min) % range + range) % range
+}
+
 pub extern "C" fn builtin_math_sqrt(value: f64) -> f64 {
     value.sqrt()
 }
 
+/// Cube root; unlike `sqrt` it is defined for negative inputs (`cbrt(-8) == -2`).
+pub extern "C" fn builtin_math_cbrt(value: f64) -> f64 {
+    value.cbrt()
+}
+
 pub extern "C" fn builtin_math_floor(value: f64) -> f64 {
     value.floor()
 }
@@ -80,6 +182,11 @@ pub extern "C" fn builtin_math_trunc(value: f64) -> f64 {
     value.trunc()
 }
 
+/// Fractional part, keeping the sign of `value` (`-2.75` gives `-0.75`).
+pub extern "C" fn builtin_math_frac(value: f64) -> f64 {
+    value - value.trunc()
+}
+
 // Trigonometric functions (all in degrees, Molang convention)
 pub extern "C" fn builtin_math_acos(value: f64) -> f64 {
     value.acos().to_degrees()
@@ -97,6 +204,19 @@ pub extern "C" fn builtin_math_atan2(y: f64, x: f64) -> f64 {
     y.atan2(x).to_degrees()
 }
 
+// Hyperbolic functions take plain values, not degrees
+pub extern "C" fn builtin_math_sinh(value: f64) -> f64 {
+    value.sinh()
+}
+
+pub extern "C" fn builtin_math_cosh(value: f64) -> f64 {
+    value.cosh()
+}
+
+pub extern "C" fn builtin_math_tanh(value: f64) -> f64 {
+    value.tanh()
+}
+
 // Exponential and logarithmic functions
 pub extern "C" fn builtin_math_exp(value: f64) -> f64 {
     value.exp()
@@ -110,6 +230,10 @@ pub extern "C" fn builtin_math_pow(base: f64, exponent: f64) -> f64 {
     base.powf(exponent)
 }
 
+pub extern "C" fn builtin_math_square(value: f64) -> f64 {
+    value * value
+}
+
 // Basic arithmetic functions
 pub extern "C" fn builtin_math_max(a: f64, b: f64) -> f64 {
     a.max(b)
@@ -119,10 +243,87 @@ pub extern "C" fn builtin_math_min(a: f64, b: f64) -> f64 {
     a.min(b)
 }
 
+/// Truncated remainder: the result takes the sign of `value` (`mod(-1, 360) == -1`).
 pub extern "C" fn builtin_math_mod(value: f64, denominator: f64) -> f64 {
     value % denominator
 }
 
+/// Euclidean remainder, always in `[0, |denominator|)` (`mod_euclid(-1, 360) == 359`).
+pub extern "C" fn builtin_math_mod_euclid(value: f64, denominator: f64) -> f64 {
+    value.rem_euclid(denominator)
+}
+
+/// Rounds `value` to `places` decimal places; negative `places` round to tens, hundreds,
+/// and so on (`math.round_to(1234, -2)` is `1200`). `places` is truncated to an integer.
+pub extern "C" fn builtin_math_round_to(value: f64, places: f64) -> f64 {
+    let places = places.trunc();
+    let factor = 10f64.powf(places.abs());
+    if places >= 0.0 {
+        let scaled = value * factor;
+        // Past f64 precision there are no fractional digits left to round
+        if scaled.is_finite() {
+            scaled.round() / factor
+        } else {
+            value
+        }
+    } else if factor.is_finite() {
+        (value / factor).round() * factor
+    } else {
+        // A power of ten beyond f64 range rounds every finite value to zero
+        0.0
+    }
+}
+
+/// Rounds `value` to the nearest multiple of `step`; a zero step leaves it unchanged.
+pub extern "C" fn builtin_math_snap(value: f64, step: f64) -> f64 {
+    if step == 0.0 {
+        value
+    } else {
+        (value / step).round() * step
+    }
+}
+
+/// Rounds `value` down to a multiple of `step` (`math.floor_to(1.3, 0.5)` is `1`); a zero
+/// step leaves it unchanged.
+pub extern "C" fn builtin_math_floor_to(value: f64, step: f64) -> f64 {
+    if step == 0.0 {
+        value
+    } else {
+        step_quotient(value, step).floor() * step
+    }
+}
+
+/// Rounds `value` up to a multiple of `step` (`math.ceil_to(1.3, 0.5)` is `1.5`); a zero
+/// step leaves it unchanged.
+pub extern "C" fn builtin_math_ceil_to(value: f64, step: f64) -> f64 {
+    if step == 0.0 {
+        value
+    } else {
+        step_quotient(value, step).ceil() * step
+    }
+}
+
+/// `value / step`, snapped to the nearest integer when within [`NUMBER_EPSILON`] of it so
+/// that `math.floor_to(0.3, 0.1)` is not thrown a whole step off by rounding error.
+fn step_quotient(value: f64, step: f64) -> f64 {
+    let quotient = value / step;
+    let nearest = quotient.round();
+    if (quotient - nearest).abs() <= NUMBER_EPSILON {
+        nearest
+    } else {
+        quotient
+    }
+}
+
+/// `part / whole`, or `0` when `whole` is zero instead of an infinity or NaN.
+pub extern "C" fn builtin_math_ratio(part: f64, whole: f64) -> f64 {
+    if whole == 0.0 {
+        0.0
+    } else {
+        part / whole
+    }
+}
+
 pub extern "C" fn builtin_math_sign(value: f64) -> f64 {
     if value > 0.0 {
         1.0
@@ -155,6 +356,17 @@ pub extern "C" fn builtin_math_lerp(start: f64, end: f64, t: f64) -> f64 {
     start + (end - start) * t
 }
 
+/// `math.noise(x)`: [`math_noise`] with the running context's seed, else the one last
+/// passed to [`seed_rng`] (`0` before).
+pub extern "C" fn builtin_math_noise(x: f64) -> f64 {
+    math_noise(x, noise_seed())
+}
+
+/// Like `math.lerp`, but saturates `t` to `[0, 1]` so it never extrapolates.
+pub extern "C" fn builtin_math_lerp_clamped(start: f64, end: f64, t: f64) -> f64 {
+    builtin_math_lerp(start, end, math_clamp(t, 0.0, 1.0))
+}
+
 pub extern "C" fn builtin_math_inverse_lerp(start: f64, end: f64, value: f64) -> f64 {
     if (end - start).abs() < f64::EPSILON {
         0.0
@@ -163,6 +375,32 @@ pub extern "C" fn builtin_math_inverse_lerp(start: f64, end: f64, value: f64) ->
     }
 }
 
+/// Remaps `value` from `[in_min, in_max]` onto `[out_min, out_max]` without clamping; an
+/// empty input range maps everything to `out_min`.
+pub extern "C" fn builtin_math_map_range(
+    value: f64,
+    in_min: f64,
+    in_max: f64,
+    out_min: f64,
+    out_max: f64,
+) -> f64 {
+    let range = in_max - in_min;
+    if range == 0.0 {
+        out_min
+    } else {
+        out_min + (value - in_min) * (out_max - out_min) / range
+    }
+}
+
+/// `1` when `a` and `b` differ by at most `epsilon`, else `0`.
+pub extern "C" fn builtin_math_approx_equal(a: f64, b: f64, epsilon: f64) -> f64 {
+    if (a - b).abs() <= epsilon {
+        1.0
+    } else {
+        0.0
+    }
+}
+
 pub extern "C" fn builtin_math_lerprotate(start: f64, end: f64, t: f64) -> f64 {
     let mut diff = (end - start) % 360.0;
     if diff > 180.0 {
@@ -177,6 +415,70 @@ pub extern "C" fn builtin_math_hermite_blend(t: f64) -> f64 {
     3.0 * t * t - 2.0 * t * t * t
 }
 
+/// Position of `x` between the edges, clamped to `[0, 1]`; equal edges act as a step.
+fn smooth_t(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge0 == edge1 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+    math_clamp((x - edge0) / (edge1 - edge0), 0.0, 1.0)
+}
+
+/// Hermite falloff `t²(3 - 2t)` of `x` between `edge0` and `edge1`.
+pub extern "C" fn builtin_math_smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = smooth_t(edge0, edge1, x);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Perlin's smoother falloff `t³(t(6t - 15) + 10)`, flat in both value and slope at the edges.
+pub extern "C" fn builtin_math_smootherstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = smooth_t(edge0, edge1, x);
+    t * t * t * (t * (6.0 * t - 15.0) + 10.0)
+}
+
+// Color functions
+/// Packs 0-255 channels as `r * 65536 + g * 256 + b` (channels are rounded and clamped).
+pub extern "C" fn builtin_math_rgb(r: f64, g: f64, b: f64) -> f64 {
+    let channel = |value: f64| math_clamp(value.round(), 0.0, 255.0);
+    channel(r) * 65536.0 + channel(g) * 256.0 + channel(b)
+}
+
+/// Unpacks channel `index` (0 = red, 1 = green, 2 = blue) from a `math.rgb` value.
+pub extern "C" fn builtin_math_rgb_channel(packed: f64, index: f64) -> f64 {
+    let shift = match index as i64 {
+        0 => 16,
+        1 => 8,
+        2 => 0,
+        _ => return 0.0,
+    };
+    let packed = math_clamp(packed, 0.0, 16_777_215.0) as u32;
+    f64::from((packed >> shift) & 0xFF)
+}
+
+/// Pseudo-random value in `[-1, 1]` for an integer lattice point (SplitMix64 finalizer).
+fn lattice_value(cell: i64, seed: u64) -> f64 {
+    let mut z = (cell as u64 ^ seed).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// 1D value noise in `[-1, 1]`: a hashed value at each integer, smoothstepped in between,
+/// so the result is continuous in `x` and repeats for the same input and seed. Non-finite
+/// inputs give `0`.
+pub fn math_noise(x: f64, seed: u64) -> f64 {
+    if !x.is_finite() {
+        return 0.0;
+    }
+    let floor = x.floor();
+    let cell = floor as i64;
+    let t = x - floor;
+    let t = t * t * (3.0 - 2.0 * t);
+    let start = lattice_value(cell, seed);
+    let end = lattice_value(cell.wrapping_add(1), seed);
+    start + (end - start) * t
+}
+
 // Die roll functions
 pub extern "C" fn builtin_math_die_roll(num: f64, low: f64, high: f64) -> f64 {
     let count = num.max(0.0) as i32;
@@ -1,9 +1,12 @@
+use crate::builtins::ScriptRng;
 use indexmap::IndexMap;
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
 
 /// Namespace qualifiers supported by Molang (`temp`, `variable`, `context`).
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Namespace {
     Temp,
     Variable,
@@ -25,7 +28,9 @@ impl Namespace {
         }
     }
 
-    fn from_prefix(segment: &str) -> Option<Self> {
+    /// Parses a namespace prefix in any case, long or short (`temp`/`t`, `variable`/`v`,
+    /// `context`/`c`, `query`/`q`).
+    pub fn from_prefix(segment: &str) -> Option<Self> {
         match segment.to_ascii_lowercase().as_str() {
             "temp" | "t" => Some(Namespace::Temp),
             "variable" | "var" | "v" => Some(Namespace::Variable),
@@ -63,6 +68,10 @@ impl QualifiedName {
         Self { namespace, key }
     }
 
+    /// Canonicalizes a parsed path: a leading namespace prefix (`t`, `v`, ...) selects the
+    /// namespace, otherwise the path is a bare `variable.` path; key segments are lowercased.
+    /// A lone namespace (`temp`) gets an empty key. Inverse of [`QualifiedName::to_path`]:
+    /// `from_parts(&name.to_path()) == name` for every canonical name.
     pub fn from_parts(parts: &[String]) -> Self {
         let mut iter = parts.iter();
         let first = iter.next().cloned().unwrap_or_default();
@@ -76,15 +85,11 @@ impl QualifiedName {
         } else {
             std::iter::once(first).chain(iter.cloned()).collect()
         };
-        let key = if remaining.is_empty() {
-            String::from("_")
-        } else {
-            remaining
-                .iter()
-                .map(|segment| segment.to_ascii_lowercase())
-                .collect::<Vec<_>>()
-                .join(".")
-        };
+        let key = remaining
+            .iter()
+            .map(|segment| segment.to_ascii_lowercase())
+            .collect::<Vec<_>>()
+            .join(".");
         Self { namespace, key }
     }
 
@@ -104,6 +109,8 @@ impl QualifiedName {
         }
     }
 
+    /// Full path with the namespace spelled out (`["temp", "a", "b"]`); an empty key
+    /// contributes no segments.
     pub fn to_path(&self) -> Vec<String> {
         let mut parts = vec![self.namespace.prefix().to_string()];
         if !self.key.is_empty() {
@@ -115,7 +122,11 @@ impl QualifiedName {
 
 impl fmt::Display for QualifiedName {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}.{}", self.namespace, self.key)
+        if self.key.is_empty() {
+            write!(f, "{}", self.namespace)
+        } else {
+            write!(f, "{}.{}", self.namespace, self.key)
+        }
     }
 }
 
@@ -129,10 +140,40 @@ pub enum Value {
     Null,
 }
 
+/// Largest magnitude an `f64` holds without losing integer precision (2^53).
+#[cfg_attr(not(any(feature = "serde", feature = "json")), allow(dead_code))]
+pub(crate) const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Tolerance used when comparing numbers for equality, absorbing float rounding noise.
+pub const NUMBER_EPSILON: f64 = 1e-9;
+
+/// Molang equality: numbers within [`NUMBER_EPSILON`], strings exactly, arrays element-wise
+/// in order, structs by matching field sets regardless of insertion order, and
+/// `null == null`. Values of different types are never equal.
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Number(left), Value::Number(right)) => {
+                left == right || (left - right).abs() <= NUMBER_EPSILON
+            }
+            (Value::String(left), Value::String(right)) => left == right,
+            (Value::Array(left), Value::Array(right)) => left == right,
+            (Value::Struct(left), Value::Struct(right)) => {
+                left.len() == right.len()
+                    && left
+                        .iter()
+                        .all(|(key, value)| right.get(key).is_some_and(|other| value == other))
+            }
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+}
+
 impl Value {
-    /// Wraps a numeric literal.
+    /// Wraps a numeric literal, folding `-0.0` into `0.0`.
     pub fn number(value: f64) -> Self {
-        Value::Number(value)
+        Value::Number(normalize_zero(value))
     }
 
     pub fn string(value: impl Into<String>) -> Self {
@@ -143,6 +184,29 @@ impl Value {
         Value::Array(values)
     }
 
+    /// Lowercase name of the variant, for messages shown to script authors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Struct(_) => "struct",
+            Value::Null => "null",
+        }
+    }
+
+    /// Numeric type tag returned by `typeof`: `0` null, `1` number, `2` string, `3` array,
+    /// `4` struct.
+    pub fn type_tag(&self) -> f64 {
+        match self {
+            Value::Null => 0.0,
+            Value::Number(_) => 1.0,
+            Value::String(_) => 2.0,
+            Value::Array(_) => 3.0,
+            Value::Struct(_) => 4.0,
+        }
+    }
+
     pub fn as_number(&self) -> f64 {
         match self {
             Value::Number(value) => *value,
@@ -161,6 +225,49 @@ impl Value {
         }
     }
 
+    /// Structural equality; same as `==`, kept as a named method for call sites that read
+    /// better with it.
+    pub fn deep_equals(&self, other: &Value) -> bool {
+        self == other
+    }
+
+    /// Renders the value as Molang source that evaluates back to it (`'hi'`, `[1, 2]`,
+    /// `{ x: 1, y: { z: 2 } }`). Backslashes and quotes inside strings are escaped. `None`
+    /// for values a literal cannot express: `null` and non-finite numbers.
+    pub fn to_molang_literal(&self) -> Option<String> {
+        match self {
+            Value::Number(number) if number.is_finite() => Some(self.to_string()),
+            Value::Number(_) | Value::Null => None,
+            Value::String(text) => Some(format!(
+                "'{}'",
+                text.replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace('\n', "\\n")
+                    .replace('\t', "\\t")
+                    .replace('\r', "\\r")
+                    .replace('\0', "\\0")
+            )),
+            Value::Array(values) => {
+                let items = values
+                    .iter()
+                    .map(Value::to_molang_literal)
+                    .collect::<Option<Vec<_>>>()?;
+                Some(format!("[{}]", items.join(", ")))
+            }
+            Value::Struct(fields) => {
+                let entries = fields
+                    .iter()
+                    .map(|(key, value)| Some(format!("{key}: {}", value.to_molang_literal()?)))
+                    .collect::<Option<Vec<_>>>()?;
+                if entries.is_empty() {
+                    Some("{}".to_string())
+                } else {
+                    Some(format!("{{ {} }}", entries.join(", ")))
+                }
+            }
+        }
+    }
+
     pub fn as_array(&self) -> Option<&[Value]> {
         match self {
             Value::Array(values) => Some(values),
@@ -176,10 +283,155 @@ impl Value {
     }
 }
 
+/// Canonical text form: numbers without trailing zeros (`3`, `2.5`), double-quoted strings,
+/// `[a, b, c]` arrays, `{key: value}` structs and `null`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", normalize_zero(*number)),
+            Value::String(text) => write!(f, "\"{text}\""),
+            Value::Array(values) => {
+                f.write_str("[")?;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_str("]")
+            }
+            Value::Struct(fields) => {
+                f.write_str("{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{key}: {value}")?;
+                }
+                f.write_str("}")
+            }
+            Value::Null => f.write_str("null"),
+        }
+    }
+}
+
+/// Maps an array index onto `0..len`. Negative indices count from the end (`-1` is the
+/// last element, as in Python) and indices past either end wrap around, so `len` reads
+/// element `0`. Returns `None` for empty arrays.
+pub fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let len = i64::try_from(len).ok().filter(|len| *len > 0)?;
+    usize::try_from(index.rem_euclid(len)).ok()
+}
+
+/// Recoverable issue noticed while a script runs; execution continues with a fallback.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    /// An array read or element assignment used an index outside `-length..length` and was
+    /// wrapped instead.
+    IndexOutOfRange {
+        path: String,
+        index: i64,
+        length: usize,
+    },
+    /// An element assignment targeted a path holding no array; the value was left as is.
+    NotIndexable {
+        path: String,
+        type_name: &'static str,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::IndexOutOfRange {
+                path,
+                index,
+                length,
+            } => write!(
+                f,
+                "index {index} out of range for `{path}` (length {length})"
+            ),
+            Diagnostic::NotIndexable { path, type_name } => {
+                write!(
+                    f,
+                    "cannot assign an element of `{path}`, which holds a {type_name}"
+                )
+            }
+        }
+    }
+}
+
+/// Runtime failure raised while compiled code runs; reported once execution returns.
+#[derive(Debug, Clone, Error)]
+pub enum ExecError {
+    #[error("undefined variable `{name}`")]
+    UndefinedVariable { name: String },
+    #[error("cannot index `{name}`, which holds a {type_name}")]
+    NotIndexable {
+        name: String,
+        type_name: &'static str,
+    },
+}
+
+/// Host callback invoked when a script calls a registered function.
+pub type HostFunction = Arc<dyn Fn(&[f64]) -> Value + Send + Sync>;
+
+/// Host functions keyed by canonical name (`query.double`).
+#[derive(Clone, Default)]
+struct HostFunctions(HashMap<String, HostFunction>);
+
+impl fmt::Debug for HostFunctions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Iterations a `loop` may run when the context sets no limit (Bedrock's cap).
+pub const DEFAULT_MAX_LOOP_ITERATIONS: usize = 1024;
+
+/// Host callback answering reads of paths that hold no stored value, given the namespace
+/// and the lowercased dotted key (`variable.foo.bar` arrives as `Variable`, `"foo.bar"`).
+pub type FallbackResolver = Arc<dyn Fn(&Namespace, &str) -> Option<Value> + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct Fallback(Option<FallbackResolver>);
+
+impl fmt::Debug for Fallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+/// Stored values of a [`RuntimeContext`] at one point in time, from
+/// [`RuntimeContext::snapshot`].
+#[derive(Debug, Clone)]
+pub struct ContextSnapshot {
+    values: HashMap<QualifiedName, Value>,
+    queries: Arc<QueryMap>,
+}
+
+/// `query.*` entries. Each value sits behind its own `Arc`, so unsharing the map after a
+/// clone copies pointers rather than the values themselves.
+type QueryMap = HashMap<QualifiedName, Arc<Value>>;
+
 /// Runtime storage for variables. Acts like Bedrock's mutable variable scopes.
 #[derive(Debug, Clone, Default)]
 pub struct RuntimeContext {
     values: HashMap<QualifiedName, Value>,
+    /// `query.*` entries, kept apart because scripts never write them: clones share the
+    /// map and only copy it when one side sets a query, and even then share every value.
+    queries: Arc<QueryMap>,
+    diagnostics: Vec<Diagnostic>,
+    return_value: Option<Value>,
+    /// Set while `evaluate_value` runs; other evaluations skip copying returned values.
+    capture_returns: bool,
+    functions: HostFunctions,
+    strict_reads: bool,
+    strict_indexing: bool,
+    exec_error: Option<ExecError>,
+    fallback: Fallback,
+    max_loop_iterations: Option<usize>,
+    rng: Option<ScriptRng>,
 }
 
 impl RuntimeContext {
@@ -198,11 +450,31 @@ impl RuntimeContext {
             namespace,
             key: name.into().to_ascii_lowercase(),
         };
-        self.values.insert(key, value);
+        self.store_value(key, value);
     }
 
     pub fn set_value_with_name(&mut self, name: QualifiedName, value: Value) {
-        self.values.insert(name, value);
+        self.store_value(name, value);
+    }
+
+    /// The entry stored under exactly `name`: queries live in the shared map, the rest in
+    /// `values`.
+    fn stored(&self, name: &QualifiedName) -> Option<&Value> {
+        if name.namespace == Namespace::Query {
+            self.queries.get(name).map(Arc::as_ref)
+        } else {
+            self.values.get(name)
+        }
+    }
+
+    /// Stores `value` under exactly `name`. A query write unshares the query map, copying
+    /// only the pointers to the other queries.
+    fn store_value(&mut self, name: QualifiedName, value: Value) {
+        if name.namespace == Namespace::Query {
+            Arc::make_mut(&mut self.queries).insert(name, Arc::new(value));
+        } else {
+            self.values.insert(name, value);
+        }
     }
 
     /// Convenience setter for string path segments.
@@ -245,6 +517,37 @@ impl RuntimeContext {
         self.lookup_namespace_path(namespace, &segments)
     }
 
+    /// Borrows the string stored at `canonical`, or `None` if missing or not a string.
+    pub fn get_string(&self, canonical: &str) -> Option<&str> {
+        match self.get_value_ref_canonical(canonical)? {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    /// True when `canonical` holds a string, stored or from the fallback resolver.
+    pub(crate) fn holds_string_canonical(&self, canonical: &str) -> bool {
+        match self.get_value_ref_canonical(canonical) {
+            Some(value) => matches!(value, Value::String(_)),
+            None => matches!(self.get_value_canonical(canonical), Some(Value::String(_))),
+        }
+    }
+
+    /// Borrows the array stored at `canonical`, or `None` if missing or not an array.
+    pub fn get_array(&self, canonical: &str) -> Option<&[Value]> {
+        self.get_value_ref_canonical(canonical)?.as_array()
+    }
+
+    /// Borrows the struct stored at `canonical`, or `None` if missing or not a struct.
+    pub fn get_struct(&self, canonical: &str) -> Option<&IndexMap<String, Value>> {
+        self.get_value_ref_canonical(canonical)?.as_struct()
+    }
+
+    fn get_value_ref_canonical(&self, canonical: &str) -> Option<&Value> {
+        let (namespace, segments) = parse_canonical_path(canonical)?;
+        self.lookup_namespace_ref(namespace, &segments)
+    }
+
     pub fn set_number_canonical(&mut self, canonical: &str, value: f64) {
         if let Some((namespace, segments)) = parse_canonical_path(canonical) {
             if namespace == Namespace::Query || segments.is_empty() {
@@ -283,7 +586,7 @@ impl RuntimeContext {
             } else {
                 format!("{key}.")
             };
-            self.values.retain(|name, _| {
+            let keep = |name: &QualifiedName| {
                 if name.namespace() != &namespace {
                     return true;
                 }
@@ -296,18 +599,165 @@ impl RuntimeContext {
                 } else {
                     !current.starts_with(&prefix)
                 }
-            });
+            };
+            if namespace == Namespace::Query {
+                Arc::make_mut(&mut self.queries).retain(|name, _| keep(name));
+            } else {
+                self.values.retain(|name, _| keep(name));
+            }
+        }
+    }
+
+    /// Removes every value in `namespace`, e.g. `Namespace::Temp` to drop scratch state
+    /// between runs while `variable.*`, `context.*` and `query.*` stay untouched.
+    pub fn clear_namespace(&mut self, namespace: Namespace) {
+        if namespace == Namespace::Query {
+            self.queries = Arc::default();
+        } else {
+            self.values.retain(|name, _| name.namespace != namespace);
+        }
+    }
+
+    /// Captures all stored values (every namespace) so a run can later be rolled back
+    /// with [`RuntimeContext::restore`]. Host functions and settings are not included.
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            values: self.values.clone(),
+            queries: Arc::clone(&self.queries),
+        }
+    }
+
+    /// Replaces all stored values with those captured by `snapshot`, discarding anything
+    /// assigned since.
+    pub fn restore(&mut self, snapshot: ContextSnapshot) {
+        self.values = snapshot.values;
+        self.queries = snapshot.queries;
+    }
+
+    /// Copies every stored value of `other` into this context, keeping namespaces. On a
+    /// collision `overwrite` picks the side that wins; a struct taken from `other`
+    /// replaces the whole struct here, nested fields included. Fields only one side has
+    /// end up in the parent struct either way, so its nested paths stay consistent.
+    pub fn merge(&mut self, other: &RuntimeContext, overwrite: bool) {
+        let mut entries: Vec<_> = other.entries().collect();
+        // Parents sort before their nested paths (`a` < `a.x`)
+        entries.sort_by(|(a, _), (b, _)| (&a.namespace, &a.key).cmp(&(&b.namespace, &b.key)));
+        for (name, value) in entries {
+            if self.stored(name).is_some() {
+                if !overwrite {
+                    continue;
+                }
+                self.clear_value_canonical(&name.to_string());
+            }
+            let segments: Vec<String> = name.key.split('.').map(str::to_string).collect();
+            self.assign_nested(name.namespace.clone(), &segments, value.clone());
         }
     }
 
     pub fn copy_value_canonical(&mut self, dest: &str, src: &str) {
-        if let Some(value) = self.get_value_canonical(src) {
+        if let Some(value) = self.read_value_canonical(src) {
             self.set_value_canonical(dest, value);
         } else {
             self.clear_value_canonical(dest);
         }
     }
 
+    /// `get_value_canonical` for script reads: a missing path goes through
+    /// `note_missing_read`, so strict mode rejects it.
+    pub(crate) fn read_value_canonical(&mut self, canonical: &str) -> Option<Value> {
+        let value = self.get_value_canonical(canonical);
+        if value.is_none() {
+            self.note_missing_read(canonical);
+        }
+        value
+    }
+
+    /// Fail-fast mode: reading an unset `temp.`/`variable.` path makes the evaluation
+    /// return `ExecError::UndefinedVariable` instead of yielding `0`. Queries and
+    /// `context.` stay lenient.
+    pub fn strict_reads(mut self, strict: bool) -> Self {
+        self.strict_reads = strict;
+        self
+    }
+
+    pub fn set_strict_reads(&mut self, strict: bool) {
+        self.strict_reads = strict;
+    }
+
+    /// Indexing a stored value that is not an array (`temp.n[0]` after `temp.n = 5;`)
+    /// makes the evaluation return `ExecError::NotIndexable` instead of yielding `0`.
+    pub fn strict_indexing(mut self, strict: bool) -> Self {
+        self.strict_indexing = strict;
+        self
+    }
+
+    pub fn set_strict_indexing(&mut self, strict: bool) {
+        self.strict_indexing = strict;
+    }
+
+    /// Caps how many times any `loop` runs; larger counts are clamped to `limit`.
+    pub fn with_max_loop_iterations(mut self, limit: usize) -> Self {
+        self.max_loop_iterations = Some(limit);
+        self
+    }
+
+    pub fn set_max_loop_iterations(&mut self, limit: usize) {
+        self.max_loop_iterations = Some(limit);
+    }
+
+    /// Current `loop` cap, [`DEFAULT_MAX_LOOP_ITERATIONS`] unless configured.
+    pub fn max_loop_iterations(&self) -> usize {
+        self.max_loop_iterations
+            .unwrap_or(DEFAULT_MAX_LOOP_ITERATIONS)
+    }
+
+    /// Gives this context its own random generator seeded with `seed`. Scripts run against
+    /// it draw `math.random*`, `math.die_roll*` and `math.weighted_index` from it and use
+    /// the `seed` noise field, so its sequence does not depend on other contexts.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.seed_rng(seed);
+        self
+    }
+
+    /// Reseeds this context's random generator, restarting its sequence.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = Some(ScriptRng::new(seed));
+    }
+
+    pub(crate) fn take_rng(&mut self) -> Option<ScriptRng> {
+        self.rng.take()
+    }
+
+    pub(crate) fn restore_rng(&mut self, rng: Option<ScriptRng>) {
+        self.rng = rng;
+    }
+
+    /// Records a read of the missing path `canonical`; only the first one is kept.
+    pub fn note_missing_read(&mut self, canonical: &str) {
+        if !self.strict_reads || self.exec_error.is_some() {
+            return;
+        }
+        if let Some((Namespace::Temp | Namespace::Variable, _)) = parse_canonical_path(canonical) {
+            self.exec_error = Some(ExecError::UndefinedVariable {
+                name: canonical.to_string(),
+            });
+        }
+    }
+
+    /// Installs a catch-all resolver consulted when a read finds no stored value, so one
+    /// closure can back a whole dynamic variable space. Stored values always win, and
+    /// borrowing accessors such as `get_string` do not see resolved values.
+    pub fn set_fallback_resolver(
+        &mut self,
+        resolver: impl Fn(&Namespace, &str) -> Option<Value> + Send + Sync + 'static,
+    ) {
+        self.fallback = Fallback(Some(Arc::new(resolver)));
+    }
+
+    pub(crate) fn take_exec_error(&mut self) -> Option<ExecError> {
+        self.exec_error.take()
+    }
+
     pub fn array_push_number_canonical(&mut self, canonical: &str, value: f64) {
         self.array_push_value_canonical(canonical, Value::number(value));
     }
@@ -331,15 +781,198 @@ impl RuntimeContext {
             .unwrap_or(0.0)
     }
 
+    /// `array_get_number_canonical` for script reads: records an `IndexOutOfRange`
+    /// diagnostic, and in strict modes the `NotIndexable` or missing-read error.
+    pub(crate) fn read_element_number_canonical(&mut self, canonical: &str, index: f64) -> f64 {
+        match self.get_value_ref_canonical(canonical) {
+            Some(Value::Array(values)) => {
+                let length = values.len();
+                let idx = index as i64;
+                let bound = i64::try_from(length).unwrap_or(i64::MAX);
+                if idx < -bound || idx >= bound {
+                    self.diagnostics.push(Diagnostic::IndexOutOfRange {
+                        path: canonical.to_string(),
+                        index: idx,
+                        length,
+                    });
+                }
+            }
+            Some(other) if self.strict_indexing && self.exec_error.is_none() => {
+                self.exec_error = Some(ExecError::NotIndexable {
+                    name: canonical.to_string(),
+                    type_name: other.type_name(),
+                });
+            }
+            Some(_) => {}
+            None => {
+                if self.get_value_canonical(canonical).is_none() {
+                    self.note_missing_read(canonical);
+                }
+            }
+        }
+        self.array_get_number_canonical(canonical, index)
+    }
+
+    /// True when `index` lies within `-length..length` of the array at `canonical` and
+    /// names a non-null element.
+    pub fn array_has_element_canonical(&self, canonical: &str, index: f64) -> bool {
+        let Some(Value::Array(values)) = self.get_value_ref_canonical(canonical) else {
+            return false;
+        };
+        let idx = index as i64;
+        let bound = i64::try_from(values.len()).unwrap_or(i64::MAX);
+        (-bound..bound).contains(&idx)
+            && resolve_index(idx, values.len())
+                .and_then(|position| values.get(position))
+                .is_some_and(|value| !matches!(value, Value::Null))
+    }
+
+    /// Replaces the element at `index` of the array stored at `canonical`, resolving the
+    /// index like reads do. The array is updated where it is stored, both under its own
+    /// key and inside any enclosing struct, without rebuilding it. A target holding no
+    /// array is left untouched and reported as `NotIndexable`: an error under strict
+    /// indexing, a diagnostic otherwise.
+    pub fn array_set_value_canonical(&mut self, canonical: &str, index: f64, value: Value) {
+        let Some((namespace, segments)) = parse_canonical_path(canonical) else {
+            return;
+        };
+        if namespace == Namespace::Query || segments.is_empty() {
+            return;
+        }
+        let length = match self.lookup_namespace_ref(namespace.clone(), &segments) {
+            Some(Value::Array(values)) => values.len(),
+            other => {
+                let type_name = other.map_or("null", Value::type_name);
+                if self.strict_indexing {
+                    if self.exec_error.is_none() {
+                        self.exec_error = Some(ExecError::NotIndexable {
+                            name: canonical.to_string(),
+                            type_name,
+                        });
+                    }
+                } else {
+                    self.diagnostics.push(Diagnostic::NotIndexable {
+                        path: canonical.to_string(),
+                        type_name,
+                    });
+                }
+                return;
+            }
+        };
+        let idx = index as i64;
+        let bound = i64::try_from(length).unwrap_or(i64::MAX);
+        if idx < -bound || idx >= bound {
+            self.diagnostics.push(Diagnostic::IndexOutOfRange {
+                path: canonical.to_string(),
+                index: idx,
+                length,
+            });
+        }
+        let Some(position) = resolve_index(idx, length) else {
+            return;
+        };
+        // Queries were rejected above, so the array lives in `values`
+        let store = &mut self.values;
+        for depth in 1..=segments.len() {
+            let key = QualifiedName::new(namespace.clone(), segments[..depth].join("."));
+            let element = store
+                .get_mut(&key)
+                .and_then(|entry| lookup_nested_mut(entry, &segments[depth..]))
+                .and_then(|array| match array {
+                    Value::Array(values) => values.get_mut(position),
+                    _ => None,
+                });
+            if let Some(element) = element {
+                *element = value.clone();
+            }
+        }
+    }
+
+    /// Diagnostics recorded by scripts run against this context, oldest first. They
+    /// accumulate across runs until drained with `take_diagnostics`.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Registers a host function callable from scripts as `name(...)`, e.g. `query.double`.
+    /// Names without a namespace prefix land in `variable.` like any other path.
+    pub fn register_function(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[f64]) -> f64 + Send + Sync + 'static,
+    ) {
+        self.insert_function(name, Arc::new(move |args| Value::number(function(args))));
+    }
+
+    /// Registers a host function returning a full value, so scripts can assign its
+    /// struct/array result (`temp.p = query.get_position();`) and read fields from it.
+    pub fn register_value_function(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[f64]) -> Value + Send + Sync + 'static,
+    ) {
+        self.insert_function(name, Arc::new(function));
+    }
+
+    fn insert_function(&mut self, name: &str, function: HostFunction) {
+        let parts: Vec<String> = name.split('.').map(str::to_string).collect();
+        let canonical = QualifiedName::from_parts(&parts).to_string();
+        self.functions.0.insert(canonical, function);
+    }
+
+    /// Canonical names of every registered host function.
+    pub fn function_names(&self) -> impl Iterator<Item = &str> {
+        self.functions.0.keys().map(String::as_str)
+    }
+
+    /// Calls the host function registered under `canonical`, if any.
+    pub fn call_function_canonical(&self, canonical: &str, args: &[f64]) -> Option<Value> {
+        self.functions
+            .0
+            .get(canonical)
+            .map(|function| function(args))
+    }
+
+    /// Remembers the full value a script returned from `canonical`, beyond its number, when
+    /// the caller asked for it.
+    pub fn capture_return_canonical(&mut self, canonical: &str) {
+        if self.capture_returns {
+            self.return_value = self.get_value_canonical(canonical);
+        }
+    }
+
+    pub(crate) fn set_capture_returns(&mut self, capture: bool) {
+        self.capture_returns = capture;
+    }
+
+    pub(crate) fn take_return_value(&mut self) -> Option<Value> {
+        self.return_value.take()
+    }
+
+    /// Number of elements `for_each` visits: array length, or field count for structs.
     pub fn array_length_canonical(&self, canonical: &str) -> i64 {
         match self.get_value_canonical(canonical) {
             Some(Value::Array(values)) => values.len() as i64,
+            Some(Value::Struct(map)) => map.len() as i64,
             _ => 0,
         }
     }
 
+    /// Copies the element at `index` into `dest`. Structs yield their field values in
+    /// insertion order so `for_each` can iterate over them like arrays.
     pub fn array_copy_element_canonical(&mut self, canonical: &str, index: i64, dest: &str) {
-        if let Some(value) = self.array_get_value_by_index(canonical, index) {
+        let element = match self.get_value_canonical(canonical) {
+            Some(Value::Struct(map)) => usize::try_from(index)
+                .ok()
+                .and_then(|idx| map.get_index(idx))
+                .map(|(_, value)| value.clone()),
+            _ => self.array_get_value_by_index(canonical, index),
+        };
+        if let Some(value) = element {
             self.set_value_canonical(dest, value);
         } else {
             self.clear_value_canonical(dest);
@@ -354,19 +987,7 @@ impl RuntimeContext {
     fn array_get_value_by_index(&self, canonical: &str, index: i64) -> Option<Value> {
         match self.get_value_canonical(canonical) {
             Some(Value::Array(values)) => {
-                if values.is_empty() {
-                    return None;
-                }
-                let mut idx = index;
-                if idx < 0 {
-                    idx = 0;
-                }
-                let len = values.len() as i64;
-                if len == 0 {
-                    return None;
-                }
-                let wrapped = (idx % len + len) % len;
-                values.get(wrapped as usize).cloned()
+                resolve_index(index, values.len()).and_then(|idx| values.get(idx).cloned())
             }
             _ => None,
         }
@@ -388,11 +1009,8 @@ impl RuntimeContext {
 
     pub fn set_query_value(&mut self, name: impl Into<String>, value: f64) {
         let key = name.into().to_ascii_lowercase();
-        self.values.insert(
-            QualifiedName {
-                namespace: Namespace::Query,
-                key,
-            },
+        self.store_value(
+            QualifiedName::new(Namespace::Query, key),
             Value::number(value),
         );
     }
@@ -404,11 +1022,8 @@ impl RuntimeContext {
 
     pub fn set_query_string(&mut self, name: impl Into<String>, value: impl Into<String>) {
         let key = name.into().to_ascii_lowercase();
-        self.values.insert(
-            QualifiedName {
-                namespace: Namespace::Query,
-                key,
-            },
+        self.store_value(
+            QualifiedName::new(Namespace::Query, key),
             Value::string(value),
         );
     }
@@ -420,55 +1035,61 @@ impl RuntimeContext {
 
     pub fn set_query_generic_value(&mut self, name: impl Into<String>, value: Value) {
         let key = name.into().to_ascii_lowercase();
-        self.values.insert(
-            QualifiedName {
-                namespace: Namespace::Query,
-                key,
-            },
-            value,
-        );
+        self.store_value(QualifiedName::new(Namespace::Query, key), value);
+    }
+
+    /// Replaces `query.<name>` with any value, e.g. to resize a query array between runs.
+    pub fn set_query_value_any(&mut self, name: impl Into<String>, value: Value) {
+        self.set_query_generic_value(name, value);
+    }
+
+    pub fn set_query_array(&mut self, name: impl Into<String>, values: Vec<Value>) {
+        self.set_query_generic_value(name, Value::array(values));
+    }
+
+    pub fn set_query_struct(&mut self, name: impl Into<String>, fields: IndexMap<String, Value>) {
+        self.set_query_generic_value(name, Value::Struct(fields));
+    }
+
+    /// Sets a number at a dotted query path, building the enclosing structs so that
+    /// `set_query_number_nested("entity.health", 20.0)` makes both `query.entity.health`
+    /// and the struct `query.entity` readable. Sibling fields already set are kept.
+    pub fn set_query_number_nested(&mut self, name: impl Into<String>, value: f64) {
+        let segments: Vec<String> = name
+            .into()
+            .split('.')
+            .map(|segment| segment.to_ascii_lowercase())
+            .collect();
+        self.assign_nested(Namespace::Query, &segments, Value::number(value));
     }
 
     fn assign_nested(&mut self, namespace: Namespace, segments: &[String], value: Value) {
         let key = segments.join(".");
         let mut current = value;
-        self.values
-            .insert(QualifiedName::new(namespace.clone(), key), current.clone());
+        self.store_value(QualifiedName::new(namespace.clone(), key), current.clone());
 
         for depth in (1..segments.len()).rev() {
-            let parent_key = segments[..depth].join(".");
+            let parent = QualifiedName::new(namespace.clone(), segments[..depth].join("."));
             let field = segments[depth].clone();
-            let existing = self
-                .values
-                .get(&QualifiedName::new(namespace.clone(), parent_key.clone()))
-                .cloned();
-            let mut map = match existing {
-                Some(Value::Struct(map)) => map,
+            let mut map = match self.stored(&parent) {
+                Some(Value::Struct(map)) => map.clone(),
                 _ => IndexMap::new(),
             };
             map.insert(field, current.clone());
             current = Value::Struct(map.clone());
-            self.values.insert(
-                QualifiedName::new(namespace.clone(), parent_key),
-                Value::Struct(map),
-            );
+            self.store_value(parent, Value::Struct(map));
         }
     }
 
     fn lookup_namespace_path(&self, namespace: Namespace, segments: &[String]) -> Option<Value> {
         let key = segments.join(".");
-        if let Some(value) = self
-            .values
-            .get(&QualifiedName::new(namespace.clone(), key.clone()))
-        {
+        if let Some(value) = self.stored(&QualifiedName::new(namespace.clone(), key.clone())) {
             return Some(value.clone());
         }
 
         for depth in (1..=segments.len()).rev() {
             let prefix = segments[..depth].join(".");
-            if let Some(value) = self
-                .values
-                .get(&QualifiedName::new(namespace.clone(), prefix.clone()))
+            if let Some(value) = self.stored(&QualifiedName::new(namespace.clone(), prefix.clone()))
             {
                 if depth == segments.len() {
                     return Some(value.clone());
@@ -479,18 +1100,60 @@ impl RuntimeContext {
             }
         }
 
+        self.fallback
+            .0
+            .as_ref()
+            .and_then(|resolver| resolver(&namespace, &key))
+    }
+
+    /// Borrowing counterpart of `lookup_namespace_path`; synthesized values such as
+    /// `array.length` are not stored anywhere and therefore never returned.
+    fn lookup_namespace_ref(&self, namespace: Namespace, segments: &[String]) -> Option<&Value> {
+        let key = segments.join(".");
+        if let Some(value) = self.stored(&QualifiedName::new(namespace.clone(), key)) {
+            return Some(value);
+        }
+
+        for depth in (1..segments.len()).rev() {
+            let prefix = segments[..depth].join(".");
+            if let Some(value) = self.stored(&QualifiedName::new(namespace.clone(), prefix)) {
+                if let Some(found) = lookup_nested_ref(value, &segments[depth..]) {
+                    return Some(found);
+                }
+            }
+        }
+
         None
     }
 
-    /// Returns a sorted list of all variables in the context for display purposes.
+    /// Every stored entry, queries included, in no particular order.
+    fn entries(&self) -> impl Iterator<Item = (&QualifiedName, &Value)> {
+        self.values.iter().chain(
+            self.queries
+                .iter()
+                .map(|(name, value)| (name, value.as_ref())),
+        )
+    }
+
+    /// Every stored variable once, by canonical name, sorted by namespace (`temp`,
+    /// `variable`, `context`, `query`) then key. A struct is listed under its own name,
+    /// directly followed by entries for the nested field paths assigned through it.
     pub fn list_variables(&self) -> Vec<(String, &Value)> {
-        let mut result: Vec<(String, &Value)> = self
-            .values
-            .iter()
+        let mut entries: Vec<(&QualifiedName, &Value)> = self.entries().collect();
+        entries.sort_by(|(a, _), (b, _)| (&a.namespace, &a.key).cmp(&(&b.namespace, &b.key)));
+        entries
+            .into_iter()
             .map(|(name, value)| (name.to_string(), value))
-            .collect();
-        result.sort_by(|a, b| a.0.cmp(&b.0));
-        result
+            .collect()
+    }
+}
+
+/// Folds negative zero into positive zero so results never surface as `-0`.
+pub fn normalize_zero(value: f64) -> f64 {
+    if value == 0.0 {
+        0.0
+    } else {
+        value
     }
 }
 
@@ -515,6 +1178,30 @@ fn lookup_nested_value(value: &Value, tail: &[String]) -> Option<Value> {
     }
 }
 
+fn lookup_nested_ref<'a>(value: &'a Value, tail: &[String]) -> Option<&'a Value> {
+    match tail.split_first() {
+        None => Some(value),
+        Some((key, rest)) => match value {
+            Value::Struct(map) => map
+                .get(key)
+                .and_then(|child| lookup_nested_ref(child, rest)),
+            _ => None,
+        },
+    }
+}
+
+fn lookup_nested_mut<'a>(value: &'a mut Value, tail: &[String]) -> Option<&'a mut Value> {
+    match tail.split_first() {
+        None => Some(value),
+        Some((key, rest)) => match value {
+            Value::Struct(map) => map
+                .get_mut(key)
+                .and_then(|child| lookup_nested_mut(child, rest)),
+            _ => None,
+        },
+    }
+}
+
 fn parse_canonical_path(canonical: &str) -> Option<(Namespace, Vec<String>)> {
     let mut iter = canonical.split('.');
     let ns = iter.next()?;
@@ -0,0 +1,297 @@
+//! Pretty-printer turning a parsed [`Program`] back into canonical Molang source.
+//!
+//! Output uses two-space indentation, one statement per line, spaces around binary
+//! operators and only the parentheses precedence requires. Formatting is a pure function of
+//! the syntax tree, so comments are dropped and sugar the parser expands (`+=`) is written
+//! out in its expanded form.
+
+use crate::ast::{BinaryOp, ControlFlowExpr, Expr, Program, Statement, UnaryOp};
+use crate::eval::Value;
+
+const INDENT: &str = "  ";
+
+/// Binding strength of `?:`; `??` binds one level looser.
+const CONDITIONAL: u8 = 1;
+const UNARY: u8 = 11;
+const POSTFIX: u8 = 12;
+
+/// Formats every statement of `program` on its own line, each followed by a newline.
+pub fn format_program(program: &Program) -> String {
+    let mut out = String::new();
+    for statement in &program.statements {
+        write_statement(&mut out, statement, 0);
+        out.push('\n');
+    }
+    out
+}
+
+/// Formats a single expression with the same rules as [`format_program`].
+pub fn format_expr(expr: &Expr) -> String {
+    expr_at(expr, 0)
+}
+
+fn write_statement(out: &mut String, statement: &Statement, depth: usize) {
+    out.push_str(&INDENT.repeat(depth));
+    match statement {
+        Statement::Block(statements) => write_block(out, statements, depth),
+        Statement::Loop { count, index, body } => {
+            out.push_str(&format!("loop({}, ", format_expr(count)));
+            if let Some(index) = index {
+                out.push_str(&format!("{}, ", index.join(".")));
+            }
+            write_body(out, body, depth);
+            out.push_str(");");
+        }
+        Statement::ForEach {
+            variable,
+            collection,
+            body,
+        } => {
+            out.push_str(&format!(
+                "for_each({}, {}, ",
+                variable.join("."),
+                format_expr(collection)
+            ));
+            write_body(out, body, depth);
+            out.push_str(");");
+        }
+        Statement::Return(Some(value)) => out.push_str(&format!("return {};", format_expr(value))),
+        Statement::Return(None) => out.push_str("return;"),
+        other => {
+            write_simple_statement(out, other);
+            out.push(';');
+        }
+    }
+}
+
+/// Writes an expression or assignment statement without its trailing `;`.
+fn write_simple_statement(out: &mut String, statement: &Statement) {
+    match statement {
+        Statement::Assignment { target, value } => {
+            out.push_str(&format!("{} = {}", target.join("."), format_expr(value)));
+        }
+        Statement::IndexAssignment {
+            target,
+            index,
+            op,
+            value,
+        } => {
+            let op = op.map(binary_symbol).unwrap_or("");
+            out.push_str(&format!(
+                "{}[{}] {op}= {}",
+                target.join("."),
+                format_expr(index),
+                format_expr(value)
+            ));
+        }
+        Statement::Expr(expr) => {
+            let text = format_expr(expr);
+            // A leading `{` would be read back as a block
+            if text.starts_with('{') {
+                out.push_str(&format!("({text})"));
+            } else {
+                out.push_str(&text);
+            }
+        }
+        _ => unreachable!("only expression and assignment statements are simple"),
+    }
+}
+
+/// Writes a `loop`/`for_each` body. Only expressions and assignments may appear bare, so
+/// any other statement is wrapped in a block.
+fn write_body(out: &mut String, body: &Statement, depth: usize) {
+    match body {
+        Statement::Block(statements) => write_block(out, statements, depth),
+        Statement::Expr(_) | Statement::Assignment { .. } | Statement::IndexAssignment { .. } => {
+            write_simple_statement(out, body)
+        }
+        other => write_block(out, std::slice::from_ref(other), depth),
+    }
+}
+
+fn write_block(out: &mut String, statements: &[Statement], depth: usize) {
+    if statements.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{\n");
+    for statement in statements {
+        write_statement(out, statement, depth + 1);
+        out.push('\n');
+    }
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+}
+
+/// Formats `expr`, parenthesized when it binds looser than `min`.
+fn expr_at(expr: &Expr, min: u8) -> String {
+    let text = match expr {
+        Expr::Number(number) => Value::number(*number).to_string(),
+        Expr::String(text) => Value::string(text.as_str())
+            .to_molang_literal()
+            .unwrap_or_default(),
+        Expr::Path(segments) => segments.join("."),
+        Expr::Array(items) => format!("[{}]", list(items)),
+        Expr::Struct(fields) if fields.is_empty() => "{}".to_string(),
+        Expr::Struct(fields) => {
+            let entries: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| format!("{}: {}", field_name(key), format_expr(value)))
+                .collect();
+            format!("{{ {} }}", entries.join(", "))
+        }
+        Expr::Unary { op, expr } => {
+            let symbol = match op {
+                UnaryOp::Plus => "+",
+                UnaryOp::Minus => "-",
+                UnaryOp::Not => "!",
+            };
+            format!("{symbol}{}", expr_at(expr, UNARY))
+        }
+        Expr::Binary {
+            op: BinaryOp::NullCoalesce,
+            left,
+            right,
+        } => {
+            // A trailing conditional on the left would swallow the `??` into its branch; one
+            // on the right parses fine bare but reads ambiguously, so it is wrapped too
+            format!("{} ?? {}", closed(left), expr_at(right, CONDITIONAL + 1))
+        }
+        Expr::Binary { op, left, right } => {
+            let precedence = binary_precedence(*op);
+            format!(
+                "{} {} {}",
+                expr_at(left, precedence),
+                binary_symbol(*op),
+                expr_at(right, precedence + 1)
+            )
+        }
+        Expr::Conditional {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            // Else branches chain as `else if`; a conditional then branch is parenthesized
+            // so the `:` cannot attach to the wrong `?`
+            let then_text = closed(then_branch);
+            let condition = expr_at(condition, CONDITIONAL + 1);
+            match else_branch {
+                Some(else_branch) => {
+                    format!("{condition} ? {then_text} : {}", format_expr(else_branch))
+                }
+                None => format!("{condition} ? {then_text}"),
+            }
+        }
+        Expr::Call { target, args, .. } => format!("{}({})", expr_at(target, POSTFIX), list(args)),
+        Expr::Flow(ControlFlowExpr::Break) => "break".to_string(),
+        Expr::Flow(ControlFlowExpr::Continue) => "continue".to_string(),
+        Expr::Index { target, index } => {
+            format!("{}[{}]", expr_at(target, POSTFIX), format_expr(index))
+        }
+        Expr::Member { target, field } => format!("{}.{field}", expr_at(target, POSTFIX)),
+        Expr::Match {
+            subject,
+            cases,
+            default,
+        } => {
+            // Labels are parsed without `?:`/`??`, so looser ones are parenthesized
+            let mut entries: Vec<String> = cases
+                .iter()
+                .map(|(label, result)| {
+                    let label = expr_at(label, binary_precedence(BinaryOp::Or));
+                    format!("{label}: {}", format_expr(result))
+                })
+                .collect();
+            if let Some(default) = default {
+                entries.push(format!("default: {}", format_expr(default)));
+            }
+            if entries.is_empty() {
+                format!("match({}) {{}}", format_expr(subject))
+            } else {
+                format!(
+                    "match({}) {{ {} }}",
+                    format_expr(subject),
+                    entries.join("; ")
+                )
+            }
+        }
+    };
+    if precedence(expr) < min {
+        format!("({text})")
+    } else {
+        text
+    }
+}
+
+/// Formats `expr` for a position followed by more of the enclosing expression, wrapping a
+/// conditional that would otherwise extend over what follows.
+fn closed(expr: &Expr) -> String {
+    match expr {
+        Expr::Conditional { .. } => format!("({})", format_expr(expr)),
+        _ => format_expr(expr),
+    }
+}
+
+fn list(items: &[Expr]) -> String {
+    items.iter().map(format_expr).collect::<Vec<_>>().join(", ")
+}
+
+/// Struct keys that are not plain identifiers are written as strings.
+fn field_name(key: &str) -> String {
+    let mut chars = key.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    if is_identifier {
+        key.to_string()
+    } else {
+        Value::string(key).to_molang_literal().unwrap_or_default()
+    }
+}
+
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, .. } => binary_precedence(*op),
+        Expr::Conditional { .. } => CONDITIONAL,
+        Expr::Unary { .. } => UNARY,
+        _ => POSTFIX,
+    }
+}
+
+/// Mirrors the parser's descent order, loosest first.
+fn binary_precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::NullCoalesce => 0,
+        BinaryOp::Or => 2,
+        BinaryOp::And => 3,
+        BinaryOp::BitOr => 4,
+        BinaryOp::BitXor => 5,
+        BinaryOp::BitAnd => 6,
+        BinaryOp::Equal | BinaryOp::NotEqual => 7,
+        BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 8,
+        BinaryOp::Add | BinaryOp::Sub => 9,
+        BinaryOp::Mul | BinaryOp::Div => 10,
+    }
+}
+
+fn binary_symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::Equal => "==",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+        BinaryOp::BitAnd => "&",
+        BinaryOp::BitOr => "|",
+        BinaryOp::BitXor => "^",
+        BinaryOp::NullCoalesce => "??",
+    }
+}
@@ -1,5 +1,8 @@
 use crate::ast::{BinaryOp, ControlFlowExpr, Expr, Program, Statement, UnaryOp};
+use crate::eval::{resolve_index, QualifiedName, Value};
+use crate::lexer::Span;
 use indexmap::IndexMap;
+use std::collections::HashSet;
 use thiserror::Error;
 
 /// Expression IR that can be fed directly to the Cranelift JIT.
@@ -32,6 +35,11 @@ pub enum IrExpr {
         target: Box<IrExpr>,
         index: Box<IrExpr>,
     },
+    Match {
+        subject: Box<IrExpr>,
+        cases: Vec<(IrExpr, IrExpr)>,
+        default: Option<Box<IrExpr>>,
+    },
     Flow(ControlFlowExpr),
 }
 
@@ -42,9 +50,16 @@ pub enum IrStatement {
         target: Vec<String>,
         value: IrExpr,
     },
+    IndexAssign {
+        target: Vec<String>,
+        index: IrExpr,
+        op: Option<BinaryOp>,
+        value: IrExpr,
+    },
     Block(Vec<IrStatement>),
     Loop {
         count: IrExpr,
+        index: Option<Vec<String>>,
         body: Box<IrStatement>,
     },
     ForEach {
@@ -61,9 +76,416 @@ pub struct IrProgram {
     pub statements: Vec<IrStatement>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum FunctionRef {
     Builtin(BuiltinFunction),
+    Value(ValueFunction),
+    /// Function registered by the host on `RuntimeContext`, by canonical name.
+    Host(QualifiedName),
+}
+
+/// Most numeric arguments a host function can receive from compiled code.
+pub const HOST_FUNCTION_MAX_ARGS: usize = 4;
+
+/// Functions over whole runtime values (strings, arrays, structs) rather than plain numbers.
+/// The JIT hands their arguments over as slots to `molang_rt_call_value_function`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueFunction {
+    Equals,
+    ArrayCount,
+    MathGradient,
+    StructMax,
+    StructMin,
+    /// `+` with a string operand; not callable by name.
+    Concat,
+    /// `math.lerp` assigned with two array operands, interpolating element-wise.
+    ArrayLerp,
+    /// `math.weighted_index(weights)`: random index drawn in proportion to each weight.
+    MathWeightedIndex,
+    ArrayPush,
+    ArrayPop,
+    ArraySet,
+    MathDistance,
+    MathDistanceSquared,
+    StructRemove,
+    ArrayContains,
+    ArrayIndexOf,
+    ArraySum,
+    /// `.count()` with no arguments: element count (fields for structs); not callable by
+    /// name.
+    ArrayLength,
+    /// `typeof(v)`: numeric tag of the value's type, see [`Value::type_tag`].
+    TypeOf,
+    MathIsNumber,
+    MathIsString,
+    MathIsArray,
+    MathIsStruct,
+}
+
+impl ValueFunction {
+    /// Every value function; the index is the code passed across the runtime helper ABI.
+    pub const ALL: &'static [ValueFunction] = &[
+        ValueFunction::Equals,
+        ValueFunction::ArrayCount,
+        ValueFunction::MathGradient,
+        ValueFunction::StructMax,
+        ValueFunction::StructMin,
+        ValueFunction::Concat,
+        ValueFunction::ArrayLerp,
+        ValueFunction::MathWeightedIndex,
+        ValueFunction::ArrayPush,
+        ValueFunction::ArrayPop,
+        ValueFunction::ArraySet,
+        ValueFunction::MathDistance,
+        ValueFunction::MathDistanceSquared,
+        ValueFunction::StructRemove,
+        ValueFunction::ArrayContains,
+        ValueFunction::ArrayIndexOf,
+        ValueFunction::ArraySum,
+        ValueFunction::ArrayLength,
+        ValueFunction::TypeOf,
+        ValueFunction::MathIsNumber,
+        ValueFunction::MathIsString,
+        ValueFunction::MathIsArray,
+        ValueFunction::MathIsStruct,
+    ];
+
+    /// Most arguments any value function accepts (fixed by the runtime helper signature).
+    pub const MAX_ARITY: usize = 3;
+
+    /// Resolves a call path through [`path`](Self::path).
+    pub fn from_path(path: &[String]) -> Option<Self> {
+        Self::ALL.iter().copied().find(|function| {
+            function
+                .path()
+                .is_some_and(|name| name.split('.').eq(path.iter().map(String::as_str)))
+        })
+    }
+
+    /// The script-facing path (`array.push`), or `None` for functions only reached through
+    /// syntax or another builtin: `+` concatenation, `.count()` and `math.lerp` on arrays.
+    pub fn path(self) -> Option<&'static str> {
+        match self {
+            ValueFunction::Concat | ValueFunction::ArrayLength | ValueFunction::ArrayLerp => None,
+            other => Some(other.name()),
+        }
+    }
+
+    /// Method-style call such as `temp.arr.contains(5)`: the function named by the last
+    /// segment, and the receiver path before it, which becomes the first argument.
+    /// `.count()` counts elements, while `.count(value)` counts matches like `array.count`.
+    pub fn from_method(path: &[String], arg_count: usize) -> Option<(Self, &[String])> {
+        let (name, receiver) = path.split_last()?;
+        let function = match (name.as_str(), arg_count) {
+            ("contains", _) => ValueFunction::ArrayContains,
+            ("index_of", _) => ValueFunction::ArrayIndexOf,
+            ("sum", _) => ValueFunction::ArraySum,
+            ("count", 0) => ValueFunction::ArrayLength,
+            ("count", _) => ValueFunction::ArrayCount,
+            _ => return None,
+        };
+        (!receiver.is_empty()).then_some((function, receiver))
+    }
+
+    pub fn from_code(code: i64) -> Option<Self> {
+        usize::try_from(code)
+            .ok()
+            .and_then(|index| Self::ALL.get(index).copied())
+    }
+
+    pub fn code(self) -> i64 {
+        Self::ALL
+            .iter()
+            .position(|function| *function == self)
+            .unwrap_or_default() as i64
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ValueFunction::Equals => "equals",
+            ValueFunction::ArrayCount => "array.count",
+            ValueFunction::MathGradient => "math.gradient",
+            ValueFunction::StructMax => "struct.max",
+            ValueFunction::StructMin => "struct.min",
+            ValueFunction::Concat => "+",
+            ValueFunction::ArrayLerp => "math.lerp",
+            ValueFunction::MathWeightedIndex => "math.weighted_index",
+            ValueFunction::ArrayPush => "array.push",
+            ValueFunction::ArrayPop => "array.pop",
+            ValueFunction::ArraySet => "array.set",
+            ValueFunction::MathDistance => "math.distance",
+            ValueFunction::MathDistanceSquared => "math.distance_squared",
+            ValueFunction::StructRemove => "struct.remove",
+            ValueFunction::ArrayContains => "array.contains",
+            ValueFunction::ArrayIndexOf => "array.index_of",
+            ValueFunction::ArraySum => "array.sum",
+            ValueFunction::ArrayLength => "count",
+            ValueFunction::TypeOf => "typeof",
+            ValueFunction::MathIsNumber => "math.is_number",
+            ValueFunction::MathIsString => "math.is_string",
+            ValueFunction::MathIsArray => "math.is_array",
+            ValueFunction::MathIsStruct => "math.is_struct",
+        }
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            ValueFunction::Equals
+            | ValueFunction::ArrayCount
+            | ValueFunction::MathGradient
+            | ValueFunction::Concat
+            | ValueFunction::ArrayPush
+            | ValueFunction::MathDistance
+            | ValueFunction::MathDistanceSquared
+            | ValueFunction::StructRemove
+            | ValueFunction::ArrayContains
+            | ValueFunction::ArrayIndexOf => 2,
+            ValueFunction::StructMax
+            | ValueFunction::StructMin
+            | ValueFunction::MathWeightedIndex
+            | ValueFunction::ArrayPop
+            | ValueFunction::ArraySum
+            | ValueFunction::ArrayLength
+            | ValueFunction::TypeOf
+            | ValueFunction::MathIsNumber
+            | ValueFunction::MathIsString
+            | ValueFunction::MathIsArray
+            | ValueFunction::MathIsStruct => 1,
+            ValueFunction::ArrayLerp | ValueFunction::ArraySet => 3,
+        }
+    }
+
+    pub fn evaluate(self, args: &[Value]) -> Value {
+        let arg = |index: usize| args.get(index).unwrap_or(&Value::Null);
+        match self {
+            ValueFunction::Equals => Value::number(bool_number(arg(0).deep_equals(arg(1)))),
+            ValueFunction::ArrayCount => {
+                let count = arg(0).as_array().map_or(0, |items| {
+                    items.iter().filter(|item| item.deep_equals(arg(1))).count()
+                });
+                Value::number(count as f64)
+            }
+            ValueFunction::ArrayContains => Value::number(bool_number(
+                arg(0)
+                    .as_array()
+                    .is_some_and(|items| items.contains(arg(1))),
+            )),
+            ValueFunction::ArrayIndexOf => {
+                let index = arg(0)
+                    .as_array()
+                    .and_then(|items| items.iter().position(|item| item == arg(1)));
+                Value::number(index.map_or(-1.0, |index| index as f64))
+            }
+            ValueFunction::TypeOf => Value::number(arg(0).type_tag()),
+            ValueFunction::MathIsNumber => {
+                Value::number(bool_number(matches!(arg(0), Value::Number(_))))
+            }
+            ValueFunction::MathIsString => {
+                Value::number(bool_number(matches!(arg(0), Value::String(_))))
+            }
+            ValueFunction::MathIsArray => {
+                Value::number(bool_number(matches!(arg(0), Value::Array(_))))
+            }
+            ValueFunction::MathIsStruct => {
+                Value::number(bool_number(matches!(arg(0), Value::Struct(_))))
+            }
+            ValueFunction::ArraySum => Value::number(
+                arg(0)
+                    .as_array()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(Value::as_number)
+                    .sum(),
+            ),
+            ValueFunction::ArrayLength => Value::number(match arg(0) {
+                Value::Array(items) => items.len() as f64,
+                Value::Struct(fields) => fields.len() as f64,
+                _ => 0.0,
+            }),
+            ValueFunction::MathGradient => {
+                let stops = arg(0).as_array().unwrap_or_default();
+                Value::number(gradient(stops, arg(1).as_number()))
+            }
+            ValueFunction::StructMax => Value::number(struct_number_fold(arg(0), f64::max)),
+            ValueFunction::StructMin => Value::number(struct_number_fold(arg(0), f64::min)),
+            ValueFunction::Concat => match (arg(0), arg(1)) {
+                (Value::String(left), Value::String(right)) => {
+                    Value::string(format!("{left}{right}"))
+                }
+                (left, right) => Value::number(left.as_number() + right.as_number()),
+            },
+            ValueFunction::ArrayPush => {
+                let mut items = arg(0).as_array().unwrap_or_default().to_vec();
+                items.push(arg(1).clone());
+                Value::array(items)
+            }
+            ValueFunction::ArrayPop => {
+                let items = arg(0).as_array().unwrap_or_default();
+                Value::array(items[..items.len().saturating_sub(1)].to_vec())
+            }
+            ValueFunction::ArraySet => {
+                let mut items = arg(0).as_array().unwrap_or_default().to_vec();
+                if let Some(index) = resolve_index(arg(1).as_number() as i64, items.len()) {
+                    items[index] = arg(2).clone();
+                }
+                Value::array(items)
+            }
+            ValueFunction::MathWeightedIndex => {
+                let weights: Vec<f64> = arg(0)
+                    .as_array()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(Value::as_number)
+                    .collect();
+                Value::number(crate::builtins::math_weighted_index(&weights))
+            }
+            ValueFunction::StructRemove => match arg(0) {
+                Value::Struct(fields) => {
+                    let mut fields = fields.clone();
+                    if let Value::String(key) = arg(1) {
+                        fields.shift_remove(&key.to_ascii_lowercase());
+                    }
+                    Value::Struct(fields)
+                }
+                other => other.clone(),
+            },
+            ValueFunction::MathDistance => Value::number(squared_distance(arg(0), arg(1)).sqrt()),
+            ValueFunction::MathDistanceSquared => Value::number(squared_distance(arg(0), arg(1))),
+            ValueFunction::ArrayLerp => {
+                let t = arg(2).as_number();
+                let lerp = |start: &Value, end: &Value| {
+                    crate::builtins::builtin_math_lerp(start.as_number(), end.as_number(), t)
+                };
+                match (arg(0), arg(1)) {
+                    (Value::Array(start), Value::Array(end)) if start.len() == end.len() => {
+                        Value::array(
+                            start
+                                .iter()
+                                .zip(end)
+                                .map(|(start, end)| Value::number(lerp(start, end)))
+                                .collect(),
+                        )
+                    }
+                    (start, end) => Value::number(lerp(start, end)),
+                }
+            }
+        }
+    }
+}
+
+/// True for a `+` that may join strings: a chain with a string literal operand, or two
+/// operands that may hold strings at runtime (`temp.a + temp.b`). It compiles to a call
+/// that concatenates when both values are strings and adds them otherwise.
+pub fn is_string_concat(expr: &IrExpr) -> bool {
+    match expr {
+        IrExpr::Binary {
+            op: BinaryOp::Add,
+            left,
+            right,
+        } => {
+            has_string_operand(left)
+                || has_string_operand(right)
+                || (may_hold_string(left) && may_hold_string(right))
+        }
+        _ => false,
+    }
+}
+
+fn has_string_operand(expr: &IrExpr) -> bool {
+    matches!(expr, IrExpr::String(_)) || is_string_concat(expr)
+}
+
+/// Variables and value/host call results are only known to be strings at runtime.
+fn may_hold_string(expr: &IrExpr) -> bool {
+    matches!(
+        expr,
+        IrExpr::Path(_)
+            | IrExpr::Call {
+                function: FunctionRef::Value(_) | FunctionRef::Host(_),
+                ..
+            }
+    ) || has_string_operand(expr)
+}
+
+/// Squared Euclidean distance between two points given as arrays of coordinates. A
+/// number counts as a 1D point; components missing from the shorter point count as `0`.
+fn squared_distance(left: &Value, right: &Value) -> f64 {
+    let components = |value: &Value| match value {
+        Value::Array(items) => items.iter().map(Value::as_number).collect(),
+        other => vec![other.as_number()],
+    };
+    let (left, right): (Vec<f64>, Vec<f64>) = (components(left), components(right));
+    (0..left.len().max(right.len()))
+        .map(|index| {
+            let delta = left.get(index).unwrap_or(&0.0) - right.get(index).unwrap_or(&0.0);
+            delta * delta
+        })
+        .sum()
+}
+
+/// Result of a deterministic builtin call whose arguments are all constants, so the JIT
+/// emits the value instead of a call.
+fn fold_builtin_call(function: &FunctionRef, args: &[IrExpr]) -> Option<f64> {
+    let FunctionRef::Builtin(builtin) = function else {
+        return None;
+    };
+    if !builtin.is_deterministic() {
+        return None;
+    }
+    let values = args
+        .iter()
+        .map(|arg| match arg {
+            IrExpr::Constant(value) => Some(*value),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(builtin.evaluate(&values))
+}
+
+/// Call expression for `function`, folded to a constant when possible.
+fn call_or_fold(function: FunctionRef, args: Vec<IrExpr>) -> IrExpr {
+    match fold_builtin_call(&function, &args) {
+        Some(value) => IrExpr::Constant(value),
+        None => IrExpr::Call { function, args },
+    }
+}
+
+/// Folds the numeric field values of a struct, skipping other fields; `0` when none.
+fn struct_number_fold(value: &Value, fold: fn(f64, f64) -> f64) -> f64 {
+    value
+        .as_struct()
+        .into_iter()
+        .flat_map(|fields| fields.values())
+        .filter_map(|field| match field {
+            Value::Number(number) => Some(*number),
+            _ => None,
+        })
+        .reduce(fold)
+        .unwrap_or(0.0)
+}
+
+/// Samples evenly spaced numeric `stops` at `t` (clamped to `[0, 1]`), interpolating
+/// linearly between the two nearest stops.
+fn gradient(stops: &[Value], t: f64) -> f64 {
+    match stops {
+        [] => 0.0,
+        [only] => only.as_number(),
+        _ => {
+            let position = crate::builtins::math_clamp(t, 0.0, 1.0) * (stops.len() - 1) as f64;
+            let index = (position.floor() as usize).min(stops.len() - 2);
+            let start = stops[index].as_number();
+            let end = stops[index + 1].as_number();
+            crate::builtins::builtin_math_lerp(start, end, position - index as f64)
+        }
+    }
+}
+
+fn bool_number(value: bool) -> f64 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -74,31 +496,53 @@ pub enum BuiltinFunction {
     MathRandom,
     MathRandomInteger,
     MathClamp,
+    MathWrap,
     MathSqrt,
+    MathCbrt,
     MathFloor,
     MathCeil,
     MathRound,
     MathTrunc,
+    MathFrac,
+    MathClamp01,
     MathAcos,
     MathAsin,
     MathAtan,
     MathAtan2,
+    MathSinh,
+    MathCosh,
+    MathTanh,
     MathExp,
     MathLn,
     MathPow,
+    MathSquare,
     MathMax,
     MathMin,
     MathMod,
+    MathModEuclid,
+    MathSnap,
+    MathFloorTo,
+    MathCeilTo,
+    MathRoundTo,
+    MathRatio,
     MathSign,
     MathCopySign,
     MathPi,
     MathMinAngle,
     MathLerp,
+    MathLerpClamped,
     MathInverseLerp,
+    MathMapRange,
+    MathApproxEqual,
     MathLerpRotate,
     MathHermiteBlend,
+    MathSmoothstep,
+    MathSmootherstep,
+    MathRgb,
+    MathRgbChannel,
     MathDieRoll,
     MathDieRollInteger,
+    MathNoise,
     MathEaseInQuad,
     MathEaseOutQuad,
     MathEaseInOutQuad,
@@ -131,92 +575,244 @@ pub enum BuiltinFunction {
     MathEaseInOutBounce,
 }
 
+/// Builtins that map element-wise over an array first argument when assigned. The index
+/// doubles as the code passed to `molang_rt_broadcast_builtin`.
+pub const BROADCAST_BUILTINS: [BuiltinFunction; 5] = [
+    BuiltinFunction::MathAbs,
+    BuiltinFunction::MathClamp,
+    BuiltinFunction::MathFloor,
+    BuiltinFunction::MathCeil,
+    BuiltinFunction::MathRound,
+];
+
 impl BuiltinFunction {
+    /// Every builtin, in declaration order.
+    pub const ALL: &'static [BuiltinFunction] = &[
+        BuiltinFunction::MathCos,
+        BuiltinFunction::MathSin,
+        BuiltinFunction::MathAbs,
+        BuiltinFunction::MathRandom,
+        BuiltinFunction::MathRandomInteger,
+        BuiltinFunction::MathClamp,
+        BuiltinFunction::MathWrap,
+        BuiltinFunction::MathSqrt,
+        BuiltinFunction::MathCbrt,
+        BuiltinFunction::MathFloor,
+        BuiltinFunction::MathCeil,
+        BuiltinFunction::MathRound,
+        BuiltinFunction::MathTrunc,
+        BuiltinFunction::MathFrac,
+        BuiltinFunction::MathClamp01,
+        BuiltinFunction::MathAcos,
+        BuiltinFunction::MathAsin,
+        BuiltinFunction::MathAtan,
+        BuiltinFunction::MathAtan2,
+        BuiltinFunction::MathSinh,
+        BuiltinFunction::MathCosh,
+        BuiltinFunction::MathTanh,
+        BuiltinFunction::MathExp,
+        BuiltinFunction::MathLn,
+        BuiltinFunction::MathPow,
+        BuiltinFunction::MathSquare,
+        BuiltinFunction::MathMax,
+        BuiltinFunction::MathMin,
+        BuiltinFunction::MathMod,
+        BuiltinFunction::MathModEuclid,
+        BuiltinFunction::MathSnap,
+        BuiltinFunction::MathFloorTo,
+        BuiltinFunction::MathCeilTo,
+        BuiltinFunction::MathRoundTo,
+        BuiltinFunction::MathRatio,
+        BuiltinFunction::MathSign,
+        BuiltinFunction::MathCopySign,
+        BuiltinFunction::MathPi,
+        BuiltinFunction::MathMinAngle,
+        BuiltinFunction::MathLerp,
+        BuiltinFunction::MathLerpClamped,
+        BuiltinFunction::MathInverseLerp,
+        BuiltinFunction::MathMapRange,
+        BuiltinFunction::MathApproxEqual,
+        BuiltinFunction::MathLerpRotate,
+        BuiltinFunction::MathHermiteBlend,
+        BuiltinFunction::MathSmoothstep,
+        BuiltinFunction::MathSmootherstep,
+        BuiltinFunction::MathRgb,
+        BuiltinFunction::MathRgbChannel,
+        BuiltinFunction::MathDieRoll,
+        BuiltinFunction::MathDieRollInteger,
+        BuiltinFunction::MathNoise,
+        BuiltinFunction::MathEaseInQuad,
+        BuiltinFunction::MathEaseOutQuad,
+        BuiltinFunction::MathEaseInOutQuad,
+        BuiltinFunction::MathEaseInCubic,
+        BuiltinFunction::MathEaseOutCubic,
+        BuiltinFunction::MathEaseInOutCubic,
+        BuiltinFunction::MathEaseInQuart,
+        BuiltinFunction::MathEaseOutQuart,
+        BuiltinFunction::MathEaseInOutQuart,
+        BuiltinFunction::MathEaseInQuint,
+        BuiltinFunction::MathEaseOutQuint,
+        BuiltinFunction::MathEaseInOutQuint,
+        BuiltinFunction::MathEaseInSine,
+        BuiltinFunction::MathEaseOutSine,
+        BuiltinFunction::MathEaseInOutSine,
+        BuiltinFunction::MathEaseInExpo,
+        BuiltinFunction::MathEaseOutExpo,
+        BuiltinFunction::MathEaseInOutExpo,
+        BuiltinFunction::MathEaseInCirc,
+        BuiltinFunction::MathEaseOutCirc,
+        BuiltinFunction::MathEaseInOutCirc,
+        BuiltinFunction::MathEaseInBack,
+        BuiltinFunction::MathEaseOutBack,
+        BuiltinFunction::MathEaseInOutBack,
+        BuiltinFunction::MathEaseInElastic,
+        BuiltinFunction::MathEaseOutElastic,
+        BuiltinFunction::MathEaseInOutElastic,
+        BuiltinFunction::MathEaseInBounce,
+        BuiltinFunction::MathEaseOutBounce,
+        BuiltinFunction::MathEaseInOutBounce,
+    ];
+
+    /// Every builtin, for tooling that enumerates them with [`name`](Self::name) and
+    /// [`arity`](Self::arity).
+    pub fn all() -> &'static [BuiltinFunction] {
+        Self::ALL
+    }
+
+    /// Returns the broadcast code when this builtin maps element-wise over arrays.
+    pub fn broadcast_code(self) -> Option<usize> {
+        BROADCAST_BUILTINS
+            .iter()
+            .position(|builtin| *builtin == self)
+    }
+
+    /// Resolves a call path through [`name`](Self::name), plus the `math.lerp_rotate` alias.
     pub fn from_path(path: &[String]) -> Option<Self> {
         match path {
-            [ns, func] if ns == "math" => match func.as_str() {
-                "cos" => Some(BuiltinFunction::MathCos),
-                "sin" => Some(BuiltinFunction::MathSin),
-                "abs" => Some(BuiltinFunction::MathAbs),
-                "random" => Some(BuiltinFunction::MathRandom),
-                "random_integer" => Some(BuiltinFunction::MathRandomInteger),
-                "clamp" => Some(BuiltinFunction::MathClamp),
-                "sqrt" => Some(BuiltinFunction::MathSqrt),
-                "floor" => Some(BuiltinFunction::MathFloor),
-                "ceil" => Some(BuiltinFunction::MathCeil),
-                "round" => Some(BuiltinFunction::MathRound),
-                "trunc" => Some(BuiltinFunction::MathTrunc),
-                "acos" => Some(BuiltinFunction::MathAcos),
-                "asin" => Some(BuiltinFunction::MathAsin),
-                "atan" => Some(BuiltinFunction::MathAtan),
-                "atan2" => Some(BuiltinFunction::MathAtan2),
-                "exp" => Some(BuiltinFunction::MathExp),
-                "ln" => Some(BuiltinFunction::MathLn),
-                "pow" => Some(BuiltinFunction::MathPow),
-                "max" => Some(BuiltinFunction::MathMax),
-                "min" => Some(BuiltinFunction::MathMin),
-                "mod" => Some(BuiltinFunction::MathMod),
-                "sign" => Some(BuiltinFunction::MathSign),
-                "copy_sign" => Some(BuiltinFunction::MathCopySign),
-                "pi" => Some(BuiltinFunction::MathPi),
-                "min_angle" => Some(BuiltinFunction::MathMinAngle),
-                "lerp" => Some(BuiltinFunction::MathLerp),
-                "inverse_lerp" => Some(BuiltinFunction::MathInverseLerp),
-                "lerprotate" => Some(BuiltinFunction::MathLerpRotate),
-                "hermite_blend" => Some(BuiltinFunction::MathHermiteBlend),
-                "die_roll" => Some(BuiltinFunction::MathDieRoll),
-                "die_roll_integer" => Some(BuiltinFunction::MathDieRollInteger),
-                "ease_in_quad" => Some(BuiltinFunction::MathEaseInQuad),
-                "ease_out_quad" => Some(BuiltinFunction::MathEaseOutQuad),
-                "ease_in_out_quad" => Some(BuiltinFunction::MathEaseInOutQuad),
-                "ease_in_cubic" => Some(BuiltinFunction::MathEaseInCubic),
-                "ease_out_cubic" => Some(BuiltinFunction::MathEaseOutCubic),
-                "ease_in_out_cubic" => Some(BuiltinFunction::MathEaseInOutCubic),
-                "ease_in_quart" => Some(BuiltinFunction::MathEaseInQuart),
-                "ease_out_quart" => Some(BuiltinFunction::MathEaseOutQuart),
-                "ease_in_out_quart" => Some(BuiltinFunction::MathEaseInOutQuart),
-                "ease_in_quint" => Some(BuiltinFunction::MathEaseInQuint),
-                "ease_out_quint" => Some(BuiltinFunction::MathEaseOutQuint),
-                "ease_in_out_quint" => Some(BuiltinFunction::MathEaseInOutQuint),
-                "ease_in_sine" => Some(BuiltinFunction::MathEaseInSine),
-                "ease_out_sine" => Some(BuiltinFunction::MathEaseOutSine),
-                "ease_in_out_sine" => Some(BuiltinFunction::MathEaseInOutSine),
-                "ease_in_expo" => Some(BuiltinFunction::MathEaseInExpo),
-                "ease_out_expo" => Some(BuiltinFunction::MathEaseOutExpo),
-                "ease_in_out_expo" => Some(BuiltinFunction::MathEaseInOutExpo),
-                "ease_in_circ" => Some(BuiltinFunction::MathEaseInCirc),
-                "ease_out_circ" => Some(BuiltinFunction::MathEaseOutCirc),
-                "ease_in_out_circ" => Some(BuiltinFunction::MathEaseInOutCirc),
-                "ease_in_back" => Some(BuiltinFunction::MathEaseInBack),
-                "ease_out_back" => Some(BuiltinFunction::MathEaseOutBack),
-                "ease_in_out_back" => Some(BuiltinFunction::MathEaseInOutBack),
-                "ease_in_elastic" => Some(BuiltinFunction::MathEaseInElastic),
-                "ease_out_elastic" => Some(BuiltinFunction::MathEaseOutElastic),
-                "ease_in_out_elastic" => Some(BuiltinFunction::MathEaseInOutElastic),
-                "ease_in_bounce" => Some(BuiltinFunction::MathEaseInBounce),
-                "ease_out_bounce" => Some(BuiltinFunction::MathEaseOutBounce),
-                "ease_in_out_bounce" => Some(BuiltinFunction::MathEaseInOutBounce),
-                _ => None,
-            },
+            [ns, func] if ns == "math" && func == "lerp_rotate" => {
+                Some(BuiltinFunction::MathLerpRotate)
+            }
+            [ns, func] if ns == "math" => Self::ALL
+                .iter()
+                .copied()
+                .find(|builtin| builtin.name().strip_prefix("math.") == Some(func.as_str())),
             _ => None,
         }
     }
 
+    /// The script-facing path, e.g. `math.sin`; `math.lerp_rotate` is an alias of
+    /// `math.lerprotate`.
+    pub fn name(self) -> &'static str {
+        match self {
+            BuiltinFunction::MathCos => "math.cos",
+            BuiltinFunction::MathSin => "math.sin",
+            BuiltinFunction::MathAbs => "math.abs",
+            BuiltinFunction::MathRandom => "math.random",
+            BuiltinFunction::MathRandomInteger => "math.random_integer",
+            BuiltinFunction::MathClamp => "math.clamp",
+            BuiltinFunction::MathWrap => "math.wrap",
+            BuiltinFunction::MathSqrt => "math.sqrt",
+            BuiltinFunction::MathCbrt => "math.cbrt",
+            BuiltinFunction::MathFloor => "math.floor",
+            BuiltinFunction::MathCeil => "math.ceil",
+            BuiltinFunction::MathRound => "math.round",
+            BuiltinFunction::MathTrunc => "math.trunc",
+            BuiltinFunction::MathFrac => "math.frac",
+            BuiltinFunction::MathClamp01 => "math.clamp01",
+            BuiltinFunction::MathAcos => "math.acos",
+            BuiltinFunction::MathAsin => "math.asin",
+            BuiltinFunction::MathAtan => "math.atan",
+            BuiltinFunction::MathAtan2 => "math.atan2",
+            BuiltinFunction::MathSinh => "math.sinh",
+            BuiltinFunction::MathCosh => "math.cosh",
+            BuiltinFunction::MathTanh => "math.tanh",
+            BuiltinFunction::MathExp => "math.exp",
+            BuiltinFunction::MathLn => "math.ln",
+            BuiltinFunction::MathPow => "math.pow",
+            BuiltinFunction::MathSquare => "math.square",
+            BuiltinFunction::MathMax => "math.max",
+            BuiltinFunction::MathMin => "math.min",
+            BuiltinFunction::MathMod => "math.mod",
+            BuiltinFunction::MathModEuclid => "math.mod_euclid",
+            BuiltinFunction::MathSnap => "math.snap",
+            BuiltinFunction::MathFloorTo => "math.floor_to",
+            BuiltinFunction::MathCeilTo => "math.ceil_to",
+            BuiltinFunction::MathRoundTo => "math.round_to",
+            BuiltinFunction::MathRatio => "math.ratio",
+            BuiltinFunction::MathSign => "math.sign",
+            BuiltinFunction::MathCopySign => "math.copy_sign",
+            BuiltinFunction::MathPi => "math.pi",
+            BuiltinFunction::MathMinAngle => "math.min_angle",
+            BuiltinFunction::MathLerp => "math.lerp",
+            BuiltinFunction::MathLerpClamped => "math.lerp_clamped",
+            BuiltinFunction::MathInverseLerp => "math.inverse_lerp",
+            BuiltinFunction::MathMapRange => "math.map_range",
+            BuiltinFunction::MathApproxEqual => "math.approx_equal",
+            BuiltinFunction::MathLerpRotate => "math.lerprotate",
+            BuiltinFunction::MathHermiteBlend => "math.hermite_blend",
+            BuiltinFunction::MathSmoothstep => "math.smoothstep",
+            BuiltinFunction::MathSmootherstep => "math.smootherstep",
+            BuiltinFunction::MathRgb => "math.rgb",
+            BuiltinFunction::MathRgbChannel => "math.rgb_channel",
+            BuiltinFunction::MathDieRoll => "math.die_roll",
+            BuiltinFunction::MathDieRollInteger => "math.die_roll_integer",
+            BuiltinFunction::MathNoise => "math.noise",
+            BuiltinFunction::MathEaseInQuad => "math.ease_in_quad",
+            BuiltinFunction::MathEaseOutQuad => "math.ease_out_quad",
+            BuiltinFunction::MathEaseInOutQuad => "math.ease_in_out_quad",
+            BuiltinFunction::MathEaseInCubic => "math.ease_in_cubic",
+            BuiltinFunction::MathEaseOutCubic => "math.ease_out_cubic",
+            BuiltinFunction::MathEaseInOutCubic => "math.ease_in_out_cubic",
+            BuiltinFunction::MathEaseInQuart => "math.ease_in_quart",
+            BuiltinFunction::MathEaseOutQuart => "math.ease_out_quart",
+            BuiltinFunction::MathEaseInOutQuart => "math.ease_in_out_quart",
+            BuiltinFunction::MathEaseInQuint => "math.ease_in_quint",
+            BuiltinFunction::MathEaseOutQuint => "math.ease_out_quint",
+            BuiltinFunction::MathEaseInOutQuint => "math.ease_in_out_quint",
+            BuiltinFunction::MathEaseInSine => "math.ease_in_sine",
+            BuiltinFunction::MathEaseOutSine => "math.ease_out_sine",
+            BuiltinFunction::MathEaseInOutSine => "math.ease_in_out_sine",
+            BuiltinFunction::MathEaseInExpo => "math.ease_in_expo",
+            BuiltinFunction::MathEaseOutExpo => "math.ease_out_expo",
+            BuiltinFunction::MathEaseInOutExpo => "math.ease_in_out_expo",
+            BuiltinFunction::MathEaseInCirc => "math.ease_in_circ",
+            BuiltinFunction::MathEaseOutCirc => "math.ease_out_circ",
+            BuiltinFunction::MathEaseInOutCirc => "math.ease_in_out_circ",
+            BuiltinFunction::MathEaseInBack => "math.ease_in_back",
+            BuiltinFunction::MathEaseOutBack => "math.ease_out_back",
+            BuiltinFunction::MathEaseInOutBack => "math.ease_in_out_back",
+            BuiltinFunction::MathEaseInElastic => "math.ease_in_elastic",
+            BuiltinFunction::MathEaseOutElastic => "math.ease_out_elastic",
+            BuiltinFunction::MathEaseInOutElastic => "math.ease_in_out_elastic",
+            BuiltinFunction::MathEaseInBounce => "math.ease_in_bounce",
+            BuiltinFunction::MathEaseOutBounce => "math.ease_out_bounce",
+            BuiltinFunction::MathEaseInOutBounce => "math.ease_in_out_bounce",
+        }
+    }
+
     pub fn arity(self) -> usize {
         match self {
             BuiltinFunction::MathPi => 0,
             BuiltinFunction::MathCos
+            | BuiltinFunction::MathNoise
             | BuiltinFunction::MathSin
             | BuiltinFunction::MathAbs
             | BuiltinFunction::MathSqrt
+            | BuiltinFunction::MathCbrt
+            | BuiltinFunction::MathSquare
             | BuiltinFunction::MathFloor
             | BuiltinFunction::MathCeil
             | BuiltinFunction::MathRound
             | BuiltinFunction::MathTrunc
+            | BuiltinFunction::MathFrac
+            | BuiltinFunction::MathClamp01
             | BuiltinFunction::MathAcos
             | BuiltinFunction::MathAsin
             | BuiltinFunction::MathAtan
             | BuiltinFunction::MathExp
+            | BuiltinFunction::MathSinh
+            | BuiltinFunction::MathCosh
+            | BuiltinFunction::MathTanh
             | BuiltinFunction::MathLn
             | BuiltinFunction::MathSign
             | BuiltinFunction::MathMinAngle
@@ -228,10 +824,23 @@ impl BuiltinFunction {
             | BuiltinFunction::MathMax
             | BuiltinFunction::MathMin
             | BuiltinFunction::MathMod
-            | BuiltinFunction::MathCopySign => 2,
+            | BuiltinFunction::MathModEuclid
+            | BuiltinFunction::MathSnap
+            | BuiltinFunction::MathFloorTo
+            | BuiltinFunction::MathCeilTo
+            | BuiltinFunction::MathRoundTo
+            | BuiltinFunction::MathRatio
+            | BuiltinFunction::MathCopySign
+            | BuiltinFunction::MathRgbChannel => 2,
             BuiltinFunction::MathClamp
+            | BuiltinFunction::MathWrap
             | BuiltinFunction::MathLerp
+            | BuiltinFunction::MathSmoothstep
+            | BuiltinFunction::MathSmootherstep
+            | BuiltinFunction::MathLerpClamped
+            | BuiltinFunction::MathRgb
             | BuiltinFunction::MathInverseLerp
+            | BuiltinFunction::MathApproxEqual
             | BuiltinFunction::MathLerpRotate
             | BuiltinFunction::MathDieRoll
             | BuiltinFunction::MathDieRollInteger
@@ -265,6 +874,7 @@ impl BuiltinFunction {
             | BuiltinFunction::MathEaseInBounce
             | BuiltinFunction::MathEaseOutBounce
             | BuiltinFunction::MathEaseInOutBounce => 3,
+            BuiltinFunction::MathMapRange => 5,
         }
     }
 
@@ -276,31 +886,53 @@ impl BuiltinFunction {
             BuiltinFunction::MathRandom => "builtin_math_random",
             BuiltinFunction::MathRandomInteger => "builtin_math_random_integer",
             BuiltinFunction::MathClamp => "builtin_math_clamp",
+            BuiltinFunction::MathWrap => "builtin_math_wrap",
             BuiltinFunction::MathSqrt => "builtin_math_sqrt",
+            BuiltinFunction::MathCbrt => "builtin_math_cbrt",
             BuiltinFunction::MathFloor => "builtin_math_floor",
             BuiltinFunction::MathCeil => "builtin_math_ceil",
             BuiltinFunction::MathRound => "builtin_math_round",
             BuiltinFunction::MathTrunc => "builtin_math_trunc",
+            BuiltinFunction::MathFrac => "builtin_math_frac",
+            BuiltinFunction::MathClamp01 => "builtin_math_clamp01",
             BuiltinFunction::MathAcos => "builtin_math_acos",
             BuiltinFunction::MathAsin => "builtin_math_asin",
             BuiltinFunction::MathAtan => "builtin_math_atan",
             BuiltinFunction::MathAtan2 => "builtin_math_atan2",
+            BuiltinFunction::MathSinh => "builtin_math_sinh",
+            BuiltinFunction::MathCosh => "builtin_math_cosh",
+            BuiltinFunction::MathTanh => "builtin_math_tanh",
             BuiltinFunction::MathExp => "builtin_math_exp",
             BuiltinFunction::MathLn => "builtin_math_ln",
             BuiltinFunction::MathPow => "builtin_math_pow",
+            BuiltinFunction::MathSquare => "builtin_math_square",
             BuiltinFunction::MathMax => "builtin_math_max",
             BuiltinFunction::MathMin => "builtin_math_min",
             BuiltinFunction::MathMod => "builtin_math_mod",
+            BuiltinFunction::MathModEuclid => "builtin_math_mod_euclid",
+            BuiltinFunction::MathSnap => "builtin_math_snap",
+            BuiltinFunction::MathFloorTo => "builtin_math_floor_to",
+            BuiltinFunction::MathCeilTo => "builtin_math_ceil_to",
+            BuiltinFunction::MathRoundTo => "builtin_math_round_to",
+            BuiltinFunction::MathRatio => "builtin_math_ratio",
             BuiltinFunction::MathSign => "builtin_math_sign",
             BuiltinFunction::MathCopySign => "builtin_math_copy_sign",
             BuiltinFunction::MathPi => "builtin_math_pi",
             BuiltinFunction::MathMinAngle => "builtin_math_min_angle",
             BuiltinFunction::MathLerp => "builtin_math_lerp",
+            BuiltinFunction::MathLerpClamped => "builtin_math_lerp_clamped",
             BuiltinFunction::MathInverseLerp => "builtin_math_inverse_lerp",
+            BuiltinFunction::MathMapRange => "builtin_math_map_range",
+            BuiltinFunction::MathApproxEqual => "builtin_math_approx_equal",
             BuiltinFunction::MathLerpRotate => "builtin_math_lerprotate",
             BuiltinFunction::MathHermiteBlend => "builtin_math_hermite_blend",
+            BuiltinFunction::MathSmoothstep => "builtin_math_smoothstep",
+            BuiltinFunction::MathSmootherstep => "builtin_math_smootherstep",
+            BuiltinFunction::MathRgb => "builtin_math_rgb",
+            BuiltinFunction::MathRgbChannel => "builtin_math_rgb_channel",
             BuiltinFunction::MathDieRoll => "builtin_math_die_roll",
             BuiltinFunction::MathDieRollInteger => "builtin_math_die_roll_integer",
+            BuiltinFunction::MathNoise => "builtin_math_noise",
             BuiltinFunction::MathEaseInQuad => "builtin_math_ease_in_quad",
             BuiltinFunction::MathEaseOutQuad => "builtin_math_ease_out_quad",
             BuiltinFunction::MathEaseInOutQuad => "builtin_math_ease_in_out_quad",
@@ -334,6 +966,28 @@ impl BuiltinFunction {
         }
     }
 
+    /// True for `math.max` / `math.min`, which take two or more arguments; `arity()` is
+    /// their minimum.
+    pub fn is_variadic(self) -> bool {
+        matches!(self, BuiltinFunction::MathMax | BuiltinFunction::MathMin)
+    }
+
+    /// False for builtins drawing from the random generator, and for `math.noise` whose
+    /// output changes with `seed_rng`; their calls must not be folded into constants.
+    pub fn is_deterministic(self) -> bool {
+        !matches!(
+            self,
+            BuiltinFunction::MathRandom
+                | BuiltinFunction::MathRandomInteger
+                | BuiltinFunction::MathDieRoll
+                | BuiltinFunction::MathDieRollInteger
+                | BuiltinFunction::MathNoise
+        )
+    }
+
+    /// Runs the builtin on plain numbers, for constant folding and runtime helpers. Every
+    /// call is arity-checked during lowering first, so missing-argument defaults are never
+    /// reached from a script.
     pub fn evaluate(self, args: &[f64]) -> f64 {
         match self {
             BuiltinFunction::MathCos => {
@@ -358,9 +1012,17 @@ impl BuiltinFunction {
                 args.get(1).copied().unwrap_or(0.0),
                 args.get(2).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathWrap => crate::builtins::builtin_math_wrap(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
             BuiltinFunction::MathSqrt => {
                 crate::builtins::builtin_math_sqrt(args.first().copied().unwrap_or(0.0))
             }
+            BuiltinFunction::MathCbrt => {
+                crate::builtins::builtin_math_cbrt(args.first().copied().unwrap_or(0.0))
+            }
             BuiltinFunction::MathFloor => {
                 crate::builtins::builtin_math_floor(args.first().copied().unwrap_or(0.0))
             }
@@ -373,6 +1035,12 @@ impl BuiltinFunction {
             BuiltinFunction::MathTrunc => {
                 crate::builtins::builtin_math_trunc(args.first().copied().unwrap_or(0.0))
             }
+            BuiltinFunction::MathFrac => {
+                crate::builtins::builtin_math_frac(args.first().copied().unwrap_or(0.0))
+            }
+            BuiltinFunction::MathClamp01 => {
+                crate::builtins::builtin_math_clamp01(args.first().copied().unwrap_or(0.0))
+            }
             BuiltinFunction::MathAcos => {
                 crate::builtins::builtin_math_acos(args.first().copied().unwrap_or(0.0))
             }
@@ -386,6 +1054,15 @@ impl BuiltinFunction {
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathSinh => {
+                crate::builtins::builtin_math_sinh(args.first().copied().unwrap_or(0.0))
+            }
+            BuiltinFunction::MathCosh => {
+                crate::builtins::builtin_math_cosh(args.first().copied().unwrap_or(0.0))
+            }
+            BuiltinFunction::MathTanh => {
+                crate::builtins::builtin_math_tanh(args.first().copied().unwrap_or(0.0))
+            }
             BuiltinFunction::MathExp => {
                 crate::builtins::builtin_math_exp(args.first().copied().unwrap_or(0.0))
             }
@@ -396,6 +1073,9 @@ impl BuiltinFunction {
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathSquare => {
+                crate::builtins::builtin_math_square(args.first().copied().unwrap_or(0.0))
+            }
             BuiltinFunction::MathMax => crate::builtins::builtin_math_max(
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
@@ -408,6 +1088,30 @@ impl BuiltinFunction {
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathModEuclid => crate::builtins::builtin_math_mod_euclid(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathSnap => crate::builtins::builtin_math_snap(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathFloorTo => crate::builtins::builtin_math_floor_to(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathCeilTo => crate::builtins::builtin_math_ceil_to(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathRoundTo => crate::builtins::builtin_math_round_to(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathRatio => crate::builtins::builtin_math_ratio(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
             BuiltinFunction::MathSign => {
                 crate::builtins::builtin_math_sign(args.first().copied().unwrap_or(0.0))
             }
@@ -424,11 +1128,28 @@ impl BuiltinFunction {
                 args.get(1).copied().unwrap_or(0.0),
                 args.get(2).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathLerpClamped => crate::builtins::builtin_math_lerp_clamped(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
             BuiltinFunction::MathInverseLerp => crate::builtins::builtin_math_inverse_lerp(
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
                 args.get(2).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathMapRange => crate::builtins::builtin_math_map_range(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+                args.get(3).copied().unwrap_or(0.0),
+                args.get(4).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathApproxEqual => crate::builtins::builtin_math_approx_equal(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
             BuiltinFunction::MathLerpRotate => crate::builtins::builtin_math_lerprotate(
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
@@ -437,6 +1158,25 @@ impl BuiltinFunction {
             BuiltinFunction::MathHermiteBlend => {
                 crate::builtins::builtin_math_hermite_blend(args.first().copied().unwrap_or(0.0))
             }
+            BuiltinFunction::MathSmoothstep => crate::builtins::builtin_math_smoothstep(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathSmootherstep => crate::builtins::builtin_math_smootherstep(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathRgb => crate::builtins::builtin_math_rgb(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+                args.get(2).copied().unwrap_or(0.0),
+            ),
+            BuiltinFunction::MathRgbChannel => crate::builtins::builtin_math_rgb_channel(
+                args.get(0).copied().unwrap_or(0.0),
+                args.get(1).copied().unwrap_or(0.0),
+            ),
             BuiltinFunction::MathDieRoll => crate::builtins::builtin_math_die_roll(
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
@@ -447,6 +1187,9 @@ impl BuiltinFunction {
                 args.get(1).copied().unwrap_or(0.0),
                 args.get(2).copied().unwrap_or(0.0),
             ),
+            BuiltinFunction::MathNoise => {
+                crate::builtins::builtin_math_noise(args.first().copied().unwrap_or(0.0))
+            }
             BuiltinFunction::MathEaseInQuad => crate::builtins::builtin_math_ease_in_quad(
                 args.get(0).copied().unwrap_or(0.0),
                 args.get(1).copied().unwrap_or(0.0),
@@ -606,9 +1349,19 @@ impl BuiltinFunction {
 }
 
 #[derive(Default)]
-pub struct IrBuilder;
+pub struct IrBuilder {
+    host_functions: HashSet<String>,
+}
 
 impl IrBuilder {
+    /// Lets calls to these host functions (canonical names such as `query.double`) lower
+    /// instead of failing as unknown.
+    pub fn with_host_functions<'a>(mut self, names: impl IntoIterator<Item = &'a str>) -> Self {
+        self.host_functions
+            .extend(names.into_iter().map(str::to_string));
+        self
+    }
+
     /// Lowers a full AST program into statement-level IR.
     pub fn lower_program(&self, program: &Program) -> Result<IrProgram, LowerError> {
         let mut statements = Vec::new();
@@ -625,13 +1378,25 @@ impl IrBuilder {
                 target: target.clone(),
                 value: self.lower_expr(value)?,
             },
+            Statement::IndexAssignment {
+                target,
+                index,
+                op,
+                value,
+            } => IrStatement::IndexAssign {
+                target: target.clone(),
+                index: self.lower_expr(index)?,
+                op: *op,
+                value: self.lower_expr(value)?,
+            },
             Statement::Block(list) => IrStatement::Block(
                 list.iter()
                     .map(|stmt| self.lower_statement(stmt))
                     .collect::<Result<Vec<_>, _>>()?,
             ),
-            Statement::Loop { count, body } => IrStatement::Loop {
+            Statement::Loop { count, index, body } => IrStatement::Loop {
                 count: self.lower_expr(count)?,
+                index: index.clone(),
                 body: Box::new(self.lower_statement(body)?),
             },
             Statement::ForEach {
@@ -673,10 +1438,14 @@ impl IrBuilder {
                 }
                 Ok(IrExpr::Struct(lowered))
             }
-            Expr::Unary { op, expr } => Ok(IrExpr::Unary {
-                op: *op,
-                expr: Box::new(self.lower_expr(expr)?),
-            }),
+            Expr::Unary { op, expr } => match (op, self.lower_expr(expr)?) {
+                (UnaryOp::Plus, IrExpr::Constant(value)) => Ok(IrExpr::Constant(value)),
+                (UnaryOp::Minus, IrExpr::Constant(value)) => Ok(IrExpr::Constant(-value)),
+                (op, lowered) => Ok(IrExpr::Unary {
+                    op: *op,
+                    expr: Box::new(lowered),
+                }),
+            },
             Expr::Binary { op, left, right } => Ok(IrExpr::Binary {
                 op: *op,
                 left: Box::new(self.lower_expr(left)?),
@@ -694,34 +1463,81 @@ impl IrBuilder {
                     None => None,
                 },
             }),
-            Expr::Call { target, args } => {
-                let lowered_args = args
+            Expr::Call { target, args, span } => {
+                let mut lowered_args = args
                     .iter()
                     .map(|arg| self.lower_expr(arg))
                     .collect::<Result<Vec<_>, _>>()?;
-                let function = self.lower_call_target(target)?;
-                self.validate_call(&function, lowered_args.len())?;
-                Ok(IrExpr::Call {
-                    function,
-                    args: lowered_args,
-                })
+                let method = match target.as_ref() {
+                    Expr::Path(parts) => ValueFunction::from_method(parts, args.len()),
+                    _ => None,
+                };
+                let function = match (self.lower_call_target(target, *span), method) {
+                    (Err(LowerError::UnknownFunction { .. }), Some((function, receiver))) => {
+                        lowered_args.insert(0, IrExpr::Path(receiver.to_vec()));
+                        FunctionRef::Value(function)
+                    }
+                    (function, _) => function?,
+                };
+                self.validate_call(&function, lowered_args.len(), *span)?;
+                if matches!(function, FunctionRef::Builtin(builtin) if builtin.is_variadic()) {
+                    // `max(a, b, c)` becomes `max(max(a, b), c)`
+                    let rest = lowered_args.split_off(2);
+                    let first = call_or_fold(function.clone(), lowered_args);
+                    return Ok(rest.into_iter().fold(first, |chain, arg| {
+                        call_or_fold(function.clone(), vec![chain, arg])
+                    }));
+                }
+                Ok(call_or_fold(function, lowered_args))
             }
             Expr::Flow(flow) => Ok(IrExpr::Flow(*flow)),
             Expr::Index { target, index } => Ok(IrExpr::Index {
                 target: Box::new(self.lower_expr(target)?),
                 index: Box::new(self.lower_expr(index)?),
             }),
+            // `{ a: 5 }.a` resolves to the field at compile time; a missing field reads 0
+            Expr::Member { target, field } => match self.lower_expr(target)? {
+                IrExpr::Struct(fields) => Ok(fields
+                    .into_iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(field))
+                    .map_or(IrExpr::Constant(0.0), |(_, value)| value)),
+                _ => Ok(IrExpr::Constant(0.0)),
+            },
+            Expr::Match {
+                subject,
+                cases,
+                default,
+            } => Ok(IrExpr::Match {
+                subject: Box::new(self.lower_expr(subject)?),
+                cases: cases
+                    .iter()
+                    .map(|(label, result)| Ok((self.lower_expr(label)?, self.lower_expr(result)?)))
+                    .collect::<Result<Vec<_>, LowerError>>()?,
+                default: match default {
+                    Some(expr) => Some(Box::new(self.lower_expr(expr)?)),
+                    None => None,
+                },
+            }),
         }
     }
 
-    fn lower_call_target(&self, target: &Expr) -> Result<FunctionRef, LowerError> {
+    fn lower_call_target(
+        &self,
+        target: &Expr,
+        span: Option<Span>,
+    ) -> Result<FunctionRef, LowerError> {
         match target {
             Expr::Path(parts) => {
                 if let Some(builtin) = BuiltinFunction::from_path(parts) {
                     Ok(FunctionRef::Builtin(builtin))
+                } else if let Some(function) = ValueFunction::from_path(parts) {
+                    Ok(FunctionRef::Value(function))
+                } else if let Some(name) = self.host_function(parts) {
+                    Ok(FunctionRef::Host(name))
                 } else {
                     Err(LowerError::UnknownFunction {
                         name: parts.join("."),
+                        span,
                     })
                 }
             }
@@ -731,34 +1547,97 @@ impl IrBuilder {
         }
     }
 
-    fn validate_call(&self, function: &FunctionRef, arg_count: usize) -> Result<(), LowerError> {
-        match function {
-            FunctionRef::Builtin(builtin) => {
-                let expected = builtin.arity();
-                if expected != arg_count {
-                    Err(LowerError::InvalidArgumentCount {
-                        name: builtin.symbol_name().to_string(),
-                        expected,
+    fn host_function(&self, parts: &[String]) -> Option<QualifiedName> {
+        let name = QualifiedName::from_parts(parts);
+        self.host_functions
+            .contains(&name.to_string())
+            .then_some(name)
+    }
+
+    fn validate_call(
+        &self,
+        function: &FunctionRef,
+        arg_count: usize,
+        span: Option<Span>,
+    ) -> Result<(), LowerError> {
+        let (name, expected) = match function {
+            FunctionRef::Builtin(builtin) if builtin.is_variadic() && arg_count >= 2 => {
+                return Ok(());
+            }
+            FunctionRef::Builtin(builtin) => (builtin.symbol_name(), builtin.arity()),
+            FunctionRef::Value(function) => (function.name(), function.arity()),
+            FunctionRef::Host(name) => {
+                // Host functions take any number of arguments up to the helper's limit
+                return if arg_count > HOST_FUNCTION_MAX_ARGS {
+                    Err(LowerError::TooManyHostArguments {
+                        name: name.to_string(),
+                        max: HOST_FUNCTION_MAX_ARGS,
                         actual: arg_count,
+                        span,
                     })
                 } else {
                     Ok(())
-                }
+                };
             }
+        };
+        if expected != arg_count {
+            Err(LowerError::InvalidArgumentCount {
+                name: name.to_string(),
+                expected,
+                actual: arg_count,
+                span,
+            })
+        } else {
+            Ok(())
         }
     }
 }
 
 #[derive(Debug, Error)]
 pub enum LowerError {
-    #[error("unknown function `{name}`")]
-    UnknownFunction { name: String },
+    #[error("unknown function `{name}`{}", at(.span))]
+    UnknownFunction { name: String, span: Option<Span> },
     #[error("unsupported call target: {description}")]
     UnsupportedCallTarget { description: String },
-    #[error("invalid argument count for `{name}`: expected {expected}, got {actual}")]
+    #[error(
+        "invalid argument count for `{name}`: expected {expected}, got {actual}{}",
+        at(.span)
+    )]
     InvalidArgumentCount {
         name: String,
         expected: usize,
         actual: usize,
+        span: Option<Span>,
+    },
+    #[error(
+        "host function `{name}` accepts at most {max} arguments, got {actual}{}",
+        at(.span)
+    )]
+    TooManyHostArguments {
+        name: String,
+        max: usize,
+        actual: usize,
+        span: Option<Span>,
     },
+    #[error("script reads `{name}`, which the context does not provide")]
+    MissingQuery { name: String },
+}
+
+impl LowerError {
+    /// Source range of the call the error refers to, when the parser recorded one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            LowerError::UnknownFunction { span, .. }
+            | LowerError::InvalidArgumentCount { span, .. }
+            | LowerError::TooManyHostArguments { span, .. } => *span,
+            LowerError::UnsupportedCallTarget { .. } | LowerError::MissingQuery { .. } => None,
+        }
+    }
+}
+
+/// ` at 4..15` suffix for error messages, empty without a span.
+fn at(span: &Option<Span>) -> String {
+    span.map_or_else(String::new, |span| {
+        format!(" at {}..{}", span.start, span.end)
+    })
 }
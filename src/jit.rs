@@ -1,7 +1,13 @@
 use crate::ast::{BinaryOp, UnaryOp};
 use crate::builtins;
-use crate::eval::{QualifiedName, RuntimeContext, Value as RuntimeValue};
-use crate::ir::{BuiltinFunction, FunctionRef, IrExpr, IrProgram, IrStatement};
+use crate::eval::{
+    normalize_zero, QualifiedName, RuntimeContext, Value as RuntimeValue,
+    DEFAULT_MAX_LOOP_ITERATIONS, NUMBER_EPSILON,
+};
+use crate::ir::{
+    BuiltinFunction, FunctionRef, IrExpr, IrProgram, IrStatement, ValueFunction,
+    is_string_concat, BROADCAST_BUILTINS, HOST_FUNCTION_MAX_ARGS,
+};
 use cranelift::prelude::*;
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{FuncId, Linkage, Module};
@@ -20,6 +26,7 @@ pub struct CompiledExpression {
     func_id: FuncId,
     _slot_data: Vec<Box<[u8]>>,
     slots: Vec<RuntimeSlot>,
+    code_size: usize,
 }
 
 impl CompiledExpression {
@@ -31,7 +38,20 @@ impl CompiledExpression {
                 extern "C" fn(*mut RuntimeContext, *const RuntimeSlot) -> f64,
             >(raw)
         };
-        Ok(func(ctx, self.slots.as_ptr()))
+        let rng = ctx.take_rng();
+        let (value, rng) = builtins::with_script_rng(rng, || func(ctx, self.slots.as_ptr()));
+        ctx.restore_rng(rng);
+        Ok(normalize_zero(value))
+    }
+
+    /// Approximate bytes held by this compiled code: the machine code plus slot names and
+    /// bookkeeping. Allocator page rounding inside the JIT module is not counted.
+    pub fn estimated_size(&self) -> usize {
+        let slot_bytes: usize = self._slot_data.iter().map(|bytes| bytes.len()).sum();
+        std::mem::size_of::<Self>()
+            + self.code_size
+            + slot_bytes
+            + self.slots.len() * std::mem::size_of::<RuntimeSlot>()
     }
 }
 
@@ -72,6 +92,9 @@ pub fn compile_expression(expr: &IrExpr) -> Result<CompiledExpression, JitError>
 
     let func_id = module.declare_function("molang_expr", Linkage::Export, &ctx.func.signature)?;
     module.define_function(func_id, &mut ctx)?;
+    let code_size = ctx
+        .compiled_code()
+        .map_or(0, |code| code.code_buffer().len());
     module.clear_context(&mut ctx);
     module.finalize_definitions()?;
 
@@ -94,6 +117,7 @@ pub fn compile_expression(expr: &IrExpr) -> Result<CompiledExpression, JitError>
         func_id,
         _slot_data: slot_data,
         slots,
+        code_size,
     })
 }
 
@@ -133,6 +157,9 @@ pub fn compile_program(program: &IrProgram) -> Result<CompiledExpression, JitErr
 
     let func_id = module.declare_function("molang_prog", Linkage::Export, &ctx.func.signature)?;
     module.define_function(func_id, &mut ctx)?;
+    let code_size = ctx
+        .compiled_code()
+        .map_or(0, |code| code.code_buffer().len());
     module.clear_context(&mut ctx);
     module.finalize_definitions()?;
 
@@ -155,6 +182,7 @@ pub fn compile_program(program: &IrProgram) -> Result<CompiledExpression, JitErr
         func_id,
         _slot_data: slot_data,
         slots,
+        code_size,
     })
 }
 
@@ -177,6 +205,8 @@ struct Translator<'a, 'b> {
     exit_block: Block,
     return_var: Variable,
     loop_stack: Vec<LoopContext>,
+    /// Set while translating a trailing `loop`, whose last body value becomes the result.
+    loop_result: bool,
 }
 
 impl<'a, 'b> Translator<'a, 'b> {
@@ -208,6 +238,7 @@ impl<'a, 'b> Translator<'a, 'b> {
             exit_block,
             return_var,
             loop_stack: Vec::new(),
+            loop_result: false,
         }
     }
 
@@ -215,12 +246,63 @@ impl<'a, 'b> Translator<'a, 'b> {
     /// like strings, arrays, and structs.
     fn assign_expression(&mut self, target: &[String], expr: &IrExpr) -> Result<(), JitError> {
         match expr {
+            // Value function - the helper stores the full result into the target
+            IrExpr::Call {
+                function: FunctionRef::Value(function),
+                args,
+            } => {
+                self.emit_value_call(*function, args, Some(target))?;
+            }
+
+            // Host function - may return a struct/array, so the helper stores it whole
+            IrExpr::Call {
+                function: FunctionRef::Host(name),
+                args,
+            } => {
+                self.emit_host_call(name, args, Some(target))?;
+            }
+
+            // `path + path` - concatenates two strings, otherwise stays a native add
+            IrExpr::Binary { left, right, .. } if is_string_concat(expr) => {
+                match (left.as_ref(), right.as_ref()) {
+                    (IrExpr::Path(left_parts), IrExpr::Path(right_parts)) => {
+                        self.assign_path_sum(target, expr, left_parts, right_parts)?;
+                    }
+                    // String concatenation - both operands are read as full values at runtime
+                    _ => {
+                        let operands = [left.as_ref().clone(), right.as_ref().clone()];
+                        self.emit_value_call(ValueFunction::Concat, &operands, Some(target))?;
+                    }
+                }
+            }
+
+            // `math.lerp` between two arrays (or paths that may hold them)
+            IrExpr::Call {
+                function: FunctionRef::Builtin(BuiltinFunction::MathLerp),
+                args,
+            } if args.len() == 3
+                && args[..2]
+                    .iter()
+                    .all(|arg| matches!(arg, IrExpr::Array(_) | IrExpr::Path(_))) =>
+            {
+                self.emit_value_call(ValueFunction::ArrayLerp, args, Some(target))?;
+            }
+
+            // Element-wise builtin over an array (or a path or call that may yield one)
+            IrExpr::Call {
+                function: FunctionRef::Builtin(builtin),
+                args,
+            } if builtin.broadcast_code().is_some() && args.first().is_some_and(may_broadcast) => {
+                self.assign_broadcast_call(target, *builtin, args)?;
+            }
+
             // Numeric constant or computed value - evaluate and store
             IrExpr::Constant(_)
             | IrExpr::Path(_)
             | IrExpr::Unary { .. }
             | IrExpr::Binary { .. }
             | IrExpr::Conditional { .. }
+            | IrExpr::Match { .. }
             | IrExpr::Call { .. } => {
                 let value = self.translate(expr)?;
                 self.store_number(target, value)?;
@@ -270,6 +352,7 @@ impl<'a, 'b> Translator<'a, 'b> {
                         | IrExpr::Unary { .. }
                         | IrExpr::Binary { .. }
                         | IrExpr::Conditional { .. }
+                        | IrExpr::Match { .. }
                         | IrExpr::Call { .. } => {
                             // Numeric element
                             let value = self.translate(element)?;
@@ -352,6 +435,103 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(())
     }
 
+    /// `target = left + right` over two paths. A borrowing type check picks the `Concat`
+    /// helper only when both hold strings, so numeric accumulators keep a plain `fadd`.
+    fn assign_path_sum(
+        &mut self,
+        target: &[String],
+        sum: &IrExpr,
+        left: &[String],
+        right: &[String],
+    ) -> Result<(), JitError> {
+        let left_slot = self.ensure_slot_from_parts(left);
+        let (left_ptr, left_len) = self.slot_pointer_components(left_slot);
+        let right_slot = self.ensure_slot_from_parts(right);
+        let (right_ptr, right_len) = self.slot_pointer_components(right_slot);
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.both_strings, self.builder.func);
+        let call = self.builder.ins().call(
+            func_ref,
+            &[self.runtime_ptr, left_ptr, left_len, right_ptr, right_len],
+        );
+        let both_strings = self.builder.inst_results(call)[0];
+        let condition = self.bool_from_value(both_strings);
+
+        let concat_block = self.builder.create_block();
+        let add_block = self.builder.create_block();
+        let merge_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(condition, concat_block, &[], add_block, &[]);
+
+        self.builder.switch_to_block(concat_block);
+        let operands = [IrExpr::Path(left.to_vec()), IrExpr::Path(right.to_vec())];
+        self.emit_value_call(ValueFunction::Concat, &operands, Some(target))?;
+        self.builder.ins().jump(merge_block, &[]);
+        self.builder.seal_block(concat_block);
+
+        self.builder.switch_to_block(add_block);
+        let value = self.translate(sum)?;
+        self.store_number(target, value)?;
+        self.builder.ins().jump(merge_block, &[]);
+        self.builder.seal_block(add_block);
+
+        self.builder.switch_to_block(merge_block);
+        self.builder.seal_block(merge_block);
+        Ok(())
+    }
+
+    /// Applies a broadcastable builtin to every element when the first argument holds an
+    /// array at runtime; scalars go through the same helper and store a plain number.
+    fn assign_broadcast_call(
+        &mut self,
+        target: &[String],
+        builtin: BuiltinFunction,
+        args: &[IrExpr],
+    ) -> Result<(), JitError> {
+        let code = builtin.broadcast_code().unwrap_or_default();
+        let target_slot = self.ensure_slot_from_parts(target);
+        let source_slot = match &args[0] {
+            IrExpr::Path(parts) => self.ensure_slot_from_parts(parts),
+            source => {
+                // Materialize the literal (or nested call) into the target, then map it in place
+                self.assign_expression(target, source)?;
+                target_slot
+            }
+        };
+
+        let mut extra = Vec::with_capacity(2);
+        for index in 1..3 {
+            let value = match args.get(index) {
+                Some(arg) => self.translate(arg)?,
+                None => self.const_f64(0.0),
+            };
+            extra.push(value);
+        }
+
+        let (dest_ptr, dest_len) = self.slot_pointer_components(target_slot);
+        let (src_ptr, src_len) = self.slot_pointer_components(source_slot);
+        let code_value = self.builder.ins().iconst(types::I64, code as i64);
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.broadcast_builtin, self.builder.func);
+        self.builder.ins().call(
+            func_ref,
+            &[
+                self.runtime_ptr,
+                dest_ptr,
+                dest_len,
+                src_ptr,
+                src_len,
+                code_value,
+                extra[0],
+                extra[1],
+            ],
+        );
+        Ok(())
+    }
+
     fn translate(&mut self, expr: &IrExpr) -> Result<Value, JitError> {
         match expr {
             IrExpr::Constant(value) => Ok(self.builder.ins().f64const(Ieee64::with_float(*value))),
@@ -388,20 +568,8 @@ impl<'a, 'b> Translator<'a, 'b> {
                 // Otherwise, this is array indexing
                 if let IrExpr::Path(array_path) = target.as_ref() {
                     let index_value = self.translate(index)?;
-                    let array_name = QualifiedName::from_parts(array_path);
-                    let array_slot = self.ensure_slot(&array_name);
-                    let (array_ptr, array_len) = self.slot_pointer_components(array_slot);
-
-                    let func_ref = self.module.declare_func_in_func(
-                        self.runtime_helpers.array_get_number,
-                        self.builder.func,
-                    );
-                    let call = self.builder.ins().call(
-                        func_ref,
-                        &[self.runtime_ptr, array_ptr, array_len, index_value],
-                    );
-                    let results = self.builder.inst_results(call);
-                    Ok(results[0])
+                    let array_slot = self.ensure_slot_from_parts(array_path);
+                    Ok(self.emit_array_get(array_slot, index_value))
                 } else {
                     Err(JitError::UnsupportedExpression {
                         feature: "indexing non-path expression",
@@ -441,9 +609,11 @@ impl<'a, 'b> Translator<'a, 'b> {
                     UnaryOp::Plus => value,
                     UnaryOp::Minus => self.builder.ins().fneg(value),
                     UnaryOp::Not => {
-                        let bool_val = self.bool_from_value(value);
-                        let inverted = self.builder.ins().bnot(bool_val);
-                        self.float_from_bool(inverted)
+                        // `bnot` on the 0/1 flag would flip every bit (giving 255 / 254), so
+                        // compare against zero directly; NaN stays truthy, as in `bool_from_value`
+                        let zero = self.const_f64(0.0);
+                        let is_zero = self.builder.ins().fcmp(FloatCC::Equal, value, zero);
+                        self.float_from_bool(is_zero)
                     }
                 })
             }
@@ -464,16 +634,20 @@ impl<'a, 'b> Translator<'a, 'b> {
                     let (l, r) = self.translate_pair(left, right)?;
                     Ok(self.builder.ins().fdiv(l, r))
                 }
-                BinaryOp::Less => self.emit_comparison(FloatCC::LessThan, left, right),
-                BinaryOp::LessEqual => self.emit_comparison(FloatCC::LessThanOrEqual, left, right),
-                BinaryOp::Greater => self.emit_comparison(FloatCC::GreaterThan, left, right),
+                BinaryOp::Less => self.emit_ordering(FloatCC::LessThan, left, right),
+                BinaryOp::LessEqual => self.emit_ordering(FloatCC::LessThanOrEqual, left, right),
+                BinaryOp::Greater => self.emit_ordering(FloatCC::GreaterThan, left, right),
                 BinaryOp::GreaterEqual => {
-                    self.emit_comparison(FloatCC::GreaterThanOrEqual, left, right)
+                    self.emit_ordering(FloatCC::GreaterThanOrEqual, left, right)
                 }
                 BinaryOp::Equal => self.emit_value_equality(left, right, true),
                 BinaryOp::NotEqual => self.emit_value_equality(left, right, false),
                 BinaryOp::And => self.emit_logical_and(left, right),
                 BinaryOp::Or => self.emit_logical_or(left, right),
+                BinaryOp::BitAnd | BinaryOp::BitOr | BinaryOp::BitXor => {
+                    let (l, r) = self.translate_pair(left, right)?;
+                    Ok(self.emit_bitwise(*op, l, r))
+                }
                 BinaryOp::NullCoalesce => self.emit_null_coalesce(left, right),
             },
             IrExpr::Conditional {
@@ -481,17 +655,48 @@ impl<'a, 'b> Translator<'a, 'b> {
                 then_branch,
                 else_branch,
             } => self.emit_conditional(condition, then_branch, else_branch.as_deref()),
-            IrExpr::Call { function, args } => self.emit_call(*function, args),
+            IrExpr::Match {
+                subject,
+                cases,
+                default,
+            } => self.emit_match(subject, cases, default.as_deref()),
+            IrExpr::Call { function, args } => self.emit_call(function, args),
         }
     }
+    /// Truncates both operands to `i64` (saturating), applies the bitwise op and converts
+    /// the result back to `f64`.
+    fn emit_bitwise(&mut self, op: BinaryOp, left: Value, right: Value) -> Value {
+        let l = self.builder.ins().fcvt_to_sint_sat(types::I64, left);
+        let r = self.builder.ins().fcvt_to_sint_sat(types::I64, right);
+        let result = match op {
+            BinaryOp::BitAnd => self.builder.ins().band(l, r),
+            BinaryOp::BitOr => self.builder.ins().bor(l, r),
+            _ => self.builder.ins().bxor(l, r),
+        };
+        self.builder.ins().fcvt_from_sint(types::F64, result)
+    }
+
     fn finish_expression(self, result: Value) -> Vec<QualifiedName> {
         self.builder.ins().return_(&[result]);
         self.slot_names
     }
 
     fn translate_program(mut self, program: &IrProgram) -> Result<Vec<QualifiedName>, JitError> {
-        for statement in &program.statements {
-            self.translate_statement(statement)?;
+        if let Some((last, rest)) = program.statements.split_last() {
+            for statement in rest {
+                self.translate_statement(statement)?;
+            }
+            if let IrStatement::Expr(expr) = last {
+                // Without an explicit `return`, the script yields its last expression. This
+                // also covers a lone expression with string or array operands, which yields
+                // its value exactly like the pure expression path would.
+                let value = self.emit_result(expr)?;
+                self.builder.def_var(self.return_var, value);
+            } else {
+                // A trailing loop yields its last body value (0 when it never runs)
+                self.loop_result = matches!(last, IrStatement::Loop { .. });
+                self.translate_statement(last)?;
+            }
         }
         if let Some(current) = self.builder.current_block() {
             if current != self.exit_block {
@@ -505,6 +710,31 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(self.slot_names)
     }
 
+    /// Translates `statement` and returns the number it produced: the expression value,
+    /// the assigned value read back, or the last statement of a block.
+    fn statement_value(&mut self, statement: &IrStatement) -> Result<Option<Value>, JitError> {
+        match statement {
+            IrStatement::Expr(expr) => self.translate(expr).map(Some),
+            IrStatement::Assign { target, .. } => {
+                self.translate_statement(statement)?;
+                self.peek_variable(target).map(Some)
+            }
+            IrStatement::Block(statements) => match statements.split_last() {
+                Some((last, rest)) => {
+                    for stmt in rest {
+                        self.translate_statement(stmt)?;
+                    }
+                    self.statement_value(last)
+                }
+                None => Ok(None),
+            },
+            _ => {
+                self.translate_statement(statement)?;
+                Ok(None)
+            }
+        }
+    }
+
     fn translate_statement(&mut self, statement: &IrStatement) -> Result<(), JitError> {
         match statement {
             IrStatement::Assign { target, value } => {
@@ -514,6 +744,14 @@ impl<'a, 'b> Translator<'a, 'b> {
                     self.assign_expression(target, value)?;
                 }
             }
+            IrStatement::IndexAssign {
+                target,
+                index,
+                op,
+                value,
+            } => {
+                self.assign_element(target, index, *op, value)?;
+            }
             IrStatement::Expr(expr) => {
                 let _ = self.translate(expr)?;
             }
@@ -524,23 +762,37 @@ impl<'a, 'b> Translator<'a, 'b> {
             }
             IrStatement::Return(expr) => {
                 let value = match expr {
-                    Some(expr) => self.translate(expr)?,
+                    Some(expr) => self.emit_result(expr)?,
                     None => self.const_f64(0.0),
                 };
                 self.builder.def_var(self.return_var, value);
                 self.builder.ins().jump(self.exit_block, &[]);
+                // Anything after the return (e.g. the rest of a loop body and the loop's
+                // back edge) lands in this unreachable block, so the CFG stays well formed
                 let next = self.builder.create_block();
                 self.builder.switch_to_block(next);
                 self.builder.seal_block(next);
             }
-            IrStatement::Loop { count, body } => {
-                // Evaluate the loop count
-                let count_value = self.translate(count)?;
+            IrStatement::Loop { count, index, body } => {
+                // Evaluate the loop count, clamped to the context's iteration limit
+                let requested = self.translate(count)?;
+                let func_ref = self
+                    .module
+                    .declare_func_in_func(self.runtime_helpers.loop_limit, self.builder.func);
+                let call = self.builder.ins().call(func_ref, &[self.runtime_ptr]);
+                let limit = self.builder.inst_results(call)[0];
+                let clamped = self.builder.ins().fmin(requested, limit);
+                // A non-finite count (NaN or an infinity) runs zero times; only finite
+                // counts above the limit are clamped
+                let zero = self.const_f64(0.0);
+                let infinity = self.const_f64(f64::INFINITY);
+                let magnitude = self.builder.ins().fabs(requested);
+                let is_finite = self.builder.ins().fcmp(FloatCC::LessThan, magnitude, infinity);
+                let count_value = self.builder.ins().select(is_finite, clamped, zero);
 
                 // Create a variable to hold the current iteration index
                 let loop_var = Variable::new(self.slot_names.len() + self.loop_stack.len() + 1);
                 self.builder.declare_var(loop_var, types::F64);
-                let zero = self.const_f64(0.0);
                 self.builder.def_var(loop_var, zero);
 
                 // Create loop blocks
@@ -560,6 +812,10 @@ impl<'a, 'b> Translator<'a, 'b> {
 
                 // Loop body
                 self.builder.switch_to_block(loop_body);
+                if let Some(index) = index {
+                    let current_index = self.builder.use_var(loop_var);
+                    self.store_number(index, current_index)?;
+                }
 
                 // Push loop context for break/continue
                 self.loop_stack.push(LoopContext {
@@ -567,7 +823,13 @@ impl<'a, 'b> Translator<'a, 'b> {
                     continue_block: loop_increment,
                 });
 
-                self.translate_statement(body)?;
+                if std::mem::take(&mut self.loop_result) {
+                    if let Some(value) = self.statement_value(body)? {
+                        self.builder.def_var(self.return_var, value);
+                    }
+                } else {
+                    self.translate_statement(body)?;
+                }
 
                 // Pop loop context
                 self.loop_stack.pop();
@@ -636,7 +898,8 @@ impl<'a, 'b> Translator<'a, 'b> {
 
                 // Copy current element to the loop variable
                 let current_index_f64 = self.builder.use_var(loop_var);
-                let current_index_i64 = self.builder.ins().fcvt_to_sint(types::I64, current_index_f64);
+                // Saturating conversion never traps, whatever the length helper reports
+                let current_index_i64 = self.builder.ins().fcvt_to_sint_sat(types::I64, current_index_f64);
                 let collection_slot = self.ensure_slot_from_parts(&collection_parts);
                 let (array_ptr, array_len) = self.slot_pointer_components(collection_slot);
                 let dest_slot = self.ensure_slot_from_parts(variable);
@@ -698,12 +961,19 @@ impl<'a, 'b> Translator<'a, 'b> {
     }
 
     fn load_variable(&mut self, parts: &[String]) -> Result<Value, JitError> {
+        self.load_number_with(parts, self.runtime_helpers.get_number)
+    }
+
+    /// Reads a number without flagging a missing variable, for `??` probes.
+    fn peek_variable(&mut self, parts: &[String]) -> Result<Value, JitError> {
+        self.load_number_with(parts, self.runtime_helpers.peek_number)
+    }
+
+    fn load_number_with(&mut self, parts: &[String], helper: FuncId) -> Result<Value, JitError> {
         let name = QualifiedName::from_parts(parts);
         let slot = self.ensure_slot(&name);
         let (ptr, len_value) = self.slot_pointer_components(slot);
-        let func_ref = self
-            .module
-            .declare_func_in_func(self.runtime_helpers.get_number, self.builder.func);
+        let func_ref = self.module.declare_func_in_func(helper, self.builder.func);
         let call = self
             .builder
             .ins()
@@ -751,6 +1021,97 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(())
     }
 
+    /// `target[index] = value` (or `op=`). The index is evaluated once and the element is
+    /// replaced inside the stored array. Numbers go through `molang_rt_array_set_number`;
+    /// other values are copied from their slot, literals via a scratch slot.
+    fn assign_element(
+        &mut self,
+        target: &[String],
+        index: &IrExpr,
+        op: Option<BinaryOp>,
+        value: &IrExpr,
+    ) -> Result<(), JitError> {
+        let array_slot = self.ensure_slot_from_parts(target);
+        let index_value = self.translate(index)?;
+
+        let numeric = match value {
+            IrExpr::Constant(_)
+            | IrExpr::Unary { .. }
+            | IrExpr::Conditional { .. }
+            | IrExpr::Match { .. }
+            | IrExpr::Index { .. } => true,
+            IrExpr::Binary { .. } => !is_string_concat(value),
+            _ => false,
+        };
+        if op.is_some() || numeric {
+            let mut number = self.translate(value)?;
+            if let Some(op) = op {
+                let current = self.emit_array_get(array_slot, index_value);
+                number = match op {
+                    BinaryOp::Add => self.builder.ins().fadd(current, number),
+                    BinaryOp::Sub => self.builder.ins().fsub(current, number),
+                    BinaryOp::Mul => self.builder.ins().fmul(current, number),
+                    _ => self.builder.ins().fdiv(current, number),
+                };
+            }
+            let (array_ptr, array_len) = self.slot_pointer_components(array_slot);
+            let func_ref = self
+                .module
+                .declare_func_in_func(self.runtime_helpers.array_set_number, self.builder.func);
+            self.builder.ins().call(
+                func_ref,
+                &[self.runtime_ptr, array_ptr, array_len, index_value, number],
+            );
+            return Ok(());
+        }
+
+        let (source_slot, scratch) = match value {
+            IrExpr::Path(parts) => (self.ensure_slot_from_parts(parts), None),
+            other => {
+                let scratch = vec![
+                    "temp".to_string(),
+                    format!("__element_{}", self.slot_names.len()),
+                ];
+                let slot = self.ensure_slot_from_parts(&scratch);
+                self.assign_expression(&scratch, other)?;
+                (slot, Some(slot))
+            }
+        };
+        let (array_ptr, array_len) = self.slot_pointer_components(array_slot);
+        let (src_ptr, src_len) = self.slot_pointer_components(source_slot);
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.array_set_value, self.builder.func);
+        self.builder.ins().call(
+            func_ref,
+            &[self.runtime_ptr, array_ptr, array_len, index_value, src_ptr, src_len],
+        );
+        if let Some(slot) = scratch {
+            self.clear_slot(slot);
+        }
+        Ok(())
+    }
+
+    /// Reads element `index` of the array in `array_slot` through `molang_rt_array_get_number`.
+    fn emit_array_get(&mut self, array_slot: usize, index_value: Value) -> Value {
+        self.call_array_helper(self.runtime_helpers.array_get_number, array_slot, index_value)
+    }
+
+    fn call_array_helper(
+        &mut self,
+        helper: FuncId,
+        array_slot: usize,
+        index_value: Value,
+    ) -> Value {
+        let (array_ptr, array_len) = self.slot_pointer_components(array_slot);
+        let func_ref = self.module.declare_func_in_func(helper, self.builder.func);
+        let call = self
+            .builder
+            .ins()
+            .call(func_ref, &[self.runtime_ptr, array_ptr, array_len, index_value]);
+        self.builder.inst_results(call)[0]
+    }
+
     fn ensure_slot(&mut self, name: &QualifiedName) -> usize {
         if let Some(index) = self.slot_map.get(name) {
             *index
@@ -807,16 +1168,190 @@ impl<'a, 'b> Translator<'a, 'b> {
             .call(func_ref, &[self.runtime_ptr, ptr, len_value]);
     }
 
-    fn emit_call(&mut self, function: FunctionRef, args: &[IrExpr]) -> Result<Value, JitError> {
+    /// Numeric result of a `return` (or a lone expression). Paths and string/array/struct
+    /// literals are also captured whole on the context so `evaluate_value` can read them.
+    fn emit_result(&mut self, expr: &IrExpr) -> Result<Value, JitError> {
+        match expr {
+            IrExpr::Path(parts) => {
+                let slot = self.ensure_slot_from_parts(parts);
+                self.capture_return(slot);
+                self.translate(expr)
+            }
+            IrExpr::String(_) | IrExpr::Array(_) | IrExpr::Struct(_) => {
+                self.emit_captured_result(expr)
+            }
+            other if is_string_concat(other) => self.emit_captured_result(other),
+            IrExpr::Call {
+                function: FunctionRef::Builtin(builtin),
+                args,
+            } if builtin.broadcast_code().is_some() && args.first().is_some_and(may_broadcast) => {
+                self.emit_captured_result(expr)
+            }
+            other => self.translate(other),
+        }
+    }
+
+    /// Builds a non-numeric result in a scratch slot and captures it whole.
+    fn emit_captured_result(&mut self, expr: &IrExpr) -> Result<Value, JitError> {
+        let scratch = ["temp".to_string(), "__return".to_string()];
+        self.assign_expression(&scratch, expr)?;
+        let slot = self.ensure_slot_from_parts(&scratch);
+        self.capture_return(slot);
+        // A `+` that turned out to add numbers yields its sum, a scalar broadcast its number
+        let number = match expr {
+            IrExpr::Array(_) => self.translate(expr)?,
+            IrExpr::Binary { .. } | IrExpr::Call { .. } => self.load_variable(&scratch)?,
+            _ => self.const_f64(0.0),
+        };
+        self.clear_slot(slot);
+        Ok(number)
+    }
+
+    fn capture_return(&mut self, slot: usize) {
+        let (ptr, len_value) = self.slot_pointer_components(slot);
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.capture_return, self.builder.func);
+        self.builder
+            .ins()
+            .call(func_ref, &[self.runtime_ptr, ptr, len_value]);
+    }
+
+    fn emit_call(&mut self, function: &FunctionRef, args: &[IrExpr]) -> Result<Value, JitError> {
         match function {
             FunctionRef::Builtin(builtin) => {
+                if builtin.broadcast_code().is_some() && args.first().is_some_and(has_array_literal)
+                {
+                    // Only assignments and returns can hold the element-wise result
+                    return Err(JitError::UnsupportedExpression {
+                        feature: "array argument outside an assignment or return",
+                    });
+                }
+                if let (BuiltinFunction::MathPow, Some(exponent)) =
+                    (builtin, small_integer_exponent(args))
+                {
+                    let base = self.translate(&args[0])?;
+                    return Ok(self.emit_integer_power(base, exponent));
+                }
                 let arg_values = args
                     .iter()
                     .map(|arg| self.translate(arg))
                     .collect::<Result<Vec<_>, _>>()?;
-                self.emit_builtin_call(builtin, &arg_values)
+                self.emit_builtin_call(*builtin, &arg_values)
+            }
+            FunctionRef::Value(function) => self.emit_value_call(*function, args, None),
+            FunctionRef::Host(name) => self.emit_host_call(name, args, None),
+        }
+    }
+
+    /// Calls a host function through `molang_rt_call_host`. The function's canonical name
+    /// travels as a slot string; unused argument registers are padded with zero. When
+    /// `dest` is set the full result value (e.g. a struct) is stored there.
+    fn emit_host_call(
+        &mut self,
+        name: &QualifiedName,
+        args: &[IrExpr],
+        dest: Option<&[String]>,
+    ) -> Result<Value, JitError> {
+        let mut arg_values = args
+            .iter()
+            .map(|arg| self.translate(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+        let argc = self.builder.ins().iconst(types::I64, arg_values.len() as i64);
+        while arg_values.len() < HOST_FUNCTION_MAX_ARGS {
+            arg_values.push(self.const_f64(0.0));
+        }
+
+        let slot = self.ensure_slot(name);
+        let (name_ptr, name_len) = self.slot_pointer_components(slot);
+        let mut call_args = vec![self.runtime_ptr, name_ptr, name_len];
+        match dest {
+            Some(parts) => {
+                let dest_slot = self.ensure_slot_from_parts(parts);
+                let (dest_ptr, dest_len) = self.slot_pointer_components(dest_slot);
+                call_args.extend([dest_ptr, dest_len]);
+            }
+            None => {
+                let null = self.builder.ins().iconst(self.pointer_type, 0);
+                call_args.extend([null, null]);
+            }
+        }
+        call_args.push(argc);
+        call_args.extend(arg_values);
+
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.call_host, self.builder.func);
+        let call = self.builder.ins().call(func_ref, &call_args);
+        Ok(self.builder.inst_results(call)[0])
+    }
+
+    /// Calls a value function through `molang_rt_call_value_function`. Path arguments pass
+    /// their slot directly; anything else is assigned to a scratch slot that is cleared
+    /// after the call. When `dest` is set the full result value is stored there.
+    fn emit_value_call(
+        &mut self,
+        function: ValueFunction,
+        args: &[IrExpr],
+        dest: Option<&[String]>,
+    ) -> Result<Value, JitError> {
+        if args.len() > ValueFunction::MAX_ARITY {
+            return Err(JitError::UnsupportedExpression {
+                feature: "value function with more than three arguments",
+            });
+        }
+
+        let mut arg_slots = Vec::with_capacity(args.len());
+        let mut scratch_slots = Vec::new();
+        for arg in args {
+            let slot = match arg {
+                IrExpr::Path(parts) => self.ensure_slot_from_parts(parts),
+                other => {
+                    let scratch = vec![
+                        "temp".to_string(),
+                        format!("__call_arg_{}", self.slot_names.len()),
+                    ];
+                    // Reserve the slot first so nested calls pick different scratch names
+                    let slot = self.ensure_slot_from_parts(&scratch);
+                    self.assign_expression(&scratch, other)?;
+                    scratch_slots.push(slot);
+                    slot
+                }
+            };
+            arg_slots.push(slot);
+        }
+
+        let null = self.builder.ins().iconst(self.pointer_type, 0);
+        let code = self.builder.ins().iconst(types::I64, function.code());
+        let mut call_args = vec![self.runtime_ptr, code];
+        match dest {
+            Some(parts) => {
+                let slot = self.ensure_slot_from_parts(parts);
+                let (ptr, len) = self.slot_pointer_components(slot);
+                call_args.extend([ptr, len]);
+            }
+            None => call_args.extend([null, null]),
+        }
+        for index in 0..ValueFunction::MAX_ARITY {
+            match arg_slots.get(index) {
+                Some(slot) => {
+                    let (ptr, len) = self.slot_pointer_components(*slot);
+                    call_args.extend([ptr, len]);
+                }
+                None => call_args.extend([null, null]),
             }
         }
+
+        let func_ref = self
+            .module
+            .declare_func_in_func(self.runtime_helpers.call_value_function, self.builder.func);
+        let call = self.builder.ins().call(func_ref, &call_args);
+        let result = self.builder.inst_results(call)[0];
+
+        for slot in scratch_slots {
+            self.clear_slot(slot);
+        }
+        Ok(result)
     }
 
     fn emit_comparison(
@@ -830,6 +1365,99 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(self.float_from_bool(cmp))
     }
 
+    /// `<`, `<=`, `>`, `>=`: two strings compare lexicographically, anything else numerically.
+    /// Operands involving variables are only known to be strings at runtime, so the helper
+    /// returns the ordering as `-1`/`0`/`1` (NaN when either side is not a string) and the
+    /// numeric comparison is selected when it is NaN.
+    fn emit_ordering(
+        &mut self,
+        cond: FloatCC,
+        left: &IrExpr,
+        right: &IrExpr,
+    ) -> Result<Value, JitError> {
+        let ordering = match (left, right) {
+            (IrExpr::String(left_str), IrExpr::String(right_str)) => {
+                let ordering = left_str.cmp(right_str) as i8 as f64;
+                let ordering = self.const_f64(ordering);
+                let zero = self.const_f64(0.0);
+                let cmp = self.builder.ins().fcmp(cond, ordering, zero);
+                return Ok(self.float_from_bool(cmp));
+            }
+            (IrExpr::Path(left_parts), IrExpr::Path(right_parts)) => {
+                let left_slot = self.ensure_slot_from_parts(left_parts);
+                let (left_ptr, left_len) = self.slot_pointer_components(left_slot);
+                let right_slot = self.ensure_slot_from_parts(right_parts);
+                let (right_ptr, right_len) = self.slot_pointer_components(right_slot);
+                let func_ref = self
+                    .module
+                    .declare_func_in_func(self.runtime_helpers.compare_paths, self.builder.func);
+                let call = self.builder.ins().call(
+                    func_ref,
+                    &[self.runtime_ptr, left_ptr, left_len, right_ptr, right_len],
+                );
+                self.builder.inst_results(call)[0]
+            }
+            (IrExpr::Path(path_parts), IrExpr::String(text))
+            | (IrExpr::String(text), IrExpr::Path(path_parts)) => {
+                let path_slot = self.ensure_slot_from_parts(path_parts);
+                let (path_ptr, path_len) = self.slot_pointer_components(path_slot);
+                let (str_ptr, str_len) = self.string_data(text)?;
+                let func_ref = self.module.declare_func_in_func(
+                    self.runtime_helpers.compare_path_string,
+                    self.builder.func,
+                );
+                let call = self.builder.ins().call(
+                    func_ref,
+                    &[self.runtime_ptr, path_ptr, path_len, str_ptr, str_len],
+                );
+                let ordering = self.builder.inst_results(call)[0];
+                if matches!(left, IrExpr::String(_)) {
+                    self.builder.ins().fneg(ordering)
+                } else {
+                    ordering
+                }
+            }
+            _ => return self.emit_comparison(cond, left, right),
+        };
+
+        let left_val = self.translate_comparable(left)?;
+        let right_val = self.translate_comparable(right)?;
+        let numeric = self.builder.ins().fcmp(cond, left_val, right_val);
+        let zero = self.const_f64(0.0);
+        let lexical = self.builder.ins().fcmp(cond, ordering, zero);
+        let is_string = self.builder.ins().fcmp(FloatCC::Ordered, ordering, ordering);
+        let cmp = self.builder.ins().select(is_string, lexical, numeric);
+        Ok(self.float_from_bool(cmp))
+    }
+
+    /// Numeric value of a comparison operand; string literals count as `0` like
+    /// `Value::as_number`.
+    fn translate_comparable(&mut self, expr: &IrExpr) -> Result<Value, JitError> {
+        match expr {
+            IrExpr::String(_) => Ok(self.const_f64(0.0)),
+            _ => self.translate(expr),
+        }
+    }
+
+    /// Emits `text` as module data, returning its pointer and length.
+    fn string_data(&mut self, text: &str) -> Result<(Value, Value), JitError> {
+        let data_id = self
+            .module
+            .declare_anonymous_data(false, false)
+            .map_err(cranelift_module::ModuleError::from)?;
+        let mut data_desc = cranelift_module::DataDescription::new();
+        data_desc.define(text.as_bytes().to_vec().into_boxed_slice());
+        self.module
+            .define_data(data_id, &data_desc)
+            .map_err(cranelift_module::ModuleError::from)?;
+        let global_value = self
+            .module
+            .declare_data_in_func(data_id, self.builder.func);
+        let ptr = self.builder.ins().global_value(self.pointer_type, global_value);
+        let len = self.builder.ins().iconst(self.pointer_type, text.len() as i64);
+        Ok((ptr, len))
+    }
+
     fn emit_value_equality(
         &mut self,
         left: &IrExpr,
@@ -911,14 +1539,31 @@ impl<'a, 'b> Translator<'a, 'b> {
                 };
                 Ok(self.const_f64(result))
             }
-            // Numeric or other: fall back to float comparison
+            // Numeric or other: equal within NUMBER_EPSILON, like `Value` equality
             _ => {
-                let cond = if is_equal {
-                    FloatCC::Equal
+                let (left_val, right_val) = self.translate_pair(left, right)?;
+                let diff = self.builder.ins().fsub(left_val, right_val);
+                let distance = self.builder.ins().fabs(diff);
+                let epsilon = self.const_f64(NUMBER_EPSILON);
+                // The exact check keeps equal infinities equal (their difference is NaN)
+                let cmp = if is_equal {
+                    let exact = self.builder.ins().fcmp(FloatCC::Equal, left_val, right_val);
+                    let close = self.builder.ins().fcmp(
+                        FloatCC::LessThanOrEqual,
+                        distance,
+                        epsilon,
+                    );
+                    self.builder.ins().bor(exact, close)
                 } else {
-                    FloatCC::NotEqual
+                    let inexact = self.builder.ins().fcmp(FloatCC::NotEqual, left_val, right_val);
+                    let far = self.builder.ins().fcmp(
+                        FloatCC::UnorderedOrGreaterThan,
+                        distance,
+                        epsilon,
+                    );
+                    self.builder.ins().band(inexact, far)
                 };
-                self.emit_comparison(cond, left, right)
+                Ok(self.float_from_bool(cmp))
             }
         }
     }
@@ -981,9 +1626,35 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(result_param)
     }
 
+    /// `left ?? right` only falls through to `right` when `left` is a missing or null
+    /// variable; `0`, `''` and other falsy values are kept. Numbers computed in compiled
+    /// code are never null, so a non-path `left` is returned without evaluating `right`.
     fn emit_null_coalesce(&mut self, left: &IrExpr, right: &IrExpr) -> Result<Value, JitError> {
-        let left_val = self.translate(left)?;
-        let condition = self.bool_from_value(left_val);
+        // An element read is present when its index is in range (before wrapping); the
+        // index is evaluated once for both the test and the read
+        let mut element = None;
+        let present = match left {
+            IrExpr::Path(parts) => self.load_number_with(parts, self.runtime_helpers.is_present)?,
+            IrExpr::Index { target, index } => match (target.as_ref(), index.as_ref()) {
+                (IrExpr::Path(_), IrExpr::Path(field)) if field.len() == 1 && field[0] == "length" =>
+                {
+                    return self.translate(left);
+                }
+                (IrExpr::Path(array), index) => {
+                    let array_slot = self.ensure_slot_from_parts(array);
+                    let index_value = self.translate(index)?;
+                    element = Some((array_slot, index_value));
+                    self.call_array_helper(
+                        self.runtime_helpers.array_has_element,
+                        array_slot,
+                        index_value,
+                    )
+                }
+                _ => return self.translate(left),
+            },
+            _ => return self.translate(left),
+        };
+        let condition = self.bool_from_value(present);
         let then_block = self.builder.create_block();
         let else_block = self.builder.create_block();
         let merge_block = self.builder.create_block();
@@ -994,6 +1665,11 @@ impl<'a, 'b> Translator<'a, 'b> {
             .brif(condition, then_block, &[], else_block, &[]);
 
         self.builder.switch_to_block(then_block);
+        let left_val = match (element, left) {
+            (Some((array_slot, index_value)), _) => self.emit_array_get(array_slot, index_value),
+            (None, IrExpr::Path(parts)) => self.peek_variable(parts)?,
+            (None, _) => unreachable!("non-path operands return early"),
+        };
         self.builder.ins().jump(merge_block, &[left_val]);
         self.builder.seal_block(then_block);
 
@@ -1007,6 +1683,48 @@ impl<'a, 'b> Translator<'a, 'b> {
         Ok(result_param)
     }
 
+    /// `match`: a subject other than a plain path or literal is evaluated once into a
+    /// scratch slot, then the cases compile to a `?:` chain comparing against it.
+    fn emit_match(
+        &mut self,
+        subject: &IrExpr,
+        cases: &[(IrExpr, IrExpr)],
+        default: Option<&IrExpr>,
+    ) -> Result<Value, JitError> {
+        let (subject, scratch) = match subject {
+            IrExpr::Path(_) | IrExpr::Constant(_) | IrExpr::String(_) => (subject.clone(), None),
+            other => {
+                let scratch = vec![
+                    "temp".to_string(),
+                    format!("__match_{}", self.slot_names.len()),
+                ];
+                let slot = self.ensure_slot_from_parts(&scratch);
+                self.assign_expression(&scratch, other)?;
+                (IrExpr::Path(scratch), Some(slot))
+            }
+        };
+        let chain = cases
+            .iter()
+            .rev()
+            .fold(default.cloned(), |otherwise, (label, result)| {
+                Some(IrExpr::Conditional {
+                    condition: Box::new(IrExpr::Binary {
+                        op: BinaryOp::Equal,
+                        left: Box::new(subject.clone()),
+                        right: Box::new(label.clone()),
+                    }),
+                    then_branch: Box::new(result.clone()),
+                    else_branch: otherwise.map(Box::new),
+                })
+            })
+            .unwrap_or(IrExpr::Constant(0.0));
+        let value = self.translate(&chain)?;
+        if let Some(slot) = scratch {
+            self.clear_slot(slot);
+        }
+        Ok(value)
+    }
+
     fn emit_conditional(
         &mut self,
         condition: &IrExpr,
@@ -1053,6 +1771,18 @@ impl<'a, 'b> Translator<'a, 'b> {
         self.builder.ins().fcvt_from_sint(types::F64, int_value)
     }
 
+    /// `base` raised to a small constant exponent as repeated `fmul`s instead of `powf`.
+    fn emit_integer_power(&mut self, base: Value, exponent: u32) -> Value {
+        if exponent == 0 {
+            return self.const_f64(1.0);
+        }
+        let mut result = base;
+        for _ in 1..exponent {
+            result = self.builder.ins().fmul(result, base);
+        }
+        result
+    }
+
     fn const_f64(&mut self, value: f64) -> Value {
         self.builder.ins().f64const(Ieee64::with_float(value))
     }
@@ -1091,273 +1821,149 @@ impl<'a, 'b> Translator<'a, 'b> {
     }
 }
 
+/// Every math builtin's symbol name and native implementation, in registration order.
+const BUILTIN_SYMBOLS: &[(&str, *const u8)] = &[
+    ("builtin_math_cos", builtins::builtin_math_cos as *const u8),
+    ("builtin_math_sin", builtins::builtin_math_sin as *const u8),
+    ("builtin_math_abs", builtins::builtin_math_abs as *const u8),
+    ("builtin_math_random", builtins::builtin_math_random as *const u8),
+    ("builtin_math_random_integer", builtins::builtin_math_random_integer as *const u8),
+    ("builtin_math_clamp", builtins::builtin_math_clamp as *const u8),
+    ("builtin_math_wrap", builtins::builtin_math_wrap as *const u8),
+    ("builtin_math_sqrt", builtins::builtin_math_sqrt as *const u8),
+    ("builtin_math_cbrt", builtins::builtin_math_cbrt as *const u8),
+    ("builtin_math_floor", builtins::builtin_math_floor as *const u8),
+    ("builtin_math_ceil", builtins::builtin_math_ceil as *const u8),
+    ("builtin_math_round", builtins::builtin_math_round as *const u8),
+    ("builtin_math_trunc", builtins::builtin_math_trunc as *const u8),
+    ("builtin_math_frac", builtins::builtin_math_frac as *const u8),
+    ("builtin_math_clamp01", builtins::builtin_math_clamp01 as *const u8),
+    ("builtin_math_acos", builtins::builtin_math_acos as *const u8),
+    ("builtin_math_asin", builtins::builtin_math_asin as *const u8),
+    ("builtin_math_atan", builtins::builtin_math_atan as *const u8),
+    ("builtin_math_atan2", builtins::builtin_math_atan2 as *const u8),
+    ("builtin_math_sinh", builtins::builtin_math_sinh as *const u8),
+    ("builtin_math_cosh", builtins::builtin_math_cosh as *const u8),
+    ("builtin_math_tanh", builtins::builtin_math_tanh as *const u8),
+    ("builtin_math_exp", builtins::builtin_math_exp as *const u8),
+    ("builtin_math_ln", builtins::builtin_math_ln as *const u8),
+    ("builtin_math_pow", builtins::builtin_math_pow as *const u8),
+    ("builtin_math_square", builtins::builtin_math_square as *const u8),
+    ("builtin_math_max", builtins::builtin_math_max as *const u8),
+    ("builtin_math_min", builtins::builtin_math_min as *const u8),
+    ("builtin_math_mod", builtins::builtin_math_mod as *const u8),
+    ("builtin_math_mod_euclid", builtins::builtin_math_mod_euclid as *const u8),
+    ("builtin_math_snap", builtins::builtin_math_snap as *const u8),
+    ("builtin_math_floor_to", builtins::builtin_math_floor_to as *const u8),
+    ("builtin_math_ceil_to", builtins::builtin_math_ceil_to as *const u8),
+    ("builtin_math_round_to", builtins::builtin_math_round_to as *const u8),
+    ("builtin_math_ratio", builtins::builtin_math_ratio as *const u8),
+    ("builtin_math_sign", builtins::builtin_math_sign as *const u8),
+    ("builtin_math_copy_sign", builtins::builtin_math_copy_sign as *const u8),
+    ("builtin_math_pi", builtins::builtin_math_pi as *const u8),
+    ("builtin_math_min_angle", builtins::builtin_math_min_angle as *const u8),
+    ("builtin_math_lerp", builtins::builtin_math_lerp as *const u8),
+    ("builtin_math_lerp_clamped", builtins::builtin_math_lerp_clamped as *const u8),
+    ("builtin_math_inverse_lerp", builtins::builtin_math_inverse_lerp as *const u8),
+    ("builtin_math_map_range", builtins::builtin_math_map_range as *const u8),
+    ("builtin_math_approx_equal", builtins::builtin_math_approx_equal as *const u8),
+    ("builtin_math_lerprotate", builtins::builtin_math_lerprotate as *const u8),
+    ("builtin_math_hermite_blend", builtins::builtin_math_hermite_blend as *const u8),
+    ("builtin_math_smoothstep", builtins::builtin_math_smoothstep as *const u8),
+    ("builtin_math_smootherstep", builtins::builtin_math_smootherstep as *const u8),
+    ("builtin_math_rgb", builtins::builtin_math_rgb as *const u8),
+    ("builtin_math_rgb_channel", builtins::builtin_math_rgb_channel as *const u8),
+    ("builtin_math_die_roll", builtins::builtin_math_die_roll as *const u8),
+    ("builtin_math_die_roll_integer", builtins::builtin_math_die_roll_integer as *const u8),
+    ("builtin_math_noise", builtins::builtin_math_noise as *const u8),
+    ("builtin_math_ease_in_quad", builtins::builtin_math_ease_in_quad as *const u8),
+    ("builtin_math_ease_out_quad", builtins::builtin_math_ease_out_quad as *const u8),
+    ("builtin_math_ease_in_out_quad", builtins::builtin_math_ease_in_out_quad as *const u8),
+    ("builtin_math_ease_in_cubic", builtins::builtin_math_ease_in_cubic as *const u8),
+    ("builtin_math_ease_out_cubic", builtins::builtin_math_ease_out_cubic as *const u8),
+    ("builtin_math_ease_in_out_cubic", builtins::builtin_math_ease_in_out_cubic as *const u8),
+    ("builtin_math_ease_in_quart", builtins::builtin_math_ease_in_quart as *const u8),
+    ("builtin_math_ease_out_quart", builtins::builtin_math_ease_out_quart as *const u8),
+    ("builtin_math_ease_in_out_quart", builtins::builtin_math_ease_in_out_quart as *const u8),
+    ("builtin_math_ease_in_quint", builtins::builtin_math_ease_in_quint as *const u8),
+    ("builtin_math_ease_out_quint", builtins::builtin_math_ease_out_quint as *const u8),
+    ("builtin_math_ease_in_out_quint", builtins::builtin_math_ease_in_out_quint as *const u8),
+    ("builtin_math_ease_in_sine", builtins::builtin_math_ease_in_sine as *const u8),
+    ("builtin_math_ease_out_sine", builtins::builtin_math_ease_out_sine as *const u8),
+    ("builtin_math_ease_in_out_sine", builtins::builtin_math_ease_in_out_sine as *const u8),
+    ("builtin_math_ease_in_expo", builtins::builtin_math_ease_in_expo as *const u8),
+    ("builtin_math_ease_out_expo", builtins::builtin_math_ease_out_expo as *const u8),
+    ("builtin_math_ease_in_out_expo", builtins::builtin_math_ease_in_out_expo as *const u8),
+    ("builtin_math_ease_in_circ", builtins::builtin_math_ease_in_circ as *const u8),
+    ("builtin_math_ease_out_circ", builtins::builtin_math_ease_out_circ as *const u8),
+    ("builtin_math_ease_in_out_circ", builtins::builtin_math_ease_in_out_circ as *const u8),
+    ("builtin_math_ease_in_back", builtins::builtin_math_ease_in_back as *const u8),
+    ("builtin_math_ease_out_back", builtins::builtin_math_ease_out_back as *const u8),
+    ("builtin_math_ease_in_out_back", builtins::builtin_math_ease_in_out_back as *const u8),
+    ("builtin_math_ease_in_elastic", builtins::builtin_math_ease_in_elastic as *const u8),
+    ("builtin_math_ease_out_elastic", builtins::builtin_math_ease_out_elastic as *const u8),
+    ("builtin_math_ease_in_out_elastic", builtins::builtin_math_ease_in_out_elastic as *const u8),
+    ("builtin_math_ease_in_bounce", builtins::builtin_math_ease_in_bounce as *const u8),
+    ("builtin_math_ease_out_bounce", builtins::builtin_math_ease_out_bounce as *const u8),
+    ("builtin_math_ease_in_out_bounce", builtins::builtin_math_ease_in_out_bounce as *const u8),
+];
+
+/// Symbol table for the math builtins, for embedders registering them with their own
+/// Cranelift `JITBuilder`. Names match `BuiltinFunction::symbol_name`.
+pub fn builtin_symbols() -> &'static [(&'static str, *const u8)] {
+    BUILTIN_SYMBOLS
+}
+
 fn register_builtin_symbols(builder: &mut JITBuilder) {
+    for (name, pointer) in builtin_symbols() {
+        builder.symbol(*name, *pointer);
+    }
+}
+
+fn register_runtime_symbols(builder: &mut JITBuilder) {
+    builder.symbol("molang_rt_get_number", molang_rt_get_number as *const u8);
+    builder.symbol("molang_rt_peek_number", molang_rt_peek_number as *const u8);
+    builder.symbol("molang_rt_is_present", molang_rt_is_present as *const u8);
+    builder.symbol("molang_rt_set_number", molang_rt_set_number as *const u8);
+    builder.symbol("molang_rt_clear_value", molang_rt_clear_value as *const u8);
     builder.symbol(
-        "builtin_math_cos",
-        builtins::builtin_math_cos as *const u8,
+        "molang_rt_capture_return",
+        molang_rt_capture_return as *const u8,
     );
+    builder.symbol("molang_rt_copy_value", molang_rt_copy_value as *const u8);
     builder.symbol(
-        "builtin_math_sin",
-        builtins::builtin_math_sin as *const u8,
+        "molang_rt_array_push_number",
+        molang_rt_array_push_number as *const u8,
     );
     builder.symbol(
-        "builtin_math_abs",
-        builtins::builtin_math_abs as *const u8,
+        "molang_rt_array_push_string",
+        molang_rt_array_push_string as *const u8,
     );
     builder.symbol(
-        "builtin_math_random",
-        builtins::builtin_math_random as *const u8,
+        "molang_rt_array_get_number",
+        molang_rt_array_get_number as *const u8,
     );
     builder.symbol(
-        "builtin_math_random_integer",
-        builtins::builtin_math_random_integer as *const u8,
+        "molang_rt_array_has_element",
+        molang_rt_array_has_element as *const u8,
     );
     builder.symbol(
-        "builtin_math_clamp",
-        builtins::builtin_math_clamp as *const u8,
+        "molang_rt_array_set_number",
+        molang_rt_array_set_number as *const u8,
     );
     builder.symbol(
-        "builtin_math_sqrt",
-        builtins::builtin_math_sqrt as *const u8,
+        "molang_rt_array_set_value",
+        molang_rt_array_set_value as *const u8,
     );
     builder.symbol(
-        "builtin_math_floor",
-        builtins::builtin_math_floor as *const u8,
+        "molang_rt_array_length",
+        molang_rt_array_length as *const u8,
     );
     builder.symbol(
-        "builtin_math_ceil",
-        builtins::builtin_math_ceil as *const u8,
+        "molang_rt_array_copy_element",
+        molang_rt_array_copy_element as *const u8,
     );
-    builder.symbol(
-        "builtin_math_round",
-        builtins::builtin_math_round as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_trunc",
-        builtins::builtin_math_trunc as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_acos",
-        builtins::builtin_math_acos as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_asin",
-        builtins::builtin_math_asin as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_atan",
-        builtins::builtin_math_atan as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_atan2",
-        builtins::builtin_math_atan2 as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_exp",
-        builtins::builtin_math_exp as *const u8,
-    );
-    builder.symbol("builtin_math_ln", builtins::builtin_math_ln as *const u8);
-    builder.symbol(
-        "builtin_math_pow",
-        builtins::builtin_math_pow as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_max",
-        builtins::builtin_math_max as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_min",
-        builtins::builtin_math_min as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_mod",
-        builtins::builtin_math_mod as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_sign",
-        builtins::builtin_math_sign as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_copy_sign",
-        builtins::builtin_math_copy_sign as *const u8,
-    );
-    builder.symbol("builtin_math_pi", builtins::builtin_math_pi as *const u8);
-    builder.symbol(
-        "builtin_math_min_angle",
-        builtins::builtin_math_min_angle as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_lerp",
-        builtins::builtin_math_lerp as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_inverse_lerp",
-        builtins::builtin_math_inverse_lerp as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_lerprotate",
-        builtins::builtin_math_lerprotate as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_hermite_blend",
-        builtins::builtin_math_hermite_blend as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_die_roll",
-        builtins::builtin_math_die_roll as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_die_roll_integer",
-        builtins::builtin_math_die_roll_integer as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_quad",
-        builtins::builtin_math_ease_in_quad as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_quad",
-        builtins::builtin_math_ease_out_quad as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_quad",
-        builtins::builtin_math_ease_in_out_quad as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_cubic",
-        builtins::builtin_math_ease_in_cubic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_cubic",
-        builtins::builtin_math_ease_out_cubic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_cubic",
-        builtins::builtin_math_ease_in_out_cubic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_quart",
-        builtins::builtin_math_ease_in_quart as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_quart",
-        builtins::builtin_math_ease_out_quart as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_quart",
-        builtins::builtin_math_ease_in_out_quart as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_quint",
-        builtins::builtin_math_ease_in_quint as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_quint",
-        builtins::builtin_math_ease_out_quint as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_quint",
-        builtins::builtin_math_ease_in_out_quint as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_sine",
-        builtins::builtin_math_ease_in_sine as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_sine",
-        builtins::builtin_math_ease_out_sine as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_sine",
-        builtins::builtin_math_ease_in_out_sine as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_expo",
-        builtins::builtin_math_ease_in_expo as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_expo",
-        builtins::builtin_math_ease_out_expo as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_expo",
-        builtins::builtin_math_ease_in_out_expo as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_circ",
-        builtins::builtin_math_ease_in_circ as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_circ",
-        builtins::builtin_math_ease_out_circ as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_circ",
-        builtins::builtin_math_ease_in_out_circ as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_back",
-        builtins::builtin_math_ease_in_back as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_back",
-        builtins::builtin_math_ease_out_back as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_back",
-        builtins::builtin_math_ease_in_out_back as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_elastic",
-        builtins::builtin_math_ease_in_elastic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_elastic",
-        builtins::builtin_math_ease_out_elastic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_elastic",
-        builtins::builtin_math_ease_in_out_elastic as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_bounce",
-        builtins::builtin_math_ease_in_bounce as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_out_bounce",
-        builtins::builtin_math_ease_out_bounce as *const u8,
-    );
-    builder.symbol(
-        "builtin_math_ease_in_out_bounce",
-        builtins::builtin_math_ease_in_out_bounce as *const u8,
-    );
-}
-
-fn register_runtime_symbols(builder: &mut JITBuilder) {
-    builder.symbol("molang_rt_get_number", molang_rt_get_number as *const u8);
-    builder.symbol("molang_rt_set_number", molang_rt_set_number as *const u8);
-    builder.symbol("molang_rt_clear_value", molang_rt_clear_value as *const u8);
-    builder.symbol("molang_rt_copy_value", molang_rt_copy_value as *const u8);
-    builder.symbol(
-        "molang_rt_array_push_number",
-        molang_rt_array_push_number as *const u8,
-    );
-    builder.symbol(
-        "molang_rt_array_push_string",
-        molang_rt_array_push_string as *const u8,
-    );
-    builder.symbol(
-        "molang_rt_array_get_number",
-        molang_rt_array_get_number as *const u8,
-    );
-    builder.symbol(
-        "molang_rt_array_length",
-        molang_rt_array_length as *const u8,
-    );
-    builder.symbol(
-        "molang_rt_array_copy_element",
-        molang_rt_array_copy_element as *const u8,
-    );
-    builder.symbol("molang_rt_set_string", molang_rt_set_string as *const u8);
+    builder.symbol("molang_rt_set_string", molang_rt_set_string as *const u8);
     builder.symbol(
         "molang_rt_equal_paths",
         molang_rt_equal_paths as *const u8,
@@ -1370,21 +1976,46 @@ fn register_runtime_symbols(builder: &mut JITBuilder) {
         "molang_rt_equal_path_string",
         molang_rt_equal_path_string as *const u8,
     );
+    builder.symbol("molang_rt_both_strings", molang_rt_both_strings as *const u8);
     builder.symbol(
         "molang_rt_not_equal_path_string",
         molang_rt_not_equal_path_string as *const u8,
     );
+    builder.symbol(
+        "molang_rt_compare_paths",
+        molang_rt_compare_paths as *const u8,
+    );
+    builder.symbol(
+        "molang_rt_compare_path_string",
+        molang_rt_compare_path_string as *const u8,
+    );
+    builder.symbol(
+        "molang_rt_broadcast_builtin",
+        molang_rt_broadcast_builtin as *const u8,
+    );
+    builder.symbol(
+        "molang_rt_call_value_function",
+        molang_rt_call_value_function as *const u8,
+    );
+    builder.symbol("molang_rt_call_host", molang_rt_call_host as *const u8);
+    builder.symbol("molang_rt_loop_limit", molang_rt_loop_limit as *const u8);
 }
 
 #[derive(Clone, Copy)]
 struct RuntimeHelpers {
     get_number: FuncId,
+    peek_number: FuncId,
+    is_present: FuncId,
     set_number: FuncId,
     clear_value: FuncId,
+    capture_return: FuncId,
     copy_value: FuncId,
     array_push_number: FuncId,
     array_push_string: FuncId,
     array_get_number: FuncId,
+    array_has_element: FuncId,
+    array_set_number: FuncId,
+    array_set_value: FuncId,
     array_length: FuncId,
     array_copy_element: FuncId,
     set_string: FuncId,
@@ -1392,6 +2023,13 @@ struct RuntimeHelpers {
     not_equal_paths: FuncId,
     equal_path_string: FuncId,
     not_equal_path_string: FuncId,
+    compare_paths: FuncId,
+    compare_path_string: FuncId,
+    both_strings: FuncId,
+    broadcast_builtin: FuncId,
+    call_value_function: FuncId,
+    call_host: FuncId,
+    loop_limit: FuncId,
 }
 
 impl RuntimeHelpers {
@@ -1403,6 +2041,9 @@ impl RuntimeHelpers {
         sig.params.push(AbiParam::new(pointer_type));
         sig.returns.push(AbiParam::new(types::F64));
         let get_number = module.declare_function("molang_rt_get_number", Linkage::Import, &sig)?;
+        let peek_number =
+            module.declare_function("molang_rt_peek_number", Linkage::Import, &sig)?;
+        let is_present = module.declare_function("molang_rt_is_present", Linkage::Import, &sig)?;
 
         let mut set_sig = module.make_signature();
         set_sig.params.push(AbiParam::new(pointer_type));
@@ -1418,6 +2059,8 @@ impl RuntimeHelpers {
         clear_sig.params.push(AbiParam::new(pointer_type));
         let clear_value =
             module.declare_function("molang_rt_clear_value", Linkage::Import, &clear_sig)?;
+        let capture_return =
+            module.declare_function("molang_rt_capture_return", Linkage::Import, &clear_sig)?;
 
         let mut copy_sig = module.make_signature();
         copy_sig.params.push(AbiParam::new(pointer_type));
@@ -1462,6 +2105,36 @@ impl RuntimeHelpers {
             Linkage::Import,
             &array_get_sig,
         )?;
+        let array_has_element = module.declare_function(
+            "molang_rt_array_has_element",
+            Linkage::Import,
+            &array_get_sig,
+        )?;
+
+        let mut array_set_sig = module.make_signature();
+        array_set_sig.params.push(AbiParam::new(pointer_type));
+        array_set_sig.params.push(AbiParam::new(pointer_type));
+        array_set_sig.params.push(AbiParam::new(pointer_type));
+        array_set_sig.params.push(AbiParam::new(types::F64));
+        array_set_sig.params.push(AbiParam::new(types::F64));
+        let array_set_number = module.declare_function(
+            "molang_rt_array_set_number",
+            Linkage::Import,
+            &array_set_sig,
+        )?;
+
+        let mut array_set_value_sig = module.make_signature();
+        array_set_value_sig.params.push(AbiParam::new(pointer_type));
+        array_set_value_sig.params.push(AbiParam::new(pointer_type));
+        array_set_value_sig.params.push(AbiParam::new(pointer_type));
+        array_set_value_sig.params.push(AbiParam::new(types::F64));
+        array_set_value_sig.params.push(AbiParam::new(pointer_type));
+        array_set_value_sig.params.push(AbiParam::new(pointer_type));
+        let array_set_value = module.declare_function(
+            "molang_rt_array_set_value",
+            Linkage::Import,
+            &array_set_value_sig,
+        )?;
 
         let mut array_len_sig = module.make_signature();
         array_len_sig.params.push(AbiParam::new(pointer_type));
@@ -1521,14 +2194,82 @@ impl RuntimeHelpers {
             &equal_paths_sig,
         )?;
 
+        let compare_paths = module.declare_function(
+            "molang_rt_compare_paths",
+            Linkage::Import,
+            &equal_paths_sig,
+        )?;
+
+        let compare_path_string = module.declare_function(
+            "molang_rt_compare_path_string",
+            Linkage::Import,
+            &equal_paths_sig,
+        )?;
+
+        let both_strings =
+            module.declare_function("molang_rt_both_strings", Linkage::Import, &equal_paths_sig)?;
+
+        let mut broadcast_sig = module.make_signature();
+        broadcast_sig.params.push(AbiParam::new(pointer_type));
+        broadcast_sig.params.push(AbiParam::new(pointer_type));
+        broadcast_sig.params.push(AbiParam::new(pointer_type));
+        broadcast_sig.params.push(AbiParam::new(pointer_type));
+        broadcast_sig.params.push(AbiParam::new(pointer_type));
+        broadcast_sig.params.push(AbiParam::new(types::I64));
+        broadcast_sig.params.push(AbiParam::new(types::F64));
+        broadcast_sig.params.push(AbiParam::new(types::F64));
+        let broadcast_builtin = module.declare_function(
+            "molang_rt_broadcast_builtin",
+            Linkage::Import,
+            &broadcast_sig,
+        )?;
+
+        let mut value_call_sig = module.make_signature();
+        value_call_sig.params.push(AbiParam::new(pointer_type));
+        value_call_sig.params.push(AbiParam::new(types::I64));
+        for _ in 0..=ValueFunction::MAX_ARITY {
+            value_call_sig.params.push(AbiParam::new(pointer_type));
+            value_call_sig.params.push(AbiParam::new(pointer_type));
+        }
+        value_call_sig.returns.push(AbiParam::new(types::F64));
+        let call_value_function = module.declare_function(
+            "molang_rt_call_value_function",
+            Linkage::Import,
+            &value_call_sig,
+        )?;
+
+        let mut host_sig = module.make_signature();
+        for _ in 0..HOST_CALL_POINTER_PARAMS {
+            host_sig.params.push(AbiParam::new(pointer_type));
+        }
+        host_sig.params.push(AbiParam::new(types::I64));
+        for _ in 0..HOST_FUNCTION_MAX_ARGS {
+            host_sig.params.push(AbiParam::new(types::F64));
+        }
+        host_sig.returns.push(AbiParam::new(types::F64));
+        let call_host =
+            module.declare_function("molang_rt_call_host", Linkage::Import, &host_sig)?;
+
+        let mut limit_sig = module.make_signature();
+        limit_sig.params.push(AbiParam::new(pointer_type));
+        limit_sig.returns.push(AbiParam::new(types::F64));
+        let loop_limit =
+            module.declare_function("molang_rt_loop_limit", Linkage::Import, &limit_sig)?;
+
         Ok(RuntimeHelpers {
             get_number,
+            peek_number,
+            is_present,
             set_number,
             clear_value,
+            capture_return,
             copy_value,
             array_push_number,
             array_push_string,
             array_get_number,
+            array_has_element,
+            array_set_number,
+            array_set_value,
             array_length,
             array_copy_element,
             set_string,
@@ -1536,10 +2277,27 @@ impl RuntimeHelpers {
             not_equal_paths,
             equal_path_string,
             not_equal_path_string,
+            compare_paths,
+            compare_path_string,
+            both_strings,
+            broadcast_builtin,
+            call_value_function,
+            call_host,
+            loop_limit,
         })
     }
 }
 
+/// Iteration cap for `loop`, read from the context on every loop entry.
+#[no_mangle]
+pub extern "C" fn molang_rt_loop_limit(ctx: *mut RuntimeContext) -> f64 {
+    if ctx.is_null() {
+        return DEFAULT_MAX_LOOP_ITERATIONS as f64;
+    }
+    let runtime = unsafe { &*ctx };
+    runtime.max_loop_iterations() as f64
+}
+
 #[no_mangle]
 pub extern "C" fn molang_rt_get_number(
     ctx: *mut RuntimeContext,
@@ -1555,7 +2313,47 @@ pub extern "C" fn molang_rt_get_number(
         Err(_) => return 0.0,
     };
     let runtime = unsafe { &mut *ctx };
-    runtime.get_number_canonical(canonical).unwrap_or(0.0)
+    runtime.get_number_canonical(canonical).unwrap_or_else(|| {
+        runtime.note_missing_read(canonical);
+        0.0
+    })
+}
+
+/// Like `molang_rt_get_number`, but a missing variable is never reported in strict mode.
+#[no_mangle]
+pub extern "C" fn molang_rt_peek_number(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let runtime = unsafe { &*ctx };
+    slot_name(name_ptr, len)
+        .and_then(|canonical| runtime.get_number_canonical(canonical))
+        .unwrap_or(0.0)
+}
+
+/// `1.0` when the variable holds a value other than `null`, `0.0` when it is missing or null.
+#[no_mangle]
+pub extern "C" fn molang_rt_is_present(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let runtime = unsafe { &*ctx };
+    let present = slot_name(name_ptr, len)
+        .and_then(|canonical| runtime.get_value_canonical(canonical))
+        .is_some_and(|value| !matches!(value, RuntimeValue::Null));
+    if present {
+        1.0
+    } else {
+        0.0
+    }
 }
 
 #[no_mangle]
@@ -1589,6 +2387,22 @@ pub extern "C" fn molang_rt_clear_value(ctx: *mut RuntimeContext, name_ptr: *con
     }
 }
 
+#[no_mangle]
+pub extern "C" fn molang_rt_capture_return(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+) {
+    if ctx.is_null() || name_ptr.is_null() {
+        return;
+    }
+    let bytes = unsafe { slice::from_raw_parts(name_ptr, len) };
+    if let Ok(canonical) = str::from_utf8(bytes) {
+        let runtime = unsafe { &mut *ctx };
+        runtime.capture_return_canonical(canonical);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn molang_rt_copy_value(
     ctx: *mut RuntimeContext,
@@ -1657,11 +2471,74 @@ pub extern "C" fn molang_rt_array_get_number(
     let bytes = unsafe { slice::from_raw_parts(name_ptr, len) };
     if let Ok(canonical) = str::from_utf8(bytes) {
         let runtime = unsafe { &mut *ctx };
-        return runtime.array_get_number_canonical(canonical, index);
+        return runtime.read_element_number_canonical(canonical, index);
     }
     0.0
 }
 
+/// `1` when `index` names a non-null element of the array at the given path without
+/// wrapping, for `??` on element reads.
+#[no_mangle]
+pub extern "C" fn molang_rt_array_has_element(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+    index: f64,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let runtime = unsafe { &*ctx };
+    let present = slot_name(name_ptr, len)
+        .is_some_and(|canonical| runtime.array_has_element_canonical(canonical, index));
+    if present {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn molang_rt_array_set_number(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+    index: f64,
+    value: f64,
+) {
+    if ctx.is_null() || name_ptr.is_null() {
+        return;
+    }
+    let bytes = unsafe { slice::from_raw_parts(name_ptr, len) };
+    if let Ok(canonical) = str::from_utf8(bytes) {
+        let runtime = unsafe { &mut *ctx };
+        runtime.array_set_value_canonical(canonical, index, RuntimeValue::number(value));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn molang_rt_array_set_value(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    len: usize,
+    index: f64,
+    src_ptr: *const u8,
+    src_len: usize,
+) {
+    if ctx.is_null() || name_ptr.is_null() || src_ptr.is_null() {
+        return;
+    }
+    let name_bytes = unsafe { slice::from_raw_parts(name_ptr, len) };
+    let src_bytes = unsafe { slice::from_raw_parts(src_ptr, src_len) };
+    if let (Ok(canonical), Ok(src)) = (str::from_utf8(name_bytes), str::from_utf8(src_bytes)) {
+        let runtime = unsafe { &mut *ctx };
+        let value = runtime
+            .read_value_canonical(src)
+            .unwrap_or_else(|| RuntimeValue::number(0.0));
+        runtime.array_set_value_canonical(canonical, index, value);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn molang_rt_array_length(
     ctx: *mut RuntimeContext,
@@ -1734,20 +2611,11 @@ pub extern "C" fn molang_rt_equal_paths(
     let right_bytes = unsafe { slice::from_raw_parts(right_ptr, right_len) };
     if let (Ok(left_name), Ok(right_name)) = (str::from_utf8(left_bytes), str::from_utf8(right_bytes))
     {
-        let runtime = unsafe { &*ctx };
-        let left_val = runtime.get_value_canonical(left_name);
-        let right_val = runtime.get_value_canonical(right_name);
+        let runtime = unsafe { &mut *ctx };
+        let left_val = runtime.read_value_canonical(left_name);
+        let right_val = runtime.read_value_canonical(right_name);
 
-        match (left_val, right_val) {
-            (Some(RuntimeValue::String(l)), Some(RuntimeValue::String(r))) => {
-                if l == r { 1.0 } else { 0.0 }
-            }
-            (Some(RuntimeValue::Number(l)), Some(RuntimeValue::Number(r))) => {
-                if l == r { 1.0 } else { 0.0 }
-            }
-            (None, None) => 1.0,
-            _ => 0.0,
-        }
+        if left_val == right_val { 1.0 } else { 0.0 }
     } else {
         0.0
     }
@@ -1782,8 +2650,8 @@ pub extern "C" fn molang_rt_equal_path_string(
     let path_bytes = unsafe { slice::from_raw_parts(path_ptr, path_len) };
     let str_bytes = unsafe { slice::from_raw_parts(str_ptr, str_len) };
     if let (Ok(path_name), Ok(str_val)) = (str::from_utf8(path_bytes), str::from_utf8(str_bytes)) {
-        let runtime = unsafe { &*ctx };
-        if let Some(RuntimeValue::String(s)) = runtime.get_value_canonical(path_name) {
+        let runtime = unsafe { &mut *ctx };
+        if let Some(RuntimeValue::String(s)) = runtime.read_value_canonical(path_name) {
             if s == str_val { 1.0 } else { 0.0 }
         } else {
             0.0
@@ -1808,6 +2676,267 @@ pub extern "C" fn molang_rt_not_equal_path_string(
     }
 }
 
+/// `1` when both paths hold strings, so `+` concatenates instead of adding. Borrows the
+/// stored values rather than cloning them.
+#[no_mangle]
+pub extern "C" fn molang_rt_both_strings(
+    ctx: *mut RuntimeContext,
+    left_ptr: *const u8,
+    left_len: usize,
+    right_ptr: *const u8,
+    right_len: usize,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let runtime = unsafe { &*ctx };
+    let holds_string = |ptr, len| {
+        slot_name(ptr, len).is_some_and(|canonical| runtime.holds_string_canonical(canonical))
+    };
+    if holds_string(left_ptr, left_len) && holds_string(right_ptr, right_len) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Lexicographic ordering of two string values as `-1`/`0`/`1`, or NaN unless both are
+/// strings so the caller falls back to a numeric comparison.
+fn string_ordering(left: Option<RuntimeValue>, right: &str) -> f64 {
+    match left {
+        Some(RuntimeValue::String(left)) => left.as_str().cmp(right) as i8 as f64,
+        _ => f64::NAN,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn molang_rt_compare_paths(
+    ctx: *mut RuntimeContext,
+    left_ptr: *const u8,
+    left_len: usize,
+    right_ptr: *const u8,
+    right_len: usize,
+) -> f64 {
+    if ctx.is_null() || left_ptr.is_null() || right_ptr.is_null() {
+        return f64::NAN;
+    }
+    let left_bytes = unsafe { slice::from_raw_parts(left_ptr, left_len) };
+    let right_bytes = unsafe { slice::from_raw_parts(right_ptr, right_len) };
+    if let (Ok(left_name), Ok(right_name)) = (str::from_utf8(left_bytes), str::from_utf8(right_bytes))
+    {
+        let runtime = unsafe { &mut *ctx };
+        match runtime.read_value_canonical(right_name) {
+            Some(RuntimeValue::String(right)) => {
+                string_ordering(runtime.read_value_canonical(left_name), &right)
+            }
+            _ => f64::NAN,
+        }
+    } else {
+        f64::NAN
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn molang_rt_compare_path_string(
+    ctx: *mut RuntimeContext,
+    path_ptr: *const u8,
+    path_len: usize,
+    str_ptr: *const u8,
+    str_len: usize,
+) -> f64 {
+    if ctx.is_null() || path_ptr.is_null() || str_ptr.is_null() {
+        return f64::NAN;
+    }
+    let path_bytes = unsafe { slice::from_raw_parts(path_ptr, path_len) };
+    let str_bytes = unsafe { slice::from_raw_parts(str_ptr, str_len) };
+    if let (Ok(path_name), Ok(str_val)) = (str::from_utf8(path_bytes), str::from_utf8(str_bytes)) {
+        let runtime = unsafe { &mut *ctx };
+        string_ordering(runtime.read_value_canonical(path_name), str_val)
+    } else {
+        f64::NAN
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn molang_rt_broadcast_builtin(
+    ctx: *mut RuntimeContext,
+    dest_ptr: *const u8,
+    dest_len: usize,
+    src_ptr: *const u8,
+    src_len: usize,
+    code: i64,
+    arg1: f64,
+    arg2: f64,
+) {
+    if ctx.is_null() || dest_ptr.is_null() || src_ptr.is_null() {
+        return;
+    }
+    let Some(builtin) = usize::try_from(code)
+        .ok()
+        .and_then(|index| BROADCAST_BUILTINS.get(index))
+    else {
+        return;
+    };
+    let dest_bytes = unsafe { slice::from_raw_parts(dest_ptr, dest_len) };
+    let src_bytes = unsafe { slice::from_raw_parts(src_ptr, src_len) };
+    if let (Ok(dest), Ok(src)) = (str::from_utf8(dest_bytes), str::from_utf8(src_bytes)) {
+        let runtime = unsafe { &mut *ctx };
+        let apply = |value: f64| builtin.evaluate(&[value, arg1, arg2]);
+        let result = match runtime.read_value_canonical(src) {
+            Some(RuntimeValue::Array(values)) => RuntimeValue::Array(
+                values
+                    .iter()
+                    .map(|value| RuntimeValue::number(apply(value.as_number())))
+                    .collect(),
+            ),
+            other => RuntimeValue::number(apply(
+                other.map(|value| value.as_number()).unwrap_or(0.0),
+            )),
+        };
+        runtime.set_value_canonical(dest, result);
+    }
+}
+
+/// Dispatches a `ValueFunction` by code. Each argument arrives as a slot name (null when
+/// unused) and is read as a full runtime value; missing variables become `Null`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn molang_rt_call_value_function(
+    ctx: *mut RuntimeContext,
+    code: i64,
+    dest_ptr: *const u8,
+    dest_len: usize,
+    a_ptr: *const u8,
+    a_len: usize,
+    b_ptr: *const u8,
+    b_len: usize,
+    c_ptr: *const u8,
+    c_len: usize,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let Some(function) = ValueFunction::from_code(code) else {
+        return 0.0;
+    };
+    let runtime = unsafe { &mut *ctx };
+    let args = [(a_ptr, a_len), (b_ptr, b_len), (c_ptr, c_len)]
+        .into_iter()
+        .take(function.arity())
+        .map(|(ptr, len)| {
+            slot_name(ptr, len)
+                .and_then(|name| runtime.read_value_canonical(name))
+                .unwrap_or(RuntimeValue::Null)
+        })
+        .collect::<Vec<_>>();
+    let result = function.evaluate(&args);
+    let number = result.as_number();
+    if let Some(dest) = slot_name(dest_ptr, dest_len) {
+        store_result(runtime, dest, result);
+    }
+    number
+}
+
+/// Leading pointer-sized parameters of `molang_rt_call_host`: `ctx`, `name_ptr`,
+/// `name_len`, `dest_ptr` and `dest_len`. The argument count and the
+/// `HOST_FUNCTION_MAX_ARGS` number arguments follow.
+const HOST_CALL_POINTER_PARAMS: usize = 5;
+
+/// Invokes the host function registered under the given canonical name, storing the full
+/// result into `dest` when one is given. Unregistered names (e.g. a cached expression run
+/// against another context) evaluate to `0`.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn molang_rt_call_host(
+    ctx: *mut RuntimeContext,
+    name_ptr: *const u8,
+    name_len: usize,
+    dest_ptr: *const u8,
+    dest_len: usize,
+    argc: i64,
+    a0: f64,
+    a1: f64,
+    a2: f64,
+    a3: f64,
+) -> f64 {
+    if ctx.is_null() {
+        return 0.0;
+    }
+    let Some(name) = slot_name(name_ptr, name_len) else {
+        return 0.0;
+    };
+    let runtime = unsafe { &mut *ctx };
+    let args = [a0, a1, a2, a3];
+    let count = usize::try_from(argc).unwrap_or_default().min(args.len());
+    let result = runtime
+        .call_function_canonical(name, &args[..count])
+        .unwrap_or_else(|| RuntimeValue::number(0.0));
+    let number = result.as_number();
+    if let Some(dest) = slot_name(dest_ptr, dest_len) {
+        store_result(runtime, dest, result);
+    }
+    number
+}
+
+/// Stores a helper's full result into `dest`, first dropping the flattened field entries
+/// of the old value (e.g. after `struct.remove`, or a host struct with fewer fields). Only
+/// structs have them, so other results skip the scan over every stored entry.
+fn store_result(runtime: &mut RuntimeContext, dest: &str, result: RuntimeValue) {
+    if matches!(result, RuntimeValue::Struct(_)) || runtime.get_struct(dest).is_some() {
+        runtime.clear_value_canonical(dest);
+    }
+    runtime.set_value_canonical(dest, result);
+}
+
+/// Largest constant exponent `math.pow` unrolls into multiplications.
+const MAX_UNROLLED_POW: u32 = 8;
+
+/// True when `arg`, the first argument of a broadcast builtin, may yield an array: an array
+/// literal, a path, or another broadcast call over one.
+fn may_broadcast(arg: &IrExpr) -> bool {
+    match arg {
+        IrExpr::Array(_) | IrExpr::Path(_) => true,
+        IrExpr::Call {
+            function: FunctionRef::Builtin(builtin),
+            args,
+        } => builtin.broadcast_code().is_some() && args.first().is_some_and(may_broadcast),
+        _ => false,
+    }
+}
+
+/// Like [`may_broadcast`], but only for arrays known at compile time.
+fn has_array_literal(arg: &IrExpr) -> bool {
+    match arg {
+        IrExpr::Array(_) => true,
+        IrExpr::Call {
+            function: FunctionRef::Builtin(builtin),
+            args,
+        } => builtin.broadcast_code().is_some() && args.first().is_some_and(has_array_literal),
+        _ => false,
+    }
+}
+
+/// Exponent of a `math.pow` call when it is a constant integer in `0..=MAX_UNROLLED_POW`.
+fn small_integer_exponent(args: &[IrExpr]) -> Option<u32> {
+    match args.get(1) {
+        Some(IrExpr::Constant(value))
+            if value.fract() == 0.0 && (0.0..=MAX_UNROLLED_POW as f64).contains(value) =>
+        {
+            Some(*value as u32)
+        }
+        _ => None,
+    }
+}
+
+fn slot_name<'a>(ptr: *const u8, len: usize) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+    str::from_utf8(bytes).ok()
+}
+
 #[derive(Debug, Error)]
 pub enum JitError {
     #[error(transparent)]
@@ -1,33 +1,96 @@
-use crate::ir::IrExpr;
+use crate::ir::{IrExpr, IrProgram};
 use crate::jit::{self, CompiledExpression};
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+/// What a cache entry was compiled from, so the two kinds can be evicted separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Expression,
+    Program,
+}
+
 thread_local! {
-    static CACHE: RefCell<HashMap<String, Arc<CompiledExpression>>> =
+    static CACHE: RefCell<HashMap<String, (EntryKind, Arc<CompiledExpression>)>> =
         RefCell::new(HashMap::new());
 }
 
 /// Looks up or compiles a pure expression and stores it in a thread-local cache.
 pub fn compile_cached(key: &str, ir: &IrExpr) -> Result<Arc<CompiledExpression>, jit::JitError> {
-    if let Some(existing) = CACHE.with(|cache| cache.borrow().get(key).cloned()) {
+    cached(key, EntryKind::Expression, || jit::compile_expression(ir))
+}
+
+/// Program counterpart of [`compile_cached`]. `evaluate_*` never caches programs, so
+/// this is for hosts that lower and compile a statement program themselves.
+pub fn compile_program_cached(
+    key: &str,
+    program: &IrProgram,
+) -> Result<Arc<CompiledExpression>, jit::JitError> {
+    cached(key, EntryKind::Program, || jit::compile_program(program))
+}
+
+fn cached(
+    key: &str,
+    kind: EntryKind,
+    compile: impl FnOnce() -> Result<CompiledExpression, jit::JitError>,
+) -> Result<Arc<CompiledExpression>, jit::JitError> {
+    let existing = CACHE.with(|cache| {
+        cache
+            .borrow()
+            .get(key)
+            .filter(|(entry_kind, _)| *entry_kind == kind)
+            .map(|(_, compiled)| compiled.clone())
+    });
+    if let Some(existing) = existing {
         return Ok(existing);
     }
 
-    let compiled = Arc::new(jit::compile_expression(ir)?);
+    let compiled = Arc::new(compile()?);
     CACHE.with(|cache| {
-        cache.borrow_mut().insert(key.to_string(), compiled.clone());
+        cache
+            .borrow_mut()
+            .insert(key.to_string(), (kind, compiled.clone()));
     });
     Ok(compiled)
 }
 
+/// Approximate bytes held by this thread's cache: each entry's compiled code, slot names
+/// and source key. Useful for tuning how many scripts a long-running host keeps cached.
+pub fn estimated_memory() -> usize {
+    CACHE.with(|cache| {
+        cache
+            .borrow()
+            .iter()
+            .map(|(key, (_, compiled))| key.len() + compiled.estimated_size())
+            .sum()
+    })
+}
+
 #[cfg(test)]
 pub fn cache_size() -> usize {
     CACHE.with(|cache| cache.borrow().len())
 }
 
-#[cfg(test)]
+/// Drops every cached entry on this thread, expressions and programs alike.
 pub fn clear_cache() {
     CACHE.with(|cache| cache.borrow_mut().clear());
 }
+
+/// Drops only cached programs, which tend to be the larger entries, keeping expressions.
+pub fn clear_programs() {
+    clear_kind(EntryKind::Program);
+}
+
+/// Drops only cached pure expressions, keeping programs.
+pub fn clear_expressions() {
+    clear_kind(EntryKind::Expression);
+}
+
+fn clear_kind(kind: EntryKind) {
+    CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .retain(|_, (entry_kind, _)| *entry_kind != kind)
+    });
+}
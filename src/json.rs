@@ -0,0 +1,75 @@
+//! Optional JSON conversions for runtime values and contexts, enabled with the `json`
+//! feature.
+//!
+//! Object keys are lowercased on the way in, since Molang paths are case-insensitive and
+//! stored lowercase; `{"Speed": 2}` is read back as `query.speed`.
+
+use crate::eval::{Namespace, RuntimeContext, Value, MAX_EXACT_INTEGER};
+use serde_json::{Map, Number, Value as JsonValue};
+
+impl Value {
+    /// Converts JSON into a Molang value: numbers, strings, arrays, objects (as structs)
+    /// and `null` map one to one; booleans become `1` or `0`.
+    pub fn from_json(json: &JsonValue) -> Value {
+        match json {
+            JsonValue::Null => Value::Null,
+            JsonValue::Bool(flag) => Value::number(if *flag { 1.0 } else { 0.0 }),
+            JsonValue::Number(number) => Value::number(number.as_f64().unwrap_or_default()),
+            JsonValue::String(text) => Value::string(text.as_str()),
+            JsonValue::Array(items) => Value::array(items.iter().map(Value::from_json).collect()),
+            JsonValue::Object(fields) => Value::Struct(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.to_ascii_lowercase(), Value::from_json(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Converts the value to JSON. Integral numbers are written as integers (`20`, not
+    /// `20.0`) so loaded configs export unchanged; non-finite numbers have no JSON form
+    /// and become `null`.
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            Value::Number(number) if number.fract() == 0.0 && number.abs() <= MAX_EXACT_INTEGER => {
+                JsonValue::from(*number as i64)
+            }
+            Value::Number(number) => {
+                Number::from_f64(*number).map_or(JsonValue::Null, JsonValue::Number)
+            }
+            Value::String(text) => JsonValue::String(text.clone()),
+            Value::Array(items) => JsonValue::Array(items.iter().map(Value::to_json).collect()),
+            Value::Struct(fields) => JsonValue::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.to_json()))
+                    .collect(),
+            ),
+            Value::Null => JsonValue::Null,
+        }
+    }
+}
+
+impl RuntimeContext {
+    /// Stores every field of a JSON object in `namespace`, e.g. a config object loaded into
+    /// `Namespace::Query` so scripts read `query.<field>`. Existing entries with the same
+    /// names are replaced.
+    pub fn load_json(&mut self, namespace: Namespace, object: &Map<String, JsonValue>) {
+        for (key, value) in object {
+            self.insert(namespace.clone(), key.as_str(), Value::from_json(value));
+        }
+    }
+
+    /// Collects the top-level entries of `namespace` into a JSON object; nested fields
+    /// appear inside their parent struct.
+    pub fn namespace_to_json(&self, namespace: Namespace) -> Map<String, JsonValue> {
+        let prefix = format!("{namespace}.");
+        self.list_variables()
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(&prefix)?;
+                (!key.contains('.')).then(|| (key.to_string(), value.to_json()))
+            })
+            .collect()
+    }
+}
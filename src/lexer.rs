@@ -6,12 +6,59 @@ pub struct Span {
     pub end: usize,
 }
 
+/// Maps byte offsets in the original input to 1-based `(line, column)` positions for
+/// editor tooling. Columns count characters, not bytes.
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    input: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(input: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(input.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        Self { input, line_starts }
+    }
+
+    /// Position of the byte `offset`; offsets past the end map to the end of the input.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let mut offset = offset.min(self.input.len());
+        while !self.input.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|start| *start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self.input[line_start..offset].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Positions of the first and last character covered by `span`.
+    pub fn span_positions(&self, span: Span) -> ((usize, usize), (usize, usize)) {
+        (self.position(span.start), self.position(span.end))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
     pub span: Span,
 }
 
+impl Token {
+    /// Source text of the token within `input`, the string it was lexed from. Token spans
+    /// include their `end` byte, so `==` at 2 spans `2..=3`; `EOF` has no text.
+    pub fn text<'a>(&self, input: &'a str) -> &'a str {
+        if matches!(self.kind, TokenKind::EOF) {
+            return "";
+        }
+        input
+            .get(self.span.start..=self.span.end)
+            .unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenKind {
     Number(f64),
@@ -21,6 +68,10 @@ pub enum TokenKind {
     Minus,
     Star,
     Slash,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
     Dot,
     Comma,
     LParen,
@@ -43,6 +94,9 @@ pub enum TokenKind {
     GreaterEqual,
     AndAnd,
     OrOr,
+    Amp,
+    Pipe,
+    Caret,
     Arrow,
     EOF,
 }
@@ -55,6 +109,25 @@ pub enum LexError {
     InvalidNumber { span: Span },
     #[error("unterminated string starting at {start}")]
     UnterminatedString { start: usize },
+    #[error("unterminated block comment starting at {start}")]
+    UnterminatedComment { start: usize },
+    #[error("unknown escape sequence `\\{ch}` at {index}")]
+    InvalidEscape { ch: char, index: usize },
+}
+
+impl LexError {
+    /// Byte offset in the input where the error was detected.
+    pub fn offset(&self) -> usize {
+        match self {
+            LexError::UnexpectedCharacter { index, .. } | LexError::InvalidEscape { index, .. } => {
+                *index
+            }
+            LexError::InvalidNumber { span } => span.start,
+            LexError::UnterminatedString { start } | LexError::UnterminatedComment { start } => {
+                *start
+            }
+        }
+    }
 }
 
 pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
@@ -92,17 +165,48 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
             continue;
         }
 
+        if ch == '/' {
+            if matches_next_char(&mut chars, '/') {
+                skip_line_comment(&mut chars);
+                continue;
+            }
+            if matches_next_char(&mut chars, '*') {
+                skip_block_comment(idx, &mut chars)?;
+                continue;
+            }
+        }
+
         let token = match ch {
-            '+' => token(TokenKind::Plus, idx, idx),
+            '+' => {
+                if matches_next_char(&mut chars, '=') {
+                    token(TokenKind::PlusEqual, idx, idx + 1)
+                } else {
+                    token(TokenKind::Plus, idx, idx)
+                }
+            }
             '-' => {
                 if matches_next_char(&mut chars, '>') {
                     token(TokenKind::Arrow, idx, idx + 1)
+                } else if matches_next_char(&mut chars, '=') {
+                    token(TokenKind::MinusEqual, idx, idx + 1)
                 } else {
                     token(TokenKind::Minus, idx, idx)
                 }
             }
-            '*' => token(TokenKind::Star, idx, idx),
-            '/' => token(TokenKind::Slash, idx, idx),
+            '*' => {
+                if matches_next_char(&mut chars, '=') {
+                    token(TokenKind::StarEqual, idx, idx + 1)
+                } else {
+                    token(TokenKind::Star, idx, idx)
+                }
+            }
+            '/' => {
+                if matches_next_char(&mut chars, '=') {
+                    token(TokenKind::SlashEqual, idx, idx + 1)
+                } else {
+                    token(TokenKind::Slash, idx, idx)
+                }
+            }
             ',' => token(TokenKind::Comma, idx, idx),
             '(' => token(TokenKind::LParen, idx, idx),
             ')' => token(TokenKind::RParen, idx, idx),
@@ -151,16 +255,17 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
                 if matches_next_char(&mut chars, '&') {
                     token(TokenKind::AndAnd, idx, idx + 1)
                 } else {
-                    return Err(LexError::UnexpectedCharacter { ch, index: idx });
+                    token(TokenKind::Amp, idx, idx)
                 }
             }
             '|' => {
                 if matches_next_char(&mut chars, '|') {
                     token(TokenKind::OrOr, idx, idx + 1)
                 } else {
-                    return Err(LexError::UnexpectedCharacter { ch, index: idx });
+                    token(TokenKind::Pipe, idx, idx)
                 }
             }
+            '^' => token(TokenKind::Caret, idx, idx),
             _ => {
                 return Err(LexError::UnexpectedCharacter { ch, index: idx });
             }
@@ -179,6 +284,33 @@ pub fn lex(input: &str) -> Result<Vec<Token>, LexError> {
     Ok(tokens)
 }
 
+/// True when `input` holds nothing but whitespace and comments, such as a `// note` line.
+/// Input that fails to lex (an unterminated `/*`) is not blank, so the error still surfaces.
+pub fn is_blank(input: &str) -> bool {
+    lex(input).is_ok_and(|tokens| tokens.iter().all(|token| token.kind == TokenKind::EOF))
+}
+
+/// Skips the rest of a `//` comment, leaving the newline for the whitespace check.
+fn skip_line_comment<I>(chars: &mut std::iter::Peekable<I>)
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    while chars.next_if(|&(_, ch)| ch != '\n').is_some() {}
+}
+
+/// Skips a `/* ... */` comment whose opening delimiter starts at `start`.
+fn skip_block_comment<I>(start: usize, chars: &mut std::iter::Peekable<I>) -> Result<(), LexError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    while let Some((_, ch)) = chars.next() {
+        if ch == '*' && matches_next_char(chars, '/') {
+            return Ok(());
+        }
+    }
+    Err(LexError::UnterminatedComment { start })
+}
+
 fn read_number<I>(
     start_idx: usize,
     start_ch: char,
@@ -187,6 +319,10 @@ fn read_number<I>(
 where
     I: Iterator<Item = (usize, char)>,
 {
+    if start_ch == '0' && matches!(chars.peek(), Some(&(_, 'x' | 'X'))) {
+        return read_hex_number(start_idx, chars);
+    }
+
     let mut literal = String::new();
     literal.push(start_ch);
     let mut end_idx = start_idx;
@@ -225,6 +361,43 @@ where
     })
 }
 
+/// Reads the digits of a `0x` literal (the leading `0` is already consumed). Packed
+/// colours such as `0xFF00FF` come out as plain numbers.
+fn read_hex_number<I>(
+    start_idx: usize,
+    chars: &mut std::iter::Peekable<I>,
+) -> Result<Token, LexError>
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let mut end_idx = start_idx;
+    if let Some((idx, _)) = chars.next() {
+        end_idx = idx;
+    }
+
+    let mut digits = String::new();
+    while let Some(&(idx, ch)) = chars.peek() {
+        if ch.is_ascii_hexdigit() {
+            digits.push(ch);
+            end_idx = idx;
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    let span = Span {
+        start: start_idx,
+        end: end_idx,
+    };
+    let value = i64::from_str_radix(&digits, 16).map_err(|_| LexError::InvalidNumber { span })?;
+
+    Ok(Token {
+        kind: TokenKind::Number(value as f64),
+        span,
+    })
+}
+
 fn read_string<I>(
     start_idx: usize,
     quote: char,
@@ -246,7 +419,19 @@ where
             });
         } else if ch == '\\' {
             if let Some((_, next_ch)) = chars.next() {
-                literal.push(next_ch);
+                literal.push(match next_ch {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    '\\' | '\'' | '"' => next_ch,
+                    _ => {
+                        return Err(LexError::InvalidEscape {
+                            ch: next_ch,
+                            index: idx,
+                        })
+                    }
+                });
             }
         } else {
             literal.push(ch);
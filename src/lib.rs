@@ -1,16 +1,27 @@
 pub mod ast;
 pub mod builtins;
 pub mod eval;
+pub mod format;
 pub mod ir;
 pub mod jit;
 mod jit_cache;
+#[cfg(feature = "json")]
+pub mod json;
 pub mod lexer;
 pub mod parser;
+#[cfg(feature = "serde")]
+pub mod serialize;
 
 use crate::ir::IrBuilder;
+use crate::jit::CompiledExpression;
+use indexmap::IndexMap;
+use std::sync::Arc;
 use thiserror::Error;
 
-pub use eval::{Namespace, RuntimeContext, Value};
+pub use eval::{Diagnostic, ExecError, Namespace, RuntimeContext, Value};
+pub use jit_cache::{
+    clear_cache, clear_expressions, clear_programs, compile_program_cached, estimated_memory,
+};
 
 #[derive(Debug, Error)]
 pub enum MolangError {
@@ -22,23 +33,254 @@ pub enum MolangError {
     Lower(#[from] ir::LowerError),
     #[error(transparent)]
     Jit(#[from] jit::JitError),
+    #[error(transparent)]
+    Exec(#[from] ExecError),
+    #[error("expected the script to return a struct, got {found}")]
+    NotAStruct { found: &'static str },
+}
+
+impl MolangError {
+    /// 1-based `(line, column)` of a lex or parse error, or of the call behind an
+    /// unknown-function or argument-count error, within `input`, the source the error came
+    /// from. Other errors raised after parsing carry no position.
+    pub fn location(&self, input: &str) -> Option<(usize, usize)> {
+        let offset = match self {
+            MolangError::Lex(error) => Some(error.offset()),
+            MolangError::Parse(error) => error.offset(),
+            MolangError::Lower(error) => error.span().map(|span| span.start),
+            _ => None,
+        }?;
+        Some(lexer::LineIndex::new(input).position(offset))
+    }
 }
 
 /// Entry point for host code: lex/parse a Molang snippet and compile to native code via
 /// Cranelift JIT. Pure expressions are cached; programs are compiled on demand.
 pub fn evaluate_expression(input: &str, ctx: &mut RuntimeContext) -> Result<f64, MolangError> {
     let tokens = lexer::lex(input)?;
-    let mut parser = parser::Parser::new(&tokens);
+    evaluate_parsed(&tokens, Some(input), ctx)
+}
+
+/// Lexes and parses `input` into its syntax tree without lowering or compiling it, for
+/// tooling such as linters that walk [`ast::Program`].
+pub fn parse(input: &str) -> Result<ast::Program, MolangError> {
+    let tokens = lexer::lex(input)?;
+    Ok(parser::Parser::new(&tokens).parse_program()?)
+}
+
+/// Parses `input` and prints it back in canonical layout (see [`format::format_program`]).
+/// Formatting is idempotent: formatting the output again returns it unchanged.
+pub fn format(input: &str) -> Result<String, MolangError> {
+    Ok(format::format_program(&parse(input)?))
+}
+
+/// Reseeds the random generator behind `math.random`, `math.weighted_index` and the
+/// die-roll builtins, and selects the `math.noise` field, for reproducible tests and replays
+/// (see [`builtins::seed_rng`]).
+pub fn seed_rng(seed: u64) {
+    builtins::seed_rng(seed);
+}
+
+/// Lists every builtin callable by path as `(path, arity)`, e.g. `("math.clamp", 3)`:
+/// the numeric builtins first, then those over whole values (`array.push`, `typeof`).
+/// Variadic builtins (`math.max`, `math.min`) report their minimum arity.
+pub fn list_builtins() -> Vec<(&'static str, usize)> {
+    let numeric = ir::BuiltinFunction::all()
+        .iter()
+        .map(|builtin| (builtin.name(), builtin.arity()));
+    let values = ir::ValueFunction::ALL
+        .iter()
+        .filter_map(|function| Some((function.path()?, function.arity())));
+    numeric.chain(values).collect()
+}
+
+/// Evaluates a pre-lexed token stream (as produced by [`lexer::lex`]), skipping the lexer.
+/// Without a source string there is no cache key, so pure expressions are compiled fresh.
+pub fn evaluate_tokens(
+    tokens: &[lexer::Token],
+    ctx: &mut RuntimeContext,
+) -> Result<f64, MolangError> {
+    evaluate_parsed(tokens, None, ctx)
+}
+
+/// Like [`evaluate_expression`], but hands back the full returned value, so scripts ending in
+/// `return { x: ..., y: ... };` (or returning a string/array) can feed several outputs.
+pub fn evaluate_value(input: &str, ctx: &mut RuntimeContext) -> Result<Value, MolangError> {
+    ctx.set_capture_returns(true);
+    let number = evaluate_expression(input, ctx);
+    ctx.set_capture_returns(false);
+    let captured = ctx.take_return_value();
+    Ok(captured.unwrap_or(Value::number(number?)))
+}
+
+/// Same as [`evaluate_value`].
+#[deprecated(note = "use `evaluate_value`, which behaves identically")]
+pub fn evaluate_to_value(input: &str, ctx: &mut RuntimeContext) -> Result<Value, MolangError> {
+    evaluate_value(input, ctx)
+}
+
+/// Evaluates a script that returns a struct and yields its fields.
+pub fn evaluate_struct(
+    input: &str,
+    ctx: &mut RuntimeContext,
+) -> Result<IndexMap<String, Value>, MolangError> {
+    match evaluate_value(input, ctx)? {
+        Value::Struct(fields) => Ok(fields),
+        other => Err(MolangError::NotAStruct {
+            found: other.type_name(),
+        }),
+    }
+}
+
+/// One-liner for "evaluate this given these named numbers": binds each `(name, value)` as
+/// `query.<name>` on a fresh context and evaluates `input`.
+pub fn evaluate_with_queries(input: &str, queries: &[(&str, f64)]) -> Result<f64, MolangError> {
+    let mut ctx = RuntimeContext::default();
+    for (name, value) in queries {
+        ctx.set_query_value(*name, *value);
+    }
+    evaluate_expression(input, &mut ctx)
+}
+
+fn evaluate_parsed(
+    tokens: &[lexer::Token],
+    cache_key: Option<&str>,
+    ctx: &mut RuntimeContext,
+) -> Result<f64, MolangError> {
+    let compiled = compile_parsed(tokens, cache_key, ctx)?;
+    run_compiled(&compiled, ctx)
+}
+
+/// Runs compiled code, then surfaces any runtime error it recorded on `ctx`.
+fn run_compiled(
+    compiled: &CompiledExpression,
+    ctx: &mut RuntimeContext,
+) -> Result<f64, MolangError> {
+    ctx.take_exec_error();
+    ctx.take_return_value();
+    let value = compiled.evaluate(ctx)?;
+    match ctx.take_exec_error() {
+        Some(error) => Err(error.into()),
+        None => Ok(value),
+    }
+}
+
+fn compile_parsed(
+    tokens: &[lexer::Token],
+    cache_key: Option<&str>,
+    ctx: &RuntimeContext,
+) -> Result<Arc<CompiledExpression>, MolangError> {
+    let mut parser = parser::Parser::new(tokens);
     let program = parser.parse_program()?;
-    let builder = IrBuilder::default();
+    compile_program_ast(&program, cache_key, ctx)
+}
+
+fn compile_program_ast(
+    program: &ast::Program,
+    cache_key: Option<&str>,
+    ctx: &RuntimeContext,
+) -> Result<Arc<CompiledExpression>, MolangError> {
+    let builder = IrBuilder::default().with_host_functions(ctx.function_names());
     if let Some(expr) = program.as_jit_expression() {
         let ir = builder.lower(expr)?;
-        let compiled = jit_cache::compile_cached(input, &ir)?;
-        compiled.evaluate(ctx).map_err(MolangError::from)
+        match cache_key {
+            Some(key) => Ok(jit_cache::compile_cached(key, &ir)?),
+            None => Ok(Arc::new(jit::compile_expression(&ir)?)),
+        }
     } else {
-        let ir_program = builder.lower_program(&program)?;
-        let compiled = jit::compile_program(&ir_program)?;
-        compiled.evaluate(ctx).map_err(MolangError::from)
+        let ir_program = builder.lower_program(program)?;
+        Ok(Arc::new(jit::compile_program(&ir_program)?))
+    }
+}
+
+/// How [`plan`] expects a script to be compiled. Both modes run native code; they differ
+/// in whether the compiled code is cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// A single pure expression, compiled once and cached by source string.
+    CachedExpression,
+    /// A statement program, compiled on demand for every evaluation.
+    Program,
+}
+
+/// Result of [`plan`]: the chosen mode and a human-readable reason for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionPlan {
+    pub mode: ExecutionMode,
+    pub reason: String,
+}
+
+/// Lexes and parses `input` and reports which compile path it would take, without
+/// compiling or evaluating anything.
+pub fn plan(input: &str) -> Result<ExecutionPlan, MolangError> {
+    let program = parse(input)?;
+    Ok(match program.fallback_reason() {
+        None => ExecutionPlan {
+            mode: ExecutionMode::CachedExpression,
+            reason: "pure expression".to_string(),
+        },
+        Some(reason) => ExecutionPlan {
+            mode: ExecutionMode::Program,
+            reason: reason.to_string(),
+        },
+    })
+}
+
+/// Compiled script bound to its own context, for hosts that only tweak a few queries
+/// between frames. `recompute` re-runs the native code without lexing or compiling again.
+pub struct ReactiveProgram {
+    compiled: Arc<CompiledExpression>,
+    ctx: RuntimeContext,
+    required_queries: Vec<String>,
+}
+
+impl ReactiveProgram {
+    /// Compiles `input` once and keeps `ctx` as the context for every recompute.
+    pub fn new(input: &str, ctx: RuntimeContext) -> Result<Self, MolangError> {
+        let program = parse(input)?;
+        let compiled = compile_program_ast(&program, Some(input), &ctx)?;
+        Ok(Self {
+            compiled,
+            ctx,
+            required_queries: program.required_queries(),
+        })
+    }
+
+    /// Canonical names of the `query.` paths the script reads, sorted.
+    pub fn required_queries(&self) -> &[String] {
+        &self.required_queries
+    }
+
+    /// Checks that `ctx` provides every query the script reads, either as a stored value
+    /// or through its fallback resolver, so a mismatched host config fails before the
+    /// first run instead of silently reading `0`.
+    pub fn validate(&self, ctx: &RuntimeContext) -> Result<(), MolangError> {
+        match self
+            .required_queries
+            .iter()
+            .find(|name| ctx.get_value_canonical(name).is_none())
+        {
+            Some(name) => Err(ir::LowerError::MissingQuery { name: name.clone() }.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Updates a numeric query binding; takes effect on the next `recompute`.
+    pub fn update_query(&mut self, name: impl Into<String>, value: f64) {
+        self.ctx.set_query_value(name, value);
+    }
+
+    /// Runs the compiled code against the current context.
+    pub fn recompute(&mut self) -> Result<f64, MolangError> {
+        run_compiled(&self.compiled, &mut self.ctx)
+    }
+
+    pub fn context(&self) -> &RuntimeContext {
+        &self.ctx
+    }
+
+    pub fn context_mut(&mut self) -> &mut RuntimeContext {
+        &mut self.ctx
     }
 }
 
@@ -62,9 +304,10 @@ mod tests {
             evaluate_expression("(1 < 2) ? 5.0 : 10.0", &mut ctx).expect("ternary should work");
         assert!((ternary - 5.0).abs() < 1e-9);
 
+        // `??` tests for null, not falsiness: a literal `0` is kept
         let null_coalesce =
             evaluate_expression("0 ?? 3 + 2", &mut ctx).expect("null coalesce should work");
-        assert!((null_coalesce - 5.0).abs() < 1e-9);
+        assert!(null_coalesce.abs() < 1e-9);
 
         let logical = evaluate_expression("!(1 - 1) || (2 > 1) && (3 == 3)", &mut ctx)
             .expect("logical operations should work");
@@ -112,6 +355,27 @@ mod tests {
         assert_eq!(jit_cache::cache_size(), 1);
     }
 
+    #[test]
+    fn reactive_program_recomputes_without_recompiling() {
+        jit_cache::clear_cache();
+        let ctx = RuntimeContext::default().with_query("speed", 2.0);
+        let mut program = ReactiveProgram::new("query.speed * 10", ctx).unwrap();
+        assert!((program.recompute().unwrap() - 20.0).abs() < 1e-9);
+
+        program.update_query("speed", 3.5);
+        assert!((program.recompute().unwrap() - 35.0).abs() < 1e-9);
+        assert_eq!(jit_cache::cache_size(), 1);
+
+        let mut looped = ReactiveProgram::new(
+            "temp.total = 0; loop(query.count, { temp.total = temp.total + 1; }); return temp.total;",
+            RuntimeContext::default().with_query("count", 2.0),
+        )
+        .unwrap();
+        assert!((looped.recompute().unwrap() - 2.0).abs() < 1e-9);
+        looped.update_query("count", 5.0);
+        assert!((looped.recompute().unwrap() - 5.0).abs() < 1e-9);
+    }
+
     fn eval(script: &str) -> f64 {
         let mut ctx = RuntimeContext::default();
         evaluate_expression(script, &mut ctx).expect("script evaluation to succeed")
@@ -143,6 +407,37 @@ mod tests {
         assert!((value - 12.0).abs() < 1e-9); // skips adding 3
     }
 
+    #[test]
+    fn ternary_chooses_between_break_and_continue() {
+        let value = eval(
+            "
+            temp.x = 0;
+            temp.visited = 0;
+            loop(10, {
+                temp.x = temp.x + 1;
+                (temp.x > 5) ? break : continue;
+                temp.visited = temp.visited + 1;
+            });
+            return temp.x * 100 + temp.visited;
+            ",
+        );
+        assert!((value - 600.0).abs() < 1e-9);
+
+        let value = eval(
+            "
+            temp.x = 0;
+            temp.tail = 0;
+            loop(4, {
+                temp.x = temp.x + 1;
+                (temp.x > 5) ? break : continue;
+                temp.tail = temp.tail + 1;
+            });
+            return temp.x * 100 + temp.tail;
+            ",
+        );
+        assert!((value - 400.0).abs() < 1e-9);
+    }
+
     #[test]
     fn for_each_breaks_early() {
         let value = eval(
@@ -159,6 +454,21 @@ mod tests {
         assert!((value - 12.0).abs() < 1e-9);
     }
 
+    #[test]
+    fn for_each_iterates_struct_values() {
+        let value = eval(
+            "
+            temp.entries = { a: 1, b: 2, c: 3 };
+            temp.total = 0;
+            for_each(temp.entry, temp.entries, {
+                temp.total = temp.total + temp.entry;
+            });
+            return temp.total;
+            ",
+        );
+        assert!((value - 6.0).abs() < 1e-9);
+    }
+
     #[test]
     fn math_helpers() {
         let clamp = eval("return math.clamp(-2, 0, 10);");
@@ -172,6 +482,46 @@ mod tests {
         assert!((round - 5.0).abs() < 1e-9);
     }
 
+    fn numbers_at(ctx: &RuntimeContext, canonical: &str) -> Vec<f64> {
+        match ctx.get_value_canonical(canonical) {
+            Some(Value::Array(values)) => values.iter().map(Value::as_number).collect(),
+            other => panic!("expected array at {canonical}, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn math_helpers_broadcast_over_arrays() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression(
+            "
+            temp.abs = math.abs([-1, -2, 3]);
+            temp.clamped = math.clamp([-1, 2, 3], 0, 1);
+            temp.values = [1.4, 2.6];
+            temp.rounded = math.round(temp.values);
+            temp.scalar = -4;
+            temp.scalar_abs = math.abs(temp.scalar);
+            ",
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(numbers_at(&ctx, "temp.abs"), vec![1.0, 2.0, 3.0]);
+        assert_eq!(numbers_at(&ctx, "temp.clamped"), vec![0.0, 1.0, 1.0]);
+        assert_eq!(numbers_at(&ctx, "temp.rounded"), vec![1.0, 3.0]);
+        assert_eq!(ctx.get_number_canonical("temp.scalar_abs"), Some(4.0));
+
+        let returned = evaluate_value("return math.abs([-1, 2]);", &mut ctx).unwrap();
+        assert_eq!(returned, Value::array(vec![Value::number(1.0), Value::number(2.0)]));
+        let nested = evaluate_value("return math.floor(math.abs([-1.5, 2.5]));", &mut ctx).unwrap();
+        assert_eq!(nested, Value::array(vec![Value::number(1.0), Value::number(2.0)]));
+        evaluate_expression("temp.n = math.clamp(math.abs(temp.values), 0, 2);", &mut ctx).unwrap();
+        assert_eq!(numbers_at(&ctx, "temp.n"), vec![1.4, 2.0]);
+        assert_eq!(evaluate_expression("return math.abs(temp.scalar);", &mut ctx).unwrap(), 4.0);
+        assert!(matches!(
+            evaluate_expression("return math.abs([-1, 2]) + 1;", &mut ctx),
+            Err(MolangError::Jit(_))
+        ));
+    }
+
     #[test]
     fn struct_literals_and_nested_assignment() {
         let value = eval(
@@ -285,35 +635,1752 @@ mod tests {
     }
 
     #[test]
-    fn query_mutation_after_creation() {
-        let mut ctx = RuntimeContext::default().with_query("value", 10.0);
+    fn typed_accessors_read_back_queries() {
+        use indexmap::IndexMap;
+        let mut inner = IndexMap::new();
+        inner.insert("label".to_string(), Value::string("origin"));
+        let mut position = IndexMap::new();
+        position.insert("x".to_string(), Value::number(1.0));
+        position.insert("meta".to_string(), Value::Struct(inner));
 
-        // Initial value
-        let result = evaluate_expression("return query.value;", &mut ctx).unwrap();
-        assert!((result - 10.0).abs() < 1e-9);
+        let ctx = RuntimeContext::default()
+            .with_query_string("name", "player")
+            .with_query_value("items", Value::array(vec![Value::number(4.0)]))
+            .with_query_value("position", Value::Struct(position));
+
+        assert_eq!(ctx.get_string("query.name"), Some("player"));
+        assert_eq!(ctx.get_array("query.items").map(|items| items.len()), Some(1));
+        assert_eq!(ctx.get_struct("query.position").map(|map| map.len()), Some(2));
+        assert_eq!(ctx.get_string("query.position.meta.label"), Some("origin"));
+        assert!(ctx.get_struct("query.position.meta").is_some());
+
+        assert_eq!(ctx.get_string("query.items"), None);
+        assert!(ctx.get_array("query.name").is_none());
+        assert!(ctx.get_struct("query.missing").is_none());
+    }
 
-        // Mutate after creation
-        ctx.set_query_value("value", 20.0);
-        let result = evaluate_expression("return query.value;", &mut ctx).unwrap();
-        assert!((result - 20.0).abs() < 1e-9);
+    #[test]
+    fn equals_compares_values_deeply() {
+        let mut ctx = RuntimeContext::default();
+        let program = "temp.a = { x: 1, y: [1, 2] }; temp.b = { x: 1, y: [1, 2] }; return equals(temp.a, temp.b);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 1.0);
 
-        // Add new query values dynamically
-        ctx.set_query_string("type", "dynamic");
-        let stored = ctx.get_value_canonical("query.type");
-        assert!(matches!(stored, Some(Value::String(_))));
+        let program = "temp.a = { x: 1, y: [1, 2] }; temp.b = { x: 1, y: [1, 3] }; return equals(temp.a, temp.b);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 0.0);
+
+        let program = "temp.same = equals('abc', 'abc'); temp.diff = equals([1, 2], [1, 2, 3]); return temp.same * 10 + temp.diff;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 10.0);
     }
 
     #[test]
-    fn array_indexing_and_length() {
-        let value = eval(
-            "
-            temp.values = [10, 20, 30];
-            temp.index = 1;
-            temp.sum = temp.values[temp.index] + temp.values[3] + temp.values.length;
-            return temp.sum;
-            ",
+    fn array_count_tallies_matching_elements() {
+        let mut ctx = RuntimeContext::default();
+        let result = evaluate_expression("return array.count([1, 2, 2, 3, 2], 2);", &mut ctx).unwrap();
+        assert_eq!(result, 3.0);
+
+        let program = "temp.names = ['a', 'b', 'a']; return array.count(temp.names, 'a') + array.count(temp.names, 'z');";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn hexadecimal_literals() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("0xFF", &mut ctx).unwrap(), 255.0);
+        assert_eq!(evaluate_expression("0x10", &mut ctx).unwrap(), 16.0);
+        assert_eq!(evaluate_expression("0X1a + 0.5", &mut ctx).unwrap(), 26.5);
+        assert_eq!(evaluate_expression("0.25 + 10", &mut ctx).unwrap(), 10.25);
+        assert!(matches!(
+            evaluate_expression("0x", &mut ctx),
+            Err(MolangError::Lex(lexer::LexError::InvalidNumber { .. }))
+        ));
+    }
+
+    #[test]
+    fn lerp_clamped_saturates_t() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("math.lerp_clamped(0, 10, 2)", &mut ctx).unwrap(), 10.0);
+        assert_eq!(evaluate_expression("math.lerp_clamped(0, 10, -1)", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("math.lerp_clamped(0, 10, 0.25)", &mut ctx).unwrap(), 2.5);
+        assert_eq!(evaluate_expression("math.lerp(0, 10, 2)", &mut ctx).unwrap(), 20.0);
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("1 + 2 // ignored\n", &mut ctx).unwrap(), 3.0);
+        assert_eq!(evaluate_expression("8 /* halve */ / 2", &mut ctx).unwrap(), 4.0);
+
+        let program = "// setup\ntemp.x = 6; /* multi\nline */ return temp.x / 3;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 2.0);
+
+        assert!(matches!(
+            evaluate_expression("1 /* never closed", &mut ctx),
+            Err(MolangError::Lex(lexer::LexError::UnterminatedComment { start: 2 }))
+        ));
+    }
+
+    #[test]
+    fn out_of_range_reads_record_diagnostics() {
+        let mut ctx = RuntimeContext::default();
+        let program = "temp.values = [1, 2, 3]; return temp.values[1] + temp.values[4];";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 4.0);
+        assert_eq!(
+            ctx.diagnostics(),
+            &[Diagnostic::IndexOutOfRange {
+                path: "temp.values".to_string(),
+                index: 4,
+                length: 3,
+            }]
         );
-        assert!((value - 33.0).abs() < 1e-9);
+
+        assert_eq!(ctx.take_diagnostics().len(), 1);
+        assert!(ctx.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn scripts_can_return_structs() {
+        let mut ctx = RuntimeContext::default();
+        let fields = evaluate_struct("temp.b = 2; return { a: 1, b: temp.b };", &mut ctx).unwrap();
+        assert_eq!(fields.get("a").map(Value::as_number), Some(1.0));
+        assert_eq!(fields.get("b").map(Value::as_number), Some(2.0));
+
+        let value = evaluate_value("temp.name = 'steve'; return temp.name;", &mut ctx).unwrap();
+        assert!(value.deep_equals(&Value::string("steve")));
+        assert!(evaluate_value("1 + 2", &mut ctx).unwrap().deep_equals(&Value::number(3.0)));
+
+        assert!(matches!(
+            evaluate_struct("return 5;", &mut ctx),
+            Err(MolangError::NotAStruct { found: "number" })
+        ));
+    }
+
+    #[test]
+    fn compound_assignment_operators() {
+        let mut ctx = RuntimeContext::default();
+        let program = "temp.counter = 0; loop(5, { temp.counter += 1; }); return temp.counter;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 5.0);
+
+        let program = "temp.x = 10; temp.x -= 4; temp.x *= 3; temp.x /= 2; return temp.x;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 9.0);
+    }
+
+    #[test]
+    fn cube_root_handles_negative_inputs() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("math.cbrt(27)", &mut ctx).unwrap(), 3.0);
+        assert_eq!(evaluate_expression("math.cbrt(-8)", &mut ctx).unwrap(), -2.0);
+    }
+
+    #[test]
+    fn gradient_interpolates_between_stops() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("math.gradient([0, 10, 20], 0.25)", &mut ctx).unwrap(), 5.0);
+        assert_eq!(evaluate_expression("math.gradient([0, 10, 20], 0.75)", &mut ctx).unwrap(), 15.0);
+
+        let program = "temp.stops = [4, 8]; return math.gradient(temp.stops, 2) + math.gradient(temp.stops, -1);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 12.0);
+    }
+
+    #[test]
+    fn host_functions_are_callable() {
+        let mut ctx = RuntimeContext::default().with_query("speed", 4.0);
+        ctx.register_function("query.double", |args: &[f64]| args[0] * 2.0);
+        ctx.register_function("sum", |args: &[f64]| args.iter().sum());
+
+        let result = evaluate_expression("query.double(query.speed) + 1", &mut ctx).unwrap();
+        assert_eq!(result, 9.0);
+        let program = "temp.x = sum(1, 2, 3); return temp.x;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 6.0);
+
+        let mut plain = RuntimeContext::default();
+        assert!(matches!(
+            evaluate_expression("return query.triple(2);", &mut plain),
+            Err(MolangError::Lower(ir::LowerError::UnknownFunction { .. }))
+        ));
+    }
+
+    #[test]
+    fn query_functions_and_query_properties_are_distinct() {
+        let mut ctx = RuntimeContext::default().with_query("get_speed", 7.0);
+        ctx.register_function("query.get_speed", |args: &[f64]| args.iter().sum::<f64>() * 10.0);
+
+        // `query.get_speed()` calls the function, bare `query.get_speed` reads the property
+        assert_eq!(evaluate_expression("query.get_speed(1, 2)", &mut ctx).unwrap(), 30.0);
+        assert_eq!(evaluate_expression("query.get_speed", &mut ctx).unwrap(), 7.0);
+        assert_eq!(evaluate_expression("q.get_speed(0.5) + q.get_speed", &mut ctx).unwrap(), 12.0);
+        assert_eq!(evaluate_expression("Query.Get_Speed()", &mut ctx).unwrap(), 0.0);
+        assert!(matches!(
+            evaluate_expression("query.speed()", &mut ctx),
+            Err(MolangError::Lower(ir::LowerError::UnknownFunction { name, .. })) if name == "query.speed"
+        ));
+    }
+
+    #[test]
+    fn struct_returning_host_function_can_be_assigned() {
+        use indexmap::IndexMap;
+        let mut ctx = RuntimeContext::default();
+        ctx.register_value_function("query.get_position", |_: &[f64]| {
+            let mut position = IndexMap::new();
+            position.insert("x".to_string(), Value::number(3.0));
+            position.insert("y".to_string(), Value::number(4.0));
+            Value::Struct(position)
+        });
+
+        let program = "temp.p = query.get_position(); return temp.p.x * 10 + temp.p.y;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 34.0);
+        assert_eq!(ctx.get_struct("temp.p").map(|fields| fields.len()), Some(2));
+
+        // Reassigning a struct with fewer fields drops the old ones
+        let program = "temp.p.z = 9; temp.p = query.get_position(); return temp.p.z;";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 0.0);
+        assert_eq!(ctx.get_struct("temp.p").map(|fields| fields.len()), Some(2));
+    }
+
+    #[test]
+    fn square_and_integer_powers() {
+        let mut ctx = RuntimeContext::default().with_query("base", 1.5);
+        assert_eq!(evaluate_expression("math.square(7)", &mut ctx).unwrap(), 49.0);
+        assert_eq!(evaluate_expression("math.pow(2, 3)", &mut ctx).unwrap(), 8.0);
+        assert_eq!(evaluate_expression("math.pow(query.base, 0)", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("math.pow(query.base, 2)", &mut ctx).unwrap(), 2.25);
+        assert_eq!(evaluate_expression("math.pow(4, 0.5)", &mut ctx).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn builtin_symbol_table_covers_every_builtin() {
+        use crate::ir::BuiltinFunction;
+        let symbols = jit::builtin_symbols();
+        assert_eq!(symbols.len(), BuiltinFunction::ALL.len());
+        for builtin in BuiltinFunction::ALL {
+            assert!(
+                symbols.iter().any(|(name, _)| *name == builtin.symbol_name()),
+                "missing symbol for {builtin:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn plus_concatenates_strings() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.s = 'foo' + 'bar';", &mut ctx).unwrap();
+        assert!(matches!(
+            ctx.get_value_canonical("temp.s"),
+            Some(Value::String(text)) if text == "foobar"
+        ));
+
+        let program = "temp.name = 'steve'; temp.greeting = 'hi ' + temp.name + '!'; return temp.greeting == 'hi steve!';";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 1.0);
+
+        let value = evaluate_value("'a' + 'b'", &mut ctx).unwrap();
+        assert!(value.deep_equals(&Value::string("ab")));
+    }
+
+    #[test]
+    fn struct_min_max_over_numeric_fields() {
+        let mut ctx = RuntimeContext::default();
+        let program = "temp.stats = { a: 3, b: 7, c: 1, name: 'x' }; return struct.max(temp.stats) * 10 + struct.min(temp.stats);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 71.0);
+
+        // Structs without numeric fields yield 0
+        let program = "return struct.max({}) + struct.min({});";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 0.0);
+        let mut empty = RuntimeContext::default().with_query_value("empty", Value::Struct(IndexMap::new()));
+        let program = "return struct.max(query.empty) + struct.min(query.empty);";
+        assert_eq!(evaluate_expression(program, &mut empty).unwrap(), 0.0);
+        let program = "temp.names = { a: 'x', b: [1] }; return struct.max(temp.names) + struct.min(temp.names);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn query_arrays_and_structs_can_be_replaced() {
+        use indexmap::IndexMap;
+        let mut ctx = RuntimeContext::default();
+        ctx.set_query_array("items", vec![Value::number(1.0), Value::number(2.0)]);
+        assert_eq!(evaluate_expression("return query.items.length;", &mut ctx).unwrap(), 2.0);
+
+        ctx.set_query_array("items", (1..=4).map(|n| Value::number(n as f64)).collect());
+        assert_eq!(evaluate_expression("return query.items.length;", &mut ctx).unwrap(), 4.0);
+
+        let mut stats = IndexMap::new();
+        stats.insert("hp".to_string(), Value::number(20.0));
+        ctx.set_query_struct("stats", stats);
+        assert_eq!(evaluate_expression("return query.stats.hp;", &mut ctx).unwrap(), 20.0);
+
+        ctx.set_query_value_any("items", Value::string("none"));
+        assert_eq!(ctx.get_string("query.items"), Some("none"));
+    }
+
+    #[test]
+    fn strict_reads_reject_undefined_variables() {
+        let mut lenient = RuntimeContext::default();
+        assert_eq!(evaluate_expression("return temp.never_set;", &mut lenient).unwrap(), 0.0);
+
+        let mut strict = RuntimeContext::default().strict_reads(true);
+        assert!(matches!(
+            evaluate_expression("return temp.never_set;", &mut strict),
+            Err(MolangError::Exec(ExecError::UndefinedVariable { name })) if name == "temp.never_set"
+        ));
+
+        // Queries stay lenient and `??` may probe missing variables
+        assert_eq!(evaluate_expression("return query.missing;", &mut strict).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("return temp.never_set ?? 5;", &mut strict).unwrap(), 5.0);
+        assert_eq!(evaluate_expression("temp.x = 2; return temp.x;", &mut strict).unwrap(), 2.0);
+
+        // Element reads, path comparisons and value-function arguments are reads too
+        for script in [
+            "return temp.never_set[0];",
+            "temp.s = 'a'; return temp.s == temp.never_set;",
+            "return temp.never_set == 'a';",
+            "temp.s = 'a'; return temp.never_set < temp.s;",
+            "return array.sum(temp.never_set);",
+        ] {
+            let error = evaluate_expression(script, &mut strict).unwrap_err();
+            assert!(
+                matches!(&error, MolangError::Exec(ExecError::UndefinedVariable { name }) if name == "temp.never_set"),
+                "{script}: {error}"
+            );
+        }
+    }
+
+    #[test]
+    fn evaluate_value_returns_strings_and_arrays() {
+        let mut ctx = RuntimeContext::default();
+        let name = evaluate_value("temp.name = 'alex'; return temp.name;", &mut ctx).unwrap();
+        assert!(matches!(name, Value::String(text) if text == "alex"));
+
+        let items = evaluate_value("temp.items = [1, 2, 3]; return temp.items;", &mut ctx).unwrap();
+        assert_eq!(items.as_array().map(|items| items.len()), Some(3));
+        assert_eq!(items.as_number(), 3.0);
+        let length = evaluate_expression("temp.items = [1, 2, 3]; return temp.items;", &mut ctx).unwrap();
+        assert_eq!(length, 3.0);
+    }
+
+    #[test]
+    fn rgb_colors_round_trip() {
+        let mut ctx = RuntimeContext::default();
+        let packed = evaluate_expression("math.rgb(255, 128, 7)", &mut ctx).unwrap();
+        assert_eq!(packed, 255.0 * 65536.0 + 128.0 * 256.0 + 7.0);
+        assert_eq!(evaluate_expression("math.rgb(255, 128, 7)", &mut ctx).unwrap(), 0xFF8007 as f64);
+
+        let program = "temp.c = math.rgb(12, 34, 56); return math.rgb_channel(temp.c, 0) * 10000 + math.rgb_channel(temp.c, 1) * 100 + math.rgb_channel(temp.c, 2);";
+        assert_eq!(evaluate_expression(program, &mut ctx).unwrap(), 123456.0);
+        assert_eq!(evaluate_expression("math.rgb_channel(0xFF00FF, 1)", &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn mod_and_euclidean_mod_differ_for_negatives() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("math.mod(-1, 360)", &mut ctx).unwrap(), -1.0);
+        assert_eq!(evaluate_expression("math.mod_euclid(-1, 360)", &mut ctx).unwrap(), 359.0);
+        assert_eq!(evaluate_expression("math.mod_euclid(725, 360)", &mut ctx).unwrap(), 5.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn integral_numbers_serialize_as_integers() {
+        use crate::serialize::IntegralNumbers;
+        let whole = Value::number(3.0);
+        let fraction = Value::number(3.5);
+        assert_eq!(serde_json::to_string(&IntegralNumbers(&whole)).unwrap(), "3");
+        assert_eq!(serde_json::to_string(&IntegralNumbers(&fraction)).unwrap(), "3.5");
+        assert_eq!(serde_json::to_string(&whole).unwrap(), "3.0");
+
+        let nested = Value::array(vec![Value::number(1.0), Value::string("x")]);
+        assert_eq!(serde_json::to_string(&IntegralNumbers(&nested)).unwrap(), r#"[1,"x"]"#);
+    }
+
+    #[test]
+    fn plan_classifies_without_evaluating() {
+        let pure = plan("math.sin(query.time) * 2 + 1").unwrap();
+        assert_eq!(pure.mode, ExecutionMode::CachedExpression);
+
+        let looping = plan("loop(3, { temp.x = temp.x + 1; })").unwrap();
+        assert_eq!(looping.mode, ExecutionMode::Program);
+        assert_eq!(looping.reason, "contains loop");
+
+        assert_eq!(plan("'a' + 'b'").unwrap().reason, "uses strings");
+        assert_eq!(
+            plan("temp.x = 1; return temp.x;").unwrap().reason,
+            "contains multiple statements"
+        );
+    }
+
+    #[test]
+    fn array_indices_resolve_from_either_end() {
+        assert_eq!(eval::resolve_index(-1, 4), Some(3));
+        assert_eq!(eval::resolve_index(4, 4), Some(0));
+        assert_eq!(eval::resolve_index(5, 4), Some(1));
+        assert_eq!(eval::resolve_index(0, 0), None);
+
+        let script = |index: &str| {
+            format!("temp.values = [10, 20, 30, 40]; return temp.values[{index}];")
+        };
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression(&script("-1"), &mut ctx).unwrap(), 40.0);
+        assert!(ctx.take_diagnostics().is_empty());
+        assert_eq!(evaluate_expression(&script("temp.values.length"), &mut ctx).unwrap(), 10.0);
+        assert_eq!(evaluate_expression(&script("5"), &mut ctx).unwrap(), 20.0);
+        assert_eq!(ctx.take_diagnostics().len(), 2);
+
+        let mut program = ReactiveProgram::new(&script("-2"), RuntimeContext::default()).unwrap();
+        assert_eq!(program.recompute().unwrap(), 30.0);
+    }
+
+    #[test]
+    fn lerp_interpolates_arrays_element_wise() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression(
+            "temp.start = [0, 0]; temp.end = [10, 20]; temp.mid = math.lerp(temp.start, temp.end, 0.5);",
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(numbers_at(&ctx, "temp.mid"), vec![5.0, 10.0]);
+
+        evaluate_expression("temp.x = 2; temp.y = math.lerp(temp.x, 4, 0.5);", &mut ctx).unwrap();
+        assert_eq!(ctx.get_number_canonical("temp.y"), Some(3.0));
+    }
+
+    #[test]
+    fn lerp_rotate_is_an_alias_for_lerprotate() {
+        let mut ctx = RuntimeContext::default();
+        let canonical = evaluate_expression("math.lerprotate(10, 350, 0.5)", &mut ctx).unwrap();
+        let alias = evaluate_expression("math.lerp_rotate(10, 350, 0.5)", &mut ctx).unwrap();
+        assert_eq!(alias, canonical);
+        assert_eq!(alias, 0.0);
+    }
+
+    #[test]
+    fn values_display_canonically() {
+        assert_eq!(Value::number(3.0).to_string(), "3");
+        assert_eq!(Value::number(2.5).to_string(), "2.5");
+        assert_eq!(Value::number(-0.0).to_string(), "0");
+        assert_eq!(Value::string("hi").to_string(), "\"hi\"");
+        assert_eq!(Value::Null.to_string(), "null");
+        let array = Value::array(vec![Value::number(1.0), Value::string("a"), Value::Null]);
+        assert_eq!(array.to_string(), "[1, \"a\", null]");
+
+        let mut ctx = RuntimeContext::default();
+        let nested = evaluate_value("return { x: 1, inner: { y: [2, 3] } };", &mut ctx).unwrap();
+        assert_eq!(nested.to_string(), "{x: 1, inner: {y: [2, 3]}}");
+    }
+
+    #[test]
+    fn fallback_resolver_backs_unset_variables() {
+        let mut ctx = RuntimeContext::default().strict_reads(true);
+        ctx.set_fallback_resolver(|namespace, key| match namespace {
+            Namespace::Variable => Some(Value::number(key.len() as f64)),
+            _ => None,
+        });
+        let value = evaluate_expression("variable.abc + variable.speed", &mut ctx).unwrap();
+        assert_eq!(value, 8.0);
+
+        evaluate_expression("variable.abc = 1;", &mut ctx).unwrap();
+        assert_eq!(evaluate_expression("variable.abc", &mut ctx).unwrap(), 1.0);
+        assert!(evaluate_expression("temp.missing", &mut ctx).is_err());
+    }
+
+    #[test]
+    fn spans_convert_to_line_and_column() {
+        let script = "temp.a = 1;\ntemp.b = 'é' + 2;\n  return temp.b $ 3;";
+        let index = lexer::LineIndex::new(script);
+        assert_eq!(index.position(0), (1, 1));
+        let tokens = lexer::lex("temp.a = 1;\ntemp.b = 'é' + 2;").unwrap();
+        let plus = tokens
+            .iter()
+            .find(|token| token.kind == lexer::TokenKind::Plus)
+            .unwrap();
+        assert_eq!(index.span_positions(plus.span), ((2, 14), (2, 14)));
+
+        let mut ctx = RuntimeContext::default();
+        let error = evaluate_expression(script, &mut ctx).unwrap_err();
+        assert_eq!(error.location(script), Some((3, 17)));
+    }
+
+    #[test]
+    fn values_round_trip_through_molang_literals() {
+        let mut ctx = RuntimeContext::default();
+        let original = evaluate_value(
+            "return { name: \"it's\", pos: { x: -1.5, y: 2 }, tags: ['a', 'b'], nums: [1, 2] };",
+            &mut ctx,
+        )
+        .unwrap();
+        let literal = original.to_molang_literal().unwrap();
+        let restored = evaluate_value(&format!("return {literal};"), &mut ctx).unwrap();
+        assert!(restored.deep_equals(&original), "{literal} -> {restored}");
+        assert_eq!(Value::Null.to_molang_literal(), None);
+        assert_eq!(Value::number(f64::NAN).to_molang_literal(), None);
+    }
+
+    #[test]
+    fn trailing_loop_yields_last_body_value() {
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(eval("temp.x = 0; loop(3, temp.x = temp.x + 1)"), 3.0);
+        assert_eq!(
+            evaluate_expression("loop(4, { temp.y = 2; temp.y * 5; })", &mut ctx).unwrap(),
+            10.0
+        );
+        assert_eq!(eval("loop(0, temp.x = 7)"), 0.0);
+    }
+
+    #[test]
+    fn snap_rounds_to_nearest_step() {
+        assert_eq!(eval("math.snap(7, 5)"), 5.0);
+        assert_eq!(eval("math.snap(8, 5)"), 10.0);
+        assert_eq!(eval("math.snap(-3.3, 0.5)"), -3.5);
+        assert_eq!(eval("math.snap(7.25, 0)"), 7.25);
+    }
+
+    #[test]
+    fn list_variables_orders_by_namespace_then_key() {
+        let mut ctx = RuntimeContext::default().with_query("speed", 1.0);
+        evaluate_expression(
+            "variable.a = 1; temp.player.y = 2; temp.player.x = 1; temp.b = 3;",
+            &mut ctx,
+        )
+        .unwrap();
+        let names: Vec<String> = ctx
+            .list_variables()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "temp.b",
+                "temp.player",
+                "temp.player.x",
+                "temp.player.y",
+                "variable.a",
+                "query.speed"
+            ]
+        );
+        let player = ctx
+            .list_variables()
+            .into_iter()
+            .find(|(name, _)| name == "temp.player")
+            .map(|(_, value)| value.to_string());
+        assert_eq!(player.as_deref(), Some("{y: 2, x: 1}"));
+    }
+
+    #[test]
+    fn bitwise_operators_on_integers() {
+        assert_eq!(eval("6 & 3"), 2.0);
+        assert_eq!(eval("5 | 2"), 7.0);
+        assert_eq!(eval("5 ^ 1"), 4.0);
+        assert_eq!(eval("6.9 & 3.2"), 2.0);
+        assert_eq!(eval("0xFF00FF & 0xFF"), 255.0);
+        // `&` binds tighter than `|` and looser than `==`
+        assert_eq!(eval("1 | 2 & 3"), 3.0);
+        assert_eq!(eval("3 & 2 == 2"), 1.0);
+    }
+
+    #[test]
+    fn strings_escape_their_delimiters() {
+        let string_at = |source: &str| match lexer::lex(source).unwrap()[0].kind.clone() {
+            lexer::TokenKind::String(text) => text,
+            other => panic!("expected a string token, got {other:?}"),
+        };
+        assert_eq!(string_at(r"'it\'s'"), "it's");
+        assert_eq!(string_at(r#""say \"hi\"""#), r#"say "hi""#);
+        assert_eq!(string_at(r"'path\\'"), r"path\");
+        let tokens = lexer::lex(r"'path\\' + 1").unwrap();
+        assert_eq!(tokens[1].kind, lexer::TokenKind::Plus);
+        assert!(matches!(
+            lexer::lex(r"'open\'"),
+            Err(lexer::LexError::UnterminatedString { start: 0 })
+        ));
+
+        assert_eq!(string_at(r"'a\nb'"), "a\nb");
+        assert_eq!(string_at(r"'\t\r\0'"), "\t\r\0");
+        assert!(matches!(
+            lexer::lex(r"'\x'"),
+            Err(lexer::LexError::InvalidEscape { ch: 'x', index: 1 })
+        ));
+
+        let tricky = Value::string("it's \"quoted\" \\ done\n\tnext");
+        let literal = tricky.to_molang_literal().unwrap();
+        let mut ctx = RuntimeContext::default();
+        let restored = evaluate_value(&format!("return {literal};"), &mut ctx).unwrap();
+        assert!(restored.deep_equals(&tricky), "{literal} -> {restored}");
+    }
+
+    #[test]
+    fn loops_are_capped_by_the_context_limit() {
+        let script = "temp.n = 0; loop(100000, { temp.n = temp.n + 1; }); return temp.n;";
+        let mut ctx = RuntimeContext::default().with_max_loop_iterations(10);
+        assert_eq!(evaluate_expression(script, &mut ctx).unwrap(), 10.0);
+
+        let mut program = ReactiveProgram::new(script, RuntimeContext::default()).unwrap();
+        assert_eq!(program.recompute().unwrap(), 1024.0);
+        program.context_mut().set_max_loop_iterations(3);
+        assert_eq!(program.recompute().unwrap(), 3.0);
+    }
+
+    #[test]
+    fn cache_memory_grows_with_compiled_scripts() {
+        jit_cache::clear_cache();
+        assert_eq!(jit_cache::estimated_memory(), 0);
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("math.sin(query.a) * query.b + 1", &mut ctx).unwrap();
+        let one = jit_cache::estimated_memory();
+        assert!(one > 0);
+        evaluate_expression("math.cos(query.a) - query.b * 2", &mut ctx).unwrap();
+        assert!(jit_cache::estimated_memory() > one);
+    }
+
+    #[test]
+    fn chained_ternaries_are_right_associative() {
+        assert_eq!(eval("1 ? 2 : 0 ? 3 : 4"), 2.0);
+        assert_eq!(eval("0 ? 2 : 1 ? 3 : 4"), 3.0);
+        assert_eq!(eval("0 ? 2 : 0 ? 3 : 4"), 4.0);
+        assert_eq!(eval("1 ? 0 ? 5 : 6 : 7"), 6.0);
+
+        let tokens = lexer::lex("temp.a ? 1 : temp.b ? 2 : 3").unwrap();
+        let expr = parser::Parser::new(&tokens).parse_expression().unwrap();
+        let ast::Expr::Conditional { else_branch, .. } = expr else {
+            panic!("expected a conditional, got {expr:?}");
+        };
+        assert!(matches!(
+            else_branch.as_deref(),
+            Some(ast::Expr::Conditional { .. })
+        ));
+    }
+
+    #[test]
+    fn evaluate_with_queries_binds_named_numbers() {
+        let value = evaluate_with_queries("query.a + query.b", &[("a", 1.0), ("b", 2.0)]);
+        assert_eq!(value.unwrap(), 3.0);
+        assert_eq!(evaluate_with_queries("q.missing", &[]).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn smoothstep_variants_clamp_outside_the_edges() {
+        assert_eq!(eval("math.smoothstep(0, 10, 5)"), 0.5);
+        assert_eq!(eval("math.smootherstep(0, 10, 5)"), 0.5);
+        assert!((eval("math.smoothstep(0, 10, 2.5)") - 0.15625).abs() < 1e-12);
+        assert!((eval("math.smootherstep(0, 10, 2.5)") - 0.103515625).abs() < 1e-12);
+        for function in ["smoothstep", "smootherstep"] {
+            assert_eq!(eval(&format!("math.{function}(2, 4, -1)")), 0.0);
+            assert_eq!(eval(&format!("math.{function}(2, 4, 9)")), 1.0);
+            assert_eq!(eval(&format!("math.{function}(3, 3, 3)")), 1.0);
+        }
+    }
+
+    #[test]
+    fn weighted_index_follows_the_weights() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.weights = [1, 0, 3];", &mut ctx).unwrap();
+        let mut counts = [0usize; 3];
+        for _ in 0..4000 {
+            let index = evaluate_expression("math.weighted_index(temp.weights)", &mut ctx).unwrap();
+            counts[index as usize] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        let share = counts[2] as f64 / 4000.0;
+        assert!((share - 0.75).abs() < 0.05, "index 2 picked {share} of the time");
+
+        assert_eq!(eval("math.weighted_index([])"), 0.0);
+        assert_eq!(eval("math.weighted_index([0, 0])"), 0.0);
+        assert_eq!(eval("math.weighted_index([0, -2, 5])"), 2.0);
+    }
+
+    #[test]
+    fn arrays_grow_and_shrink_with_intrinsics() {
+        let mut ctx = RuntimeContext::default();
+        let sum = evaluate_expression(
+            "temp.arr = []; temp.i = 0;
+             loop(5, { temp.i = temp.i + 1; temp.arr = array.push(temp.arr, temp.i * 2); });
+             temp.sum = 0;
+             for_each(temp.v, temp.arr, { temp.sum = temp.sum + temp.v; });
+             return temp.sum;",
+            &mut ctx,
+        )
+        .unwrap();
+        assert_eq!(sum, 30.0);
+        assert_eq!(numbers_at(&ctx, "temp.arr"), vec![2.0, 4.0, 6.0, 8.0, 10.0]);
+
+        evaluate_expression(
+            "temp.arr = array.pop(temp.arr); temp.arr = array.set(temp.arr, -1, 0); \
+             temp.arr = array.set(temp.arr, 0, 'x');",
+            &mut ctx,
+        )
+        .unwrap();
+        let arr = ctx.get_value_canonical("temp.arr").unwrap();
+        assert_eq!(arr.to_string(), "[\"x\", 4, 6, 0]");
+        assert_eq!(evaluate_expression("array.push(temp.arr, 1)", &mut ctx).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn match_selects_equal_case_or_default() {
+        let script = |value: i32| {
+            format!(
+                "temp.n = {value}; return match(temp.n) {{ 1: 10; 2: 20; 3: 30; default: -1 }};"
+            )
+        };
+        assert_eq!(eval(&script(2)), 20.0);
+        assert_eq!(eval(&script(3)), 30.0);
+        assert_eq!(eval(&script(7)), -1.0);
+        assert_eq!(eval("match(5) { 4: 1, 5: 2 }"), 2.0);
+        assert_eq!(eval("match(9) { 4: 1; }"), 0.0);
+        assert_eq!(
+            eval("temp.s = 'b'; return match(temp.s) { 'a': 1; 'b': temp.s == 'b' ? 2 : 3 };"),
+            2.0
+        );
+    }
+
+    #[test]
+    fn match_evaluates_its_subject_once() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut ctx = RuntimeContext::default();
+        ctx.register_function("query.next", move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) as f64 + 3.0
+        });
+        let value = evaluate_expression("return match(query.next()) { 1: 10; 2: 20; 3: 30 };", &mut ctx)
+            .expect("evaluation");
+        assert_eq!(value, 30.0);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let source = "temp.x = match(temp.n + 1) { 1: 'a'; (temp.a ?? 2): 2; default: 0 };\n";
+        let program = parse(source).expect("parse");
+        assert_eq!(format::format_program(&program), source);
+    }
+
+    #[test]
+    fn value_equality_is_structural_with_number_tolerance() {
+        let nested = |z: f64, tag: &str| {
+            let mut inner = IndexMap::new();
+            inner.insert("z".to_string(), Value::number(z));
+            inner.insert("tags".to_string(), Value::array(vec![Value::string(tag), Value::Null]));
+            let mut outer = IndexMap::new();
+            outer.insert("x".to_string(), Value::number(1.0));
+            outer.insert("inner".to_string(), Value::Struct(inner));
+            Value::Struct(outer)
+        };
+        assert_eq!(nested(2.0, "a"), nested(2.0, "a"));
+        assert_eq!(nested(0.1 + 0.2, "a"), nested(0.3, "a"));
+        assert_ne!(nested(2.0, "a"), nested(2.5, "a"));
+        assert_ne!(nested(2.0, "a"), nested(2.0, "b"));
+        assert_eq!(Value::Null, Value::Null);
+        assert_ne!(Value::number(0.0), Value::Null);
+        assert_ne!(Value::string("1"), Value::number(1.0));
+        assert_ne!(
+            Value::array(vec![Value::number(1.0)]),
+            Value::array(vec![Value::number(1.0), Value::number(2.0)])
+        );
+
+        let mut ctx = RuntimeContext::default();
+        let script = "temp.a = {x: 1, y: [1, 2], w: {z: 3}}; \
+                      temp.b = {w: {z: 3}, y: [1, 2], x: 1}; \
+                      temp.c = {x: 1, y: [1, 2], w: {z: 4}};";
+        evaluate_expression(script, &mut ctx).unwrap();
+        assert_eq!(evaluate_expression("temp.a == temp.b", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.a == temp.c", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("temp.a != temp.c", &mut ctx).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn qualified_name_round_trips_through_to_path() {
+        use crate::eval::{Namespace, QualifiedName};
+
+        let inputs: &[&[&str]] = &[
+            &["temp", "x"],
+            &["t", "Pos", "X"],
+            &["v", "a", "b", "c"],
+            &["variable", "speed"],
+            &["context", "other"],
+            &["q", "life_time"],
+            &["speed"],
+            &["Outer", "Inner"],
+            &["temp"],
+            &["query"],
+            &["temp", "temp"],
+            &[],
+        ];
+        for parts in inputs {
+            let parts: Vec<String> = parts.iter().map(|part| part.to_string()).collect();
+            let name = QualifiedName::from_parts(&parts);
+            assert_eq!(QualifiedName::from_parts(&name.to_path()), name, "{parts:?}");
+        }
+
+        let bare = QualifiedName::from_parts(&["temp".to_string()]);
+        assert_eq!(bare, QualifiedName::new(Namespace::Temp, String::new()));
+        assert_eq!(bare.to_path(), vec!["temp".to_string()]);
+        assert_eq!(bare.to_string(), "temp");
+        let bare_variable = QualifiedName::from_parts(&["Speed".to_string()]);
+        assert_eq!(bare_variable.to_path(), vec!["variable".to_string(), "speed".to_string()]);
+    }
+
+    #[test]
+    fn string_comparisons_are_lexicographic() {
+        assert_eq!(eval("'apple' < 'banana'"), 1.0);
+        assert_eq!(eval("'b' < 'a'"), 0.0);
+        assert_eq!(eval("'b' >= 'b'"), 1.0);
+        assert_eq!(eval("'Zebra' < 'apple'"), 1.0);
+
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.a = 'apple'; temp.b = 'banana'; temp.n = 3;", &mut ctx).unwrap();
+        assert_eq!(evaluate_expression("temp.a < temp.b", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.b <= temp.a", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("temp.a > 'aardvark'", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("'cherry' > temp.b", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("'avocado' < temp.a", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("temp.n > 2", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.n > temp.a", &mut ctx).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn nested_query_numbers_build_structs() {
+        let mut ctx = RuntimeContext::default();
+        ctx.set_query_number_nested("entity.health", 20.0);
+        ctx.set_query_number_nested("Entity.Armor", 5.0);
+        assert_eq!(evaluate_expression("query.entity.health", &mut ctx).unwrap(), 20.0);
+        assert_eq!(evaluate_expression("q.entity.armor + q.entity.health", &mut ctx).unwrap(), 25.0);
+
+        let entity = ctx.get_value_canonical("query.entity").unwrap();
+        assert_eq!(entity.to_string(), "{health: 20, armor: 5}");
+
+        ctx.set_query_number_nested("entity.health", 12.0);
+        assert_eq!(evaluate_expression("query.entity.health", &mut ctx).unwrap(), 12.0);
+        assert_eq!(evaluate_expression("query.entity.armor", &mut ctx).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn math_frac_keeps_sign() {
+        assert_eq!(eval("math.frac(2.75)"), 0.75);
+        assert_eq!(eval("math.frac(-2.75)"), -0.75);
+        assert_eq!(eval("math.frac(4)"), 0.0);
+    }
+
+    #[test]
+    fn math_ratio_is_zero_for_zero_whole() {
+        assert_eq!(eval("math.ratio(1, 4)"), 0.25);
+        assert_eq!(eval("math.ratio(1, 0)"), 0.0);
+        assert_eq!(eval("math.ratio(-3, 2)"), -1.5);
+
+        let mut ctx = RuntimeContext::default().with_query("hp", 5.0).with_query("max_hp", 0.0);
+        assert_eq!(evaluate_expression("math.ratio(query.hp, query.max_hp)", &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn clear_namespace_and_snapshot_restore() {
+        use crate::eval::Namespace;
+
+        let mut ctx = RuntimeContext::default().with_query("speed", 2.0);
+        evaluate_expression("temp.scratch = 1; variable.kept = 2; context.owner = 3;", &mut ctx)
+            .unwrap();
+        ctx.clear_namespace(Namespace::Temp);
+        assert!(ctx.get_value_canonical("temp.scratch").is_none());
+        assert_eq!(ctx.get_number_canonical("variable.kept"), Some(2.0));
+        assert_eq!(ctx.get_number_canonical("context.owner"), Some(3.0));
+        assert_eq!(ctx.get_number_canonical("query.speed"), Some(2.0));
+        assert_eq!(Namespace::from_prefix("V"), Some(Namespace::Variable));
+        assert_eq!(Namespace::from_prefix("nope"), None);
+
+        let snapshot = ctx.snapshot();
+        evaluate_expression("temp.scratch = 5; variable.kept = 9; variable.extra = 1;", &mut ctx)
+            .unwrap();
+        ctx.restore(snapshot);
+        assert!(ctx.get_value_canonical("temp.scratch").is_none());
+        assert!(ctx.get_value_canonical("variable.extra").is_none());
+        assert_eq!(ctx.get_number_canonical("variable.kept"), Some(2.0));
+    }
+
+    #[test]
+    fn comment_only_lines_are_blank() {
+        assert!(lexer::is_blank("// note"));
+        assert!(lexer::is_blank("  /* block */ // and a line\n"));
+        assert!(lexer::is_blank(""));
+        assert!(!lexer::is_blank("1 // trailing comment"));
+        assert!(!lexer::is_blank(":help"));
+        assert!(!lexer::is_blank("/* unterminated"));
+
+        let mut ctx = RuntimeContext::default();
+        assert_eq!(evaluate_expression("// note", &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn math_approx_equal_uses_given_tolerance() {
+        assert_eq!(eval("math.approx_equal(0.1 + 0.2, 0.3, 0.001)"), 1.0);
+        assert_eq!(eval("math.approx_equal(1, 1.5, 0.1)"), 0.0);
+        assert_eq!(eval("math.approx_equal(2, 2.5, 0.5)"), 1.0);
+        assert_eq!(eval("math.approx_equal(0.1 + 0.2, 0.3, 0.0001) == 1"), 1.0);
+        assert_eq!(eval("math.approx_equal(0.3001, 0.3, 0.000001)"), 0.0);
+    }
+
+    #[test]
+    fn cache_clears_programs_and_expressions_separately() {
+        let compile_program = |source: &str| {
+            let tokens = lexer::lex(source).unwrap();
+            let program = parser::Parser::new(&tokens).parse_program().unwrap();
+            let ir = IrBuilder::default().lower_program(&program).unwrap();
+            jit_cache::compile_program_cached(source, &ir).unwrap()
+        };
+
+        jit_cache::clear_cache();
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("query.a + 1", &mut ctx).unwrap();
+        let program = "temp.x = 1; loop(3, temp.x = temp.x * 2); return temp.x;";
+        let compiled = compile_program(program);
+        assert_eq!(compiled.evaluate(&mut ctx).unwrap(), 8.0);
+        assert!(Arc::ptr_eq(&compiled, &compile_program(program)));
+        assert_eq!(jit_cache::cache_size(), 2);
+
+        jit_cache::clear_programs();
+        assert_eq!(jit_cache::cache_size(), 1);
+        evaluate_expression("query.a + 1", &mut ctx).unwrap();
+        assert_eq!(jit_cache::cache_size(), 1);
+
+        compile_program(program);
+        jit_cache::clear_expressions();
+        assert_eq!(jit_cache::cache_size(), 1);
+        jit_cache::clear_cache();
+        assert_eq!(jit_cache::cache_size(), 0);
+    }
+
+    #[test]
+    fn numeric_equality_tolerates_rounding_in_every_form() {
+        assert_eq!(eval("(0.1 + 0.2) == 0.3"), 1.0);
+        assert_eq!(eval("temp.x = 0.1 + 0.2; return temp.x == 0.3;"), 1.0);
+        assert_eq!(eval("(0.1 + 0.2) != 0.3"), 0.0);
+        assert_eq!(eval("temp.x = 0.1 + 0.2; return temp.x != 0.3;"), 0.0);
+        assert_eq!(eval("1 == 1.001"), 0.0);
+        assert_eq!(eval("1 != 1.001"), 1.0);
+
+        let mut ctx = RuntimeContext::default()
+            .with_query("inf", f64::INFINITY)
+            .with_query("nan", f64::NAN);
+        assert_eq!(evaluate_expression("query.inf == query.inf", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("query.nan == query.nan", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("query.inf + 1 == query.inf", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("query.nan + 0 == query.nan", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("query.nan != 1", &mut ctx).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn constant_builtin_calls_fold_at_lower_time() {
+        let lower = |source: &str| {
+            let tokens = lexer::lex(source).unwrap();
+            let expr = parser::Parser::new(&tokens).parse_expression().unwrap();
+            IrBuilder::default().lower(&expr).unwrap()
+        };
+        assert!(matches!(lower("math.abs(-3)"), ir::IrExpr::Constant(value) if value == 3.0));
+        assert!(matches!(
+            lower("math.max(math.floor(2.7), math.sign(-5))"),
+            ir::IrExpr::Constant(value) if value == 2.0
+        ));
+        assert!(matches!(lower("math.abs(query.x)"), ir::IrExpr::Call { .. }));
+        assert!(matches!(lower("math.random(0, 1)"), ir::IrExpr::Call { .. }));
+        assert!(matches!(lower("math.die_roll(1, 1, 6)"), ir::IrExpr::Call { .. }));
+        assert!(matches!(lower("math.noise(1.5)"), ir::IrExpr::Call { .. }));
+        assert_eq!(eval("math.abs(-3) + math.pi * 0"), 3.0);
+    }
+
+    #[test]
+    fn math_distance_between_array_points() {
+        assert_eq!(eval("math.distance([0, 0], [3, 4])"), 5.0);
+        assert_eq!(eval("math.distance_squared([0, 0], [3, 4])"), 25.0);
+        assert_eq!(eval("math.distance([1, 2, 3], [1, 2, 3])"), 0.0);
+        assert_eq!(eval("math.distance([0, 0, 0], [2, 3, 6])"), 7.0);
+        assert_eq!(
+            eval("temp.a = [1, 1]; temp.b = [4, 5]; return math.distance(temp.a, temp.b);"),
+            5.0
+        );
+    }
+
+    #[test]
+    fn struct_remove_deletes_a_field() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.s = {a: 1, b: 2}; temp.s = struct.remove(temp.s, 'a');", &mut ctx)
+            .unwrap();
+        let Some(Value::Struct(fields)) = ctx.get_value_canonical("temp.s") else {
+            panic!("temp.s should stay a struct");
+        };
+        assert_eq!(fields.len(), 1);
+        assert!(ctx.get_value_canonical("temp.s.a").is_none());
+        assert_eq!(evaluate_expression("temp.s.a ?? -1", &mut ctx).unwrap(), -1.0);
+        assert_eq!(evaluate_expression("temp.s.b", &mut ctx).unwrap(), 2.0);
+
+        evaluate_expression("temp.s.c = 3; temp.s = struct.remove(temp.s, 'missing');", &mut ctx)
+            .unwrap();
+        assert_eq!(ctx.get_value_canonical("temp.s").unwrap().to_string(), "{b: 2, c: 3}");
+    }
+
+    #[test]
+    fn reactive_program_validates_required_queries() {
+        let ctx = RuntimeContext::default().with_query("a", 1.0);
+        let program = ReactiveProgram::new("query.a + query.b", ctx.clone()).unwrap();
+        assert_eq!(program.required_queries(), ["query.a", "query.b"]);
+        let error = program.validate(&ctx).unwrap_err();
+        assert!(
+            matches!(&error, MolangError::Lower(ir::LowerError::MissingQuery { name }) if name == "query.b"),
+            "{error}"
+        );
+        assert!(error.to_string().contains("query.b"));
+        assert!(program.validate(&ctx.clone().with_query("b", 2.0)).is_ok());
+
+        let mut resolved = ctx.clone();
+        resolved.set_fallback_resolver(|_, key| (key == "b").then(|| Value::number(0.0)));
+        assert!(program.validate(&resolved).is_ok());
+
+        let script = "temp.t = 0; loop(q.count, { temp.t = temp.t + query.Step; }); return temp.t;";
+        let looped = ReactiveProgram::new(script, RuntimeContext::default()).unwrap();
+        assert_eq!(looped.required_queries(), ["query.count", "query.step"]);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trips_through_a_namespace() {
+        use crate::eval::Namespace;
+
+        let config = serde_json::json!({
+            "speed": 2.5,
+            "name": "zombie",
+            "target": null,
+            "entity": { "health": 20, "tags": ["undead", "hostile"], "pos": { "x": 1, "y": 64 } }
+        });
+        let mut ctx = RuntimeContext::default();
+        ctx.load_json(Namespace::Query, config.as_object().unwrap());
+        assert_eq!(evaluate_expression("query.entity.pos.y + query.speed", &mut ctx).unwrap(), 66.5);
+        assert_eq!(evaluate_expression("query.entity.tags.length", &mut ctx).unwrap(), 2.0);
+
+        let exported = serde_json::Value::Object(ctx.namespace_to_json(Namespace::Query));
+        assert_eq!(exported, config);
+        assert!(ctx.namespace_to_json(Namespace::Temp).is_empty());
+
+        evaluate_expression("variable.result = {a: 1, b: [2, 'x']}; variable.result.c = 3;", &mut ctx)
+            .unwrap();
+        let variables = ctx.namespace_to_json(Namespace::Variable);
+        assert_eq!(
+            serde_json::Value::Object(variables),
+            serde_json::json!({ "result": { "a": 1, "b": [2, "x"], "c": 3 } })
+        );
+        assert_eq!(Value::number(f64::NAN).to_json(), serde_json::Value::Null);
+        assert_eq!(Value::from_json(&serde_json::json!({"Key": true})).to_string(), "{key: 1}");
+    }
+
+    #[test]
+    fn return_inside_loop_body_exits_the_script() {
+        assert_eq!(eval("loop(10, { return 5; });"), 5.0);
+        assert_eq!(eval("loop(10, { return 5; temp.never = 1; }); return -1;"), 5.0);
+        assert_eq!(eval("loop(3, { loop(2, { return 9; }); }); return -1;"), 9.0);
+        assert_eq!(eval("for_each(temp.x, [4, 5], { return temp.x; }); return -1;"), 4.0);
+
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.n = 0; loop(10, { temp.n = temp.n + 1; return temp.n; });", &mut ctx)
+            .unwrap();
+        assert_eq!(ctx.get_number_canonical("temp.n"), Some(1.0));
+    }
+
+    #[test]
+    fn math_clamp01_saturates_to_unit_range() {
+        assert_eq!(eval("math.clamp01(-0.5)"), 0.0);
+        assert_eq!(eval("math.clamp01(0.5)"), 0.5);
+        assert_eq!(eval("math.clamp01(2.0)"), 1.0);
+        let mut ctx = RuntimeContext::default().with_query("t", 1.5);
+        assert_eq!(evaluate_expression("math.clamp01(query.t)", &mut ctx).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn last_expression_is_the_result_without_return() {
+        assert_eq!(eval("temp.x = 5; temp.x + 1;"), 6.0);
+        assert_eq!(eval("temp.x = 5; temp.x * 2"), 10.0);
+        assert_eq!(eval("temp.x = 5; return 1; temp.x;"), 1.0);
+        assert_eq!(eval("temp.x = 5;"), 0.0);
+
+        let mut ctx = RuntimeContext::default();
+        let value = evaluate_value("temp.name = 'steve'; temp.name;", &mut ctx).unwrap();
+        assert_eq!(value, Value::string("steve"));
+    }
+
+    #[test]
+    fn math_round_to_decimal_places() {
+        assert_eq!(eval("math.round_to(3.14159, 2)"), 3.14);
+        assert_eq!(eval("math.round_to(1234, -2)"), 1200.0);
+        assert_eq!(eval("math.round_to(2.5, 0)"), 3.0);
+        assert_eq!(eval("math.round_to(-1.005, 1)"), -1.0);
+        assert_eq!(eval("math.round_to(1250, -3)"), 1000.0);
+        // Scales that overflow leave the value finite instead of NaN
+        assert_eq!(eval("math.round_to(1.5, 400)"), 1.5);
+        assert_eq!(eval("math.round_to(123456789, 305)"), 123456789.0);
+        assert_eq!(eval("math.round_to(5, -400)"), 0.0);
+    }
+
+    #[test]
+    fn call_errors_point_at_the_call_site() {
+        let mut ctx = RuntimeContext::default();
+        let input = "1 + math.foo(1)";
+        let error = evaluate_expression(input, &mut ctx).unwrap_err();
+        assert_eq!(error.to_string(), "unknown function `math.foo` at 4..14");
+        assert_eq!(error.location(input), Some((1, 5)));
+
+        let input = "temp.x = 1;\nreturn math.cos(1, 2);";
+        let error = evaluate_expression(input, &mut ctx).unwrap_err();
+        assert!(
+            matches!(
+                &error,
+                MolangError::Lower(ir::LowerError::InvalidArgumentCount {
+                    span: Some(lexer::Span { start: 19, end: 32 }),
+                    ..
+                })
+            ),
+            "{error}"
+        );
+        assert!(error.to_string().ends_with("at 19..32"), "{error}");
+        assert_eq!(error.location(input), Some((2, 8)));
+    }
+
+    #[test]
+    fn wrong_arity_builtin_fails_on_every_path() {
+        let mut ctx = RuntimeContext::default();
+        for input in [
+            "math.cos(1, 2, 3)",
+            "temp.x = math.cos(1, 2, 3); return temp.x;",
+            "math.abs(temp.unset, 1)",
+        ] {
+            assert!(
+                matches!(
+                    evaluate_expression(input, &mut ctx),
+                    Err(MolangError::Lower(ir::LowerError::InvalidArgumentCount { .. }))
+                ),
+                "{input}"
+            );
+        }
+        assert!(evaluate_value("math.cos()", &mut ctx).is_err());
+    }
+
+    #[test]
+    fn hyperbolic_builtins_take_plain_values() {
+        let mut ctx = RuntimeContext::default().with_query("x", 1.0);
+        assert_eq!(evaluate_expression("math.tanh(0)", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("math.sinh(0)", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("math.cosh(0)", &mut ctx).unwrap(), 1.0);
+        let sinh = evaluate_expression("math.sinh(query.x)", &mut ctx).unwrap();
+        assert!((sinh - 1.0f64.sinh()).abs() < 1e-12);
+        let cosh = evaluate_expression("math.cosh(query.x)", &mut ctx).unwrap();
+        assert!((cosh - 1.0f64.cosh()).abs() < 1e-12);
+        let tanh = evaluate_expression("math.tanh(query.x)", &mut ctx).unwrap();
+        assert!((tanh - 1.0f64.tanh()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn non_finite_loop_counts_run_zero_times() {
+        let script = "temp.n = 0; loop(query.count, temp.n = temp.n + 1); return temp.n;";
+        for (count, expected) in [
+            (f64::NAN, 0.0),
+            (f64::NEG_INFINITY, 0.0),
+            (-5.0, 0.0),
+            (f64::INFINITY, 0.0),
+            (1e300, 1024.0),
+        ] {
+            let mut ctx = RuntimeContext::default().with_query("count", count);
+            assert_eq!(evaluate_expression(script, &mut ctx).unwrap(), expected, "{count}");
+        }
+    }
+
+    #[test]
+    fn max_and_min_accept_more_than_two_arguments() {
+        let mut ctx = RuntimeContext::default().with_query("x", 4.0);
+        assert_eq!(evaluate_expression("math.max(1, 7, 3, 5)", &mut ctx).unwrap(), 7.0);
+        assert_eq!(evaluate_expression("math.min(query.x, 7, -3, 5)", &mut ctx).unwrap(), -3.0);
+        assert_eq!(
+            evaluate_expression("temp.m = math.max(query.x, 2, query.x * 2); return temp.m;", &mut ctx)
+                .unwrap(),
+            8.0
+        );
+        assert!(matches!(
+            evaluate_expression("math.max(1)", &mut ctx),
+            Err(MolangError::Lower(ir::LowerError::InvalidArgumentCount { .. }))
+        ));
+    }
+
+    #[test]
+    fn array_membership_queries() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.nums = [3, 5, 0.1 + 0.2]; temp.names = ['ann', 'bob'];", &mut ctx)
+            .unwrap();
+        assert_eq!(evaluate_expression("temp.nums.contains(5)", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.nums.contains(4)", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("temp.nums.index_of(0.3)", &mut ctx).unwrap(), 2.0);
+        assert_eq!(evaluate_expression("temp.nums.index_of(7)", &mut ctx).unwrap(), -1.0);
+        assert_eq!(evaluate_expression("temp.names.contains('bob')", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.names.index_of('bob')", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.names.index_of('Bob')", &mut ctx).unwrap(), -1.0);
+        assert_eq!(
+            evaluate_expression("array.index_of(temp.names, 'ann')", &mut ctx).unwrap(),
+            0.0
+        );
+        assert_eq!(evaluate_expression("temp.missing.contains(1)", &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn map_range_remaps_between_ranges() {
+        let mut ctx = RuntimeContext::default().with_query("hp", 25.0);
+        let mut map = |args: &str| {
+            evaluate_expression(&format!("math.map_range({args})"), &mut ctx).unwrap()
+        };
+        assert_eq!(map("query.hp, 0, 100, 0, 1"), 0.25);
+        assert_eq!(map("100, 0, 100, 0, 1"), 1.0);
+        assert_eq!(map("query.hp, 0, 100, 10, -10"), 5.0);
+        // Out-of-range inputs extrapolate; an empty input range yields `out_min`
+        assert_eq!(map("150, 0, 100, 0, 1"), 1.5);
+        assert_eq!(map("query.hp, 5, 5, 2, 3"), 2.0);
+
+        let script = "temp.t = math.map_range(query.hp, 0, 50, 0, 1); return temp.t;";
+        assert_eq!(evaluate_expression(script, &mut ctx).unwrap(), 0.5);
+    }
+
+    #[test]
+    fn strict_indexing_rejects_non_arrays() {
+        let script = "temp.n = 5; temp.s = 'hi'; return temp.n[0];";
+        let mut lenient = RuntimeContext::default();
+        assert_eq!(evaluate_expression(script, &mut lenient).unwrap(), 0.0);
+
+        let mut strict = RuntimeContext::default().strict_indexing(true);
+        assert!(matches!(
+            evaluate_expression(script, &mut strict),
+            Err(MolangError::Exec(ExecError::NotIndexable { name, type_name: "number" })) if name == "temp.n"
+        ));
+        let error = evaluate_expression("return temp.s[1];", &mut strict).unwrap_err();
+        assert_eq!(error.to_string(), "cannot index `temp.s`, which holds a string");
+        // Arrays and unset paths still index as usual
+        assert_eq!(evaluate_expression("temp.a = [4, 5]; return temp.a[1];", &mut strict).unwrap(), 5.0);
+        assert_eq!(evaluate_expression("return temp.unset[0];", &mut strict).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn struct_literal_members_resolve_at_compile_time() {
+        let mut ctx = RuntimeContext::default().with_query("x", 3.0);
+        assert_eq!(evaluate_expression("return {a: 5, b: 10}.b;", &mut ctx).unwrap(), 10.0);
+        assert_eq!(evaluate_expression("1 + {a: query.x * 2}.A", &mut ctx).unwrap(), 7.0);
+        assert_eq!(evaluate_expression("return {outer: {inner: 4}}.outer.inner;", &mut ctx).unwrap(), 4.0);
+        assert_eq!(evaluate_expression("return {a: 5}.missing;", &mut ctx).unwrap(), 0.0);
+        assert!(evaluate_expression("math.abs(1).x", &mut ctx).is_err());
+
+        // Bracket indexing of a literal is not a field read
+        let program = parse("({x: 5}[x]);").unwrap();
+        let ast::Statement::Expr(expr) = &program.statements[0] else { panic!() };
+        assert!(matches!(expr, ast::Expr::Index { .. }), "{expr:?}");
+    }
+
+    #[test]
+    fn merge_combines_contexts() {
+        let mut base = RuntimeContext::default().with_query("speed", 1.0);
+        let script = "temp.pos.x = 1; temp.pos.y = 2; variable.hp = 10; temp.only_base = 1;";
+        evaluate_expression(script, &mut base).unwrap();
+        let mut other = RuntimeContext::default().with_query("speed", 5.0);
+        let script = "temp.pos.y = 20; temp.pos.z = 30; variable.hp = 99; context.owner = 7;";
+        evaluate_expression(script, &mut other).unwrap();
+
+        let mut kept = base.clone();
+        kept.merge(&other, false);
+        assert_eq!(kept.get_number_canonical("query.speed"), Some(1.0));
+        assert_eq!(kept.get_number_canonical("variable.hp"), Some(10.0));
+        assert_eq!(kept.get_number_canonical("context.owner"), Some(7.0));
+        assert_eq!(kept.get_number_canonical("temp.only_base"), Some(1.0));
+        // Disjoint nested fields join the existing struct
+        let sum = evaluate_expression("temp.pos.x + temp.pos.y + temp.pos.z", &mut kept).unwrap();
+        assert_eq!(sum, 33.0);
+        let pos = kept.get_value_canonical("temp.pos").unwrap();
+        assert_eq!(pos.to_string(), "{x: 1, y: 2, z: 30}");
+
+        let mut replaced = base.clone();
+        replaced.merge(&other, true);
+        assert_eq!(replaced.get_number_canonical("query.speed"), Some(5.0));
+        assert_eq!(replaced.get_number_canonical("variable.hp"), Some(99.0));
+        assert_eq!(replaced.get_number_canonical("temp.only_base"), Some(1.0));
+        // The incoming struct replaces the old one, stale fields included
+        assert!(replaced.get_value_canonical("temp.pos.x").is_none());
+        let pos = replaced.get_value_canonical("temp.pos").unwrap();
+        assert_eq!(pos.to_string(), "{y: 20, z: 30}");
+    }
+
+    #[test]
+    fn array_sum_and_count_methods() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.arr = [1, 2, 3, 4]; temp.s = { a: 1, b: 2 };", &mut ctx).unwrap();
+        assert_eq!(evaluate_expression("temp.arr.sum()", &mut ctx).unwrap(), 10.0);
+        assert_eq!(evaluate_expression("array.sum(temp.arr)", &mut ctx).unwrap(), 10.0);
+        assert_eq!(evaluate_expression("temp.arr.count()", &mut ctx).unwrap(), 4.0);
+        assert_eq!(evaluate_expression("temp.arr.count(3)", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.s.count()", &mut ctx).unwrap(), 2.0);
+        assert_eq!(evaluate_expression("temp.missing.sum()", &mut ctx).unwrap(), 0.0);
+        let script = "temp.arr = array.push(temp.arr, 5); return temp.arr.sum() / temp.arr.count();";
+        assert_eq!(evaluate_expression(script, &mut ctx).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn parse_returns_the_syntax_tree() {
+        use crate::ast::{BinaryOp, Expr, Statement};
+
+        let program = parse("temp.n = 0; loop(3, { temp.n = temp.n + 1; }); return temp.n;").unwrap();
+        assert_eq!(program.statements.len(), 3);
+        assert!(matches!(
+            &program.statements[0],
+            Statement::Assignment { target, value: Expr::Number(n) } if target == &["temp", "n"] && *n == 0.0
+        ));
+        let Statement::Loop { count, body, .. } = &program.statements[1] else {
+            panic!("expected a loop, got {:?}", program.statements[1]);
+        };
+        assert_eq!(count, &Expr::Number(3.0));
+        assert!(matches!(
+            body.as_ref(),
+            Statement::Block(inner) if matches!(
+                &inner[..],
+                [Statement::Assignment { value: Expr::Binary { op: BinaryOp::Add, .. }, .. }]
+            )
+        ));
+        assert!(matches!(&program.statements[2], Statement::Return(Some(Expr::Path(_)))));
+
+        assert!(matches!(parse("temp.x = ;"), Err(MolangError::Parse(_))));
+        assert!(matches!(parse("'open"), Err(MolangError::Lex(_))));
+        // Parsing does not resolve functions
+        assert!(parse("math.nope(1)").is_ok());
+    }
+
+    #[test]
+    fn format_is_canonical_and_idempotent() {
+        let script = "temp.total=0;temp.values=[1,2,3];temp.p={x:1,'two words':2,s:'it\\'s'};\n\
+            for_each(temp.v,temp.values,{temp.total+=temp.v*(2+1);(temp.v>=2)?break;});\n\
+            loop(2,temp.total=temp.total-1);// comment\n\
+            temp.size=temp.total>5?3:temp.total>2?2:1;\n\
+            temp.pick=(query.a??query.b)??(query.c?1:2);temp.n=-(temp.total-1)&&!query.flag;\n\
+            return {a:temp.p.x,b:[temp.values[0],math.max(1,2,3)]}.a+temp.values.length;";
+        let formatted = format(script).unwrap();
+        assert_eq!(
+            formatted,
+            "temp.total = 0;\n\
+             temp.values = [1, 2, 3];\n\
+             temp.p = { x: 1, 'two words': 2, s: 'it\\'s' };\n\
+             for_each(temp.v, temp.values, {\n\
+            \x20 temp.total = temp.total + temp.v * (2 + 1);\n\
+            \x20 temp.v >= 2 ? break;\n\
+             });\n\
+             loop(2, temp.total = temp.total - 1);\n\
+             temp.size = temp.total > 5 ? 3 : temp.total > 2 ? 2 : 1;\n\
+             temp.pick = query.a ?? query.b ?? (query.c ? 1 : 2);\n\
+             temp.n = -(temp.total - 1) && !query.flag;\n\
+             return { a: temp.p.x, b: [temp.values[0], math.max(1, 2, 3)] }.a + temp.values.length;\n"
+        );
+        assert_eq!(format(&formatted).unwrap(), formatted);
+
+        let mut original = RuntimeContext::default().with_query("a", 4.0);
+        let mut reformatted = original.clone();
+        assert_eq!(
+            evaluate_expression(script, &mut original).unwrap(),
+            evaluate_expression(&formatted, &mut reformatted).unwrap()
+        );
+        for name in ["temp.size", "temp.pick", "temp.p"] {
+            assert_eq!(original.get_value_canonical(name), reformatted.get_value_canonical(name));
+        }
+
+        // Parentheses survive where precedence needs them, and bodies that must be blocks stay blocks
+        for source in [
+            "(a ? b : c) ?? d;",
+            "a ? (b ? c) : d;",
+            "(a + b) * -(c - d);",
+            "a ?? (b ? c : d) ?? e;",
+            "loop(2, {\n  return 1;\n});",
+            "({ a: 1 }.a);",
+            "({ a: 1 }[a]);",
+        ] {
+            let formatted = format(source).unwrap();
+            assert_eq!(formatted, format!("{source}\n"), "{source}");
+        }
+    }
+
+    #[test]
+    fn token_text_covers_multi_character_operators() {
+        let line = "1 == 2 != 3 <= a >= 0xF && 'x' || b ?? c -> d";
+        let tokens = lexer::lex(line).unwrap();
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text(line)).collect();
+        assert_eq!(
+            texts,
+            ["1", "==", "2", "!=", "3", "<=", "a", ">=", "0xF", "&&", "'x'", "||", "b", "??", "c", "->", "d", ""]
+        );
+    }
+
+    #[test]
+    fn indexed_assignment_replaces_one_element() {
+        let mut ctx = RuntimeContext::default();
+        let value = evaluate_expression(
+            "temp.arr = [1, 2, 3]; temp.arr[1] = 9; temp.arr[-1] += 10; return temp.arr[1] * 100 + temp.arr[2];",
+            &mut ctx,
+        )
+        .expect("evaluation");
+        assert_eq!(value, 913.0);
+
+        let program = parse("temp.arr[0] = 4; temp.arr[i] *= 2;").expect("parse");
+        assert_eq!(
+            format::format_program(&program),
+            "temp.arr[0] = 4;\ntemp.arr[i] *= 2;\n"
+        );
+        assert!(parse("[1, 2][0] = 3;").is_err());
+    }
+
+    #[test]
+    fn indexed_assignment_evaluates_its_index_once() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut ctx = RuntimeContext::default();
+        ctx.register_function("query.next", move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst) as f64
+        });
+        let value = evaluate_expression(
+            "temp.arr = [1, 1]; temp.arr[query.next()] += 5; return temp.arr[0] * 10 + temp.arr[1];",
+            &mut ctx,
+        )
+        .expect("evaluation");
+        assert_eq!(value, 61.0);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        evaluate_expression(
+            "temp.s.arr = [1, 2]; temp.s.arr[0] = 'a'; temp.s.arr[1] = [3];",
+            &mut ctx,
+        )
+        .expect("evaluation");
+        assert_eq!(
+            ctx.get_struct("temp.s").and_then(|s| s.get("arr")).cloned(),
+            Some(Value::array(vec![Value::string("a"), Value::array(vec![Value::number(3.0)])]))
+        );
+    }
+
+    #[test]
+    fn indexed_assignment_leaves_non_arrays_untouched() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.n = 4; temp.n[0] = 1; temp.unset[2] = 1;", &mut ctx)
+            .expect("evaluation");
+        assert_eq!(ctx.get_value_canonical("temp.n"), Some(Value::number(4.0)));
+        assert_eq!(ctx.get_value_canonical("temp.unset"), None);
+        assert!(matches!(
+            ctx.take_diagnostics().as_slice(),
+            [Diagnostic::NotIndexable { type_name: "number", .. }, Diagnostic::NotIndexable { type_name: "null", .. }]
+        ));
+
+        let mut ctx = RuntimeContext::default().strict_indexing(true);
+        let err = evaluate_expression("temp.n = 4; temp.n[0] = 1;", &mut ctx).unwrap_err();
+        assert!(matches!(err, MolangError::Exec(ExecError::NotIndexable { .. })));
+    }
+
+    #[test]
+    fn builtin_registry_lists_every_path() {
+        use crate::ir::{BuiltinFunction, ValueFunction};
+        let builtins = list_builtins();
+        assert!(builtins.contains(&("math.clamp", 3)));
+        assert!(builtins.contains(&("math.pi", 0)));
+        assert!(builtins.contains(&("math.distance", 2)));
+        assert!(builtins.contains(&("array.push", 2)));
+        assert!(builtins.contains(&("typeof", 1)));
+        assert!(!builtins.iter().any(|(name, _)| *name == "count" || *name == "+"));
+
+        // Every listed path resolves back to a function with that name and arity
+        for (name, arity) in &builtins {
+            let path: Vec<String> = name.split('.').map(str::to_string).collect();
+            let resolved = BuiltinFunction::from_path(&path)
+                .map(|builtin| (builtin.name(), builtin.arity()))
+                .or_else(|| ValueFunction::from_path(&path).map(|f| (f.name(), f.arity())));
+            assert_eq!(resolved, Some((*name, *arity)), "{name}");
+        }
+        let mut names: Vec<_> = builtins.iter().map(|(name, _)| *name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), builtins.len());
+        let scripted = ValueFunction::ALL.iter().filter(|f| f.path().is_some()).count();
+        assert_eq!(builtins.len(), BuiltinFunction::all().len() + scripted);
+
+        let alias = ["math".to_string(), "lerp_rotate".to_string()];
+        assert_eq!(BuiltinFunction::from_path(&alias), Some(BuiltinFunction::MathLerpRotate));
+    }
+
+    #[test]
+    fn not_keyword_negates_only_at_expression_start() {
+        let mut ctx = RuntimeContext::default().with_query("flag", 1.0);
+        assert_eq!(evaluate_expression("not 0", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("!0 + !1", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("NOT query.flag", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("not (1 > 2) && 1", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("not -1", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("not +0", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("not {a: 0}.a", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("temp.not = 5; return temp.not;", &mut ctx).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn seeded_rng_repeats_its_sequence() {
+        // Seeded contexts draw from their own generator, unaffected by concurrent tests
+        let draw = |ctx: &mut RuntimeContext| {
+            evaluate_value("[math.random(0, 1), math.random(0, 1), math.die_roll(2, 1, 6)]", ctx)
+                .expect("evaluation")
+        };
+        let mut ctx = RuntimeContext::default().with_rng_seed(42);
+        let first = draw(&mut ctx);
+        assert_eq!(first, draw(&mut RuntimeContext::default().with_rng_seed(42)));
+        assert_ne!(first, draw(&mut ctx));
+        ctx.seed_rng(42);
+        assert_eq!(first, draw(&mut ctx));
+        assert_ne!(first, draw(&mut RuntimeContext::default().with_rng_seed(7)));
+        let items = first.as_array().expect("array").to_vec();
+        assert_ne!(items[0], items[1]);
+
+        let noise = |seed| {
+            let mut ctx = RuntimeContext::default().with_rng_seed(seed);
+            evaluate_expression("math.noise(3.7)", &mut ctx).expect("evaluation")
+        };
+        assert_eq!(noise(5), builtins::math_noise(3.7, 5));
+        assert_eq!(noise(6), builtins::math_noise(3.7, 6));
+    }
+
+    #[test]
+    fn type_predicates_cover_every_query_value_type() {
+        let mut ctx = RuntimeContext::default()
+            .with_query("n", 2.0)
+            .with_query_value("s", Value::string("hi"))
+            .with_query_value("a", Value::array(vec![Value::number(1.0)]))
+            .with_query_value("st", Value::Struct(IndexMap::new()))
+            .with_query_value("none", Value::Null);
+        let cases = [
+            ("n", 1.0, "number"),
+            ("s", 2.0, "string"),
+            ("a", 3.0, "array"),
+            ("st", 4.0, "struct"),
+            ("none", 0.0, "null"),
+        ];
+        for (name, tag, type_name) in cases {
+            let value = ctx.get_value_canonical(&format!("query.{name}")).expect("query value");
+            assert_eq!(value.type_name(), type_name);
+            let script = format!("return typeof(query.{name});");
+            assert_eq!(evaluate_expression(&script, &mut ctx).unwrap(), tag, "{script}");
+        }
+
+        let predicates = "math.is_number(query.n) + math.is_string(query.s) * 10 + math.is_array(query.a) * 100 + math.is_struct(query.st) * 1000";
+        assert_eq!(evaluate_expression(predicates, &mut ctx).unwrap(), 1111.0);
+        assert_eq!(evaluate_expression("math.is_array(query.n) + math.is_number(query.s)", &mut ctx).unwrap(), 0.0);
+    }
+
+    #[test]
+    fn null_coalesce_only_falls_through_on_missing_values() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut ctx = RuntimeContext::default()
+            .with_query("zero", 0.0)
+            .with_query_value("nothing", Value::Null);
+        ctx.register_function("fallback", move |_: &[f64]| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            7.0
+        });
+
+        assert_eq!(evaluate_expression("query.zero ?? fallback()", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("temp.x = 0; return temp.x ?? fallback();", &mut ctx).unwrap(), 0.0);
+        assert_eq!(evaluate_expression("(1 - 1) ?? fallback()", &mut ctx).unwrap(), 0.0);
+        assert_eq!(calls.load(Ordering::SeqCst), 0, "right side ran for a present value");
+
+        assert_eq!(evaluate_expression("query.nothing ?? fallback()", &mut ctx).unwrap(), 7.0);
+        assert_eq!(evaluate_expression("return temp.unset ?? fallback();", &mut ctx).unwrap(), 7.0);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn loop_binds_the_iteration_index() {
+        let sum = eval("temp.sum = 0; loop(5, temp.i, { temp.sum = temp.sum + temp.i; }); return temp.sum;");
+        assert_eq!(sum, 10.0);
+        // `continue` still advances the index, and the body may be a bare statement
+        let odd = eval("temp.n = 0; loop(6, v.i, { (math.mod(v.i, 2) == 0) ? continue; temp.n += v.i; }); return temp.n;");
+        assert_eq!(odd, 9.0);
+        assert_eq!(eval("temp.total = 0; loop(4, t.i, temp.total += t.i); return temp.total;"), 6.0);
+        // Two arguments keep the plain form, including a path-assignment body
+        assert_eq!(eval("temp.x = 0; loop(3, temp.x = temp.x + 2); return temp.x;"), 6.0);
+
+        let program = parse("loop(2, temp.i, temp.s += temp.i);").expect("parse");
+        assert_eq!(
+            format::format_program(&program),
+            "loop(2, temp.i, temp.s = temp.s + temp.i);\n"
+        );
+    }
+
+    #[test]
+    fn floor_to_and_ceil_to_snap_to_a_step() {
+        let mut ctx = RuntimeContext::default().with_query("step", 0.5).with_query("x", -1.3);
+        assert_eq!(evaluate_expression("math.floor_to(1.3, 0.5)", &mut ctx).unwrap(), 1.0);
+        assert_eq!(evaluate_expression("math.ceil_to(1.3, 0.5)", &mut ctx).unwrap(), 1.5);
+        assert_eq!(evaluate_expression("math.floor_to(query.x, query.step)", &mut ctx).unwrap(), -1.5);
+        assert_eq!(evaluate_expression("math.ceil_to(query.x, query.step)", &mut ctx).unwrap(), -1.0);
+        assert_eq!(evaluate_expression("math.floor_to(query.x, 0)", &mut ctx).unwrap(), -1.3);
+        assert_eq!(evaluate_expression("math.ceil_to(2, 0.5)", &mut ctx).unwrap(), 2.0);
+        // Quotients a rounding error away from a whole step count as that step
+        let floored = evaluate_expression("math.floor_to(0.3, 0.1)", &mut ctx).unwrap();
+        assert!((floored - 0.3).abs() < 1e-9, "{floored}");
+        let ceiled = evaluate_expression("math.ceil_to(2.1, 0.7)", &mut ctx).unwrap();
+        assert!((ceiled - 2.1).abs() < 1e-9, "{ceiled}");
+    }
+
+    #[test]
+    fn cloned_contexts_share_query_data() {
+        let items = (0..10_000).map(|i| Value::number(i as f64)).collect();
+        let base = RuntimeContext::default().with_query_value("big", Value::array(items));
+        let buffer = base.get_array("query.big").expect("query array").as_ptr();
+
+        let mut entity = base.clone();
+        assert_eq!(entity.get_array("query.big").expect("query array").as_ptr(), buffer);
+        assert_eq!(evaluate_expression("query.big[9999]", &mut entity).unwrap(), 9999.0);
+        assert_eq!(entity.get_array("query.big").expect("query array").as_ptr(), buffer);
+
+        // Setting a query on one clone leaves the other untouched and shares the rest
+        entity.set_query_value("speed", 2.0);
+        assert_eq!(entity.get_array("query.big").expect("query array").as_ptr(), buffer);
+        assert_eq!(evaluate_expression("query.speed", &mut entity).unwrap(), 2.0);
+        assert!(base.get_value_canonical("query.speed").is_none());
+        assert_eq!(base.get_array("query.big").expect("query array").as_ptr(), buffer);
+        assert_eq!(entity.get_array("query.big").map(<[Value]>::len), Some(10_000));
+    }
+
+    #[test]
+    fn noise_is_smooth_bounded_and_seeded() {
+        use crate::builtins::math_noise;
+        for seed in [0, 1, 42] {
+            let mut previous = math_noise(-20.0, seed);
+            for step in 1..=4000 {
+                let x = -20.0 + step as f64 * 0.01;
+                let value = math_noise(x, seed);
+                assert!((-1.0..=1.0).contains(&value), "noise({x}) = {value}");
+                assert!((value - previous).abs() < 0.05, "jump at {x}");
+                previous = value;
+            }
+        }
+        assert_eq!(math_noise(3.7, 5), math_noise(3.7, 5));
+        assert_ne!(math_noise(3.7, 5), math_noise(3.7, 6));
+        assert_eq!(math_noise(f64::NAN, 5), 0.0);
+
+        let mut ctx = RuntimeContext::default().with_query("t", 1.25);
+        let value = evaluate_expression("math.noise(query.t)", &mut ctx).unwrap();
+        assert!((-1.0..=1.0).contains(&value));
+    }
+
+    #[test]
+    fn plus_concatenates_string_variables_at_runtime() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression(
+            "temp.a = 'foo'; temp.b = 'bar'; temp.s = temp.a + temp.b; temp.x = 2; temp.n = temp.x + temp.x;",
+            &mut ctx,
+        )
+        .expect("evaluation");
+        assert_eq!(ctx.get_string("temp.s"), Some("foobar"));
+        assert_eq!(ctx.get_value_canonical("temp.n"), Some(Value::number(4.0)));
+
+        // A lone expression follows the same rule as `return`
+        assert_eq!(plan("temp.a + temp.b").unwrap().reason, "may concatenate strings");
+        assert_eq!(evaluate_value("temp.a + temp.b", &mut ctx).unwrap(), Value::string("foobar"));
+        assert_eq!(evaluate_expression("temp.x + temp.n", &mut ctx).unwrap(), 6.0);
+        let sum = "temp.t = 0; loop(4, { temp.t = temp.t + temp.x; }); return temp.t;";
+        assert_eq!(evaluate_expression(sum, &mut ctx).unwrap(), 8.0);
+
+        let value = evaluate_value("temp.a = 'x'; return temp.a + temp.a + temp.a;", &mut ctx)
+            .expect("evaluation");
+        assert_eq!(value, Value::string("xxx"));
+    }
+
+    #[test]
+    fn null_coalesce_tests_element_reads() {
+        let mut ctx = RuntimeContext::default()
+            .strict_reads(true)
+            .with_query_value("a", Value::array(vec![Value::number(1.0), Value::Null]));
+        let cases = [
+            ("temp.arr = [4, 5]; return temp.arr[1] ?? 3;", 5.0),
+            ("temp.arr = [4, 5]; return temp.arr[-2] ?? 3;", 4.0),
+            ("temp.arr = [4, 5]; return temp.arr[9] ?? 3;", 3.0),
+            ("temp.n = 2; return temp.n[0] ?? 3;", 3.0),
+            ("query.a[1] ?? 7", 7.0),
+            ("query.a[0] ?? 7", 1.0),
+            ("temp.never[0] ?? 6", 6.0),
+            ("temp.s = { x: 2 }; return temp.s.x ?? 3;", 2.0),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(evaluate_expression(source, &mut ctx).unwrap(), expected, "{source}");
+        }
+        assert!(ctx.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn returned_values_are_only_captured_for_evaluate_value() {
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.a = [1, 2]; return temp.a;", &mut ctx).expect("evaluation");
+        assert_eq!(ctx.take_return_value(), None);
+
+        let value = evaluate_value("return temp.a;", &mut ctx).expect("evaluation");
+        assert_eq!(value, Value::array(vec![Value::number(1.0), Value::number(2.0)]));
+        assert_eq!(evaluate_value("return 3;", &mut ctx).unwrap(), Value::number(3.0));
+        assert!(evaluate_value("return temp.a + ;", &mut ctx).is_err());
+        evaluate_expression("return temp.a;", &mut ctx).expect("evaluation");
+        assert_eq!(ctx.take_return_value(), None);
+    }
+
+    #[test]
+    fn query_mutation_after_creation() {
+        let mut ctx = RuntimeContext::default().with_query("value", 10.0);
+
+        // Initial value
+        let result = evaluate_expression("return query.value;", &mut ctx).unwrap();
+        assert!((result - 10.0).abs() < 1e-9);
+
+        // Mutate after creation
+        ctx.set_query_value("value", 20.0);
+        let result = evaluate_expression("return query.value;", &mut ctx).unwrap();
+        assert!((result - 20.0).abs() < 1e-9);
+
+        // Add new query values dynamically
+        ctx.set_query_string("type", "dynamic");
+        let stored = ctx.get_value_canonical("query.type");
+        assert!(matches!(stored, Some(Value::String(_))));
+    }
+
+    #[test]
+    fn array_indexing_and_length() {
+        let value = eval(
+            "
+            temp.values = [10, 20, 30];
+            temp.index = 1;
+            temp.sum = temp.values[temp.index] + temp.values[3] + temp.values.length;
+            return temp.sum;
+            ",
+        );
+        assert!((value - 33.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn negative_zero_is_normalized() {
+        assert_eq!(format!("{}", eval("return math.ceil(-0.5);")), "0");
+        assert_eq!(format!("{}", eval("return math.trunc(-0.5);")), "0");
+        assert_eq!(format!("{}", eval("return 0 * -1;")), "0");
+
+        let mut ctx = RuntimeContext::default();
+        evaluate_expression("temp.zero = 0 * -1;", &mut ctx).unwrap();
+        match ctx.get_value_canonical("temp.zero") {
+            Some(Value::Number(value)) => assert!(value.is_sign_positive()),
+            other => panic!("expected number, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn wrap_handles_cyclic_ranges() {
+        assert!((eval("return math.wrap(370, 0, 360);") - 10.0).abs() < 1e-9);
+        assert!((eval("return math.wrap(-10, 0, 360);") - 350.0).abs() < 1e-9);
+        assert!((eval("return math.wrap(5, 2, 2);") - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn evaluates_pre_lexed_tokens() {
+        let scripts = [
+            "1 + math.cos(0) * 2",
+            "temp.values = [1, 2, 3]; temp.total = 0; for_each(temp.v, temp.values, { temp.total = temp.total + temp.v; }); return temp.total;",
+        ];
+        for script in scripts {
+            let tokens = lexer::lex(script).unwrap();
+            let mut ctx = RuntimeContext::default();
+            let from_tokens = evaluate_tokens(&tokens, &mut ctx).unwrap();
+            assert!((from_tokens - eval(script)).abs() < 1e-9, "{script}");
+        }
     }
 
     #[test]
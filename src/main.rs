@@ -1,6 +1,8 @@
-use molang::{eval::RuntimeContext, evaluate_expression, lexer::{lex, TokenKind}};
+use molang::{eval::{Namespace, RuntimeContext}, evaluate_expression, lexer::{is_blank, lex, TokenKind}};
 use nu_ansi_term::{Color, Style};
 use reedline::{DefaultPrompt, DefaultPromptSegment, Highlighter, Reedline, Signal, StyledText};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Check if we're in single-expression mode (command-line argument)
@@ -52,14 +54,12 @@ impl Highlighter for MolangHighlighter {
                         ));
                     }
 
-                    // Bounds check
-                    if token.span.end >= line.len() {
+                    // Get the token text (two-character operators included)
+                    let token_text = token.text(line);
+                    if token_text.is_empty() {
                         continue;
                     }
 
-                    // Get the token text
-                    let token_text = &line[token.span.start..=token.span.end];
-
                     // Apply color based on token kind
                     let style = match token.kind {
                         // Keywords and control flow
@@ -82,11 +82,15 @@ impl Highlighter for MolangHighlighter {
                         TokenKind::Less | TokenKind::LessEqual |
                         TokenKind::Greater | TokenKind::GreaterEqual |
                         TokenKind::AndAnd | TokenKind::OrOr | TokenKind::Bang |
+                        TokenKind::Amp | TokenKind::Pipe | TokenKind::Caret |
                         TokenKind::Question | TokenKind::QuestionQuestion => {
                             Style::new().fg(Color::Red)
                         }
                         // Assignment
-                        TokenKind::Equal => Style::new().fg(Color::Red).bold(),
+                        TokenKind::Equal | TokenKind::PlusEqual | TokenKind::MinusEqual |
+                        TokenKind::StarEqual | TokenKind::SlashEqual => {
+                            Style::new().fg(Color::Red).bold()
+                        }
                         // Punctuation
                         TokenKind::LParen | TokenKind::RParen |
                         TokenKind::LBrace | TokenKind::RBrace |
@@ -103,7 +107,7 @@ impl Highlighter for MolangHighlighter {
                     };
 
                     styled.push((style, token_text.to_string()));
-                    last_end = token.span.end + 1;
+                    last_end = token.span.start + token_text.len();
                 }
 
                 // Add any remaining text
@@ -124,7 +128,7 @@ impl Highlighter for MolangHighlighter {
 fn is_keyword(name: &str) -> bool {
     matches!(
         name.to_lowercase().as_str(),
-        "return" | "loop" | "for_each" | "break" | "continue" |
+        "return" | "loop" | "for_each" | "break" | "continue" | "match" | "default" |
         "temp" | "t" | "variable" | "v" | "context" | "c" | "query" | "q"
     )
 }
@@ -141,6 +145,7 @@ fn run_repl() {
     let mut line_editor = Reedline::create().with_highlighter(Box::new(MolangHighlighter));
     let mut ctx = RuntimeContext::default();
     let mut multiline_buffer = String::new();
+    let mut last_loaded: Option<PathBuf> = None;
 
     let default_prompt = DefaultPrompt::new(
         DefaultPromptSegment::Basic("molang".to_string()),
@@ -167,13 +172,57 @@ fn run_repl() {
 
                 // Handle special commands (only when not in multiline mode)
                 if multiline_buffer.is_empty() && trimmed.starts_with(':') {
-                    match trimmed {
+                    // Split off the command tail (e.g. the path after `:load`)
+                    let (command, argument) = match trimmed.split_once(char::is_whitespace) {
+                        Some((command, rest)) => (command, rest.trim()),
+                        None => (trimmed, ""),
+                    };
+                    match command {
                         ":help" | ":h" => show_help(),
-                        ":clear" | ":c" => {
-                            ctx = RuntimeContext::default();
-                            println!("{}", Color::Green.paint("✓ Context cleared"));
+                        ":clear" | ":c" if argument.is_empty() => {
+                            // Query bindings are host data, so a plain clear keeps them
+                            for namespace in [Namespace::Temp, Namespace::Variable, Namespace::Context] {
+                                ctx.clear_namespace(namespace);
+                            }
+                            println!("{}", Color::Green.paint("✓ Context cleared (queries kept)"));
                         }
+                        ":clear" | ":c" => match Namespace::from_prefix(argument) {
+                            Some(namespace) => {
+                                println!("{}", Color::Green.paint(format!("✓ Cleared {namespace}.*")));
+                                ctx.clear_namespace(namespace);
+                            }
+                            None => println!(
+                                "{}",
+                                Color::Red.paint("Usage: :clear [temp|variable|context|query]")
+                            ),
+                        },
                         ":vars" | ":v" => show_variables(&ctx),
+                        ":load" | ":l" => {
+                            // Allow quoting paths that contain spaces
+                            let path = argument.trim_matches(|c| c == '"' || c == '\'');
+                            if path.is_empty() {
+                                println!("{}", Color::Red.paint("Usage: :load <path>"));
+                            } else if load_file(Path::new(path), &mut ctx) {
+                                last_loaded = Some(PathBuf::from(path));
+                            }
+                        }
+                        ":save" | ":s" => {
+                            let path = argument.trim_matches(|c| c == '"' || c == '\'');
+                            if path.is_empty() {
+                                println!("{}", Color::Red.paint("Usage: :save <path>"));
+                            } else {
+                                save_variables(Path::new(path), &ctx);
+                            }
+                        }
+                        ":reload" | ":r" => match &last_loaded {
+                            Some(path) => {
+                                load_file(path, &mut ctx);
+                            }
+                            None => println!(
+                                "{}",
+                                Color::Red.paint("No file loaded yet (use :load <path>)")
+                            ),
+                        },
                         ":exit" | ":quit" | ":q" => {
                             println!("{}", Color::Cyan.paint("Goodbye!"));
                             break;
@@ -200,9 +249,9 @@ fn run_repl() {
                     continue; // Skip empty lines
                 }
 
-                // Evaluate the complete expression
+                // Evaluate the complete expression; comment-only input prints nothing
                 let input = multiline_buffer.trim().to_string();
-                if !input.is_empty() {
+                if !is_blank(&input) {
                     evaluate_and_display(&input, &mut ctx);
                 }
 
@@ -224,6 +273,82 @@ fn run_repl() {
     }
 }
 
+/// Reads `path` and evaluates its contents against `ctx`. Returns false when the file
+/// could not be read, so `:reload` only remembers files that exist.
+fn load_file(path: &Path, ctx: &mut RuntimeContext) -> bool {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => {
+            println!(
+                "{}",
+                Color::DarkGray.paint(format!("Loaded {}", path.display()))
+            );
+            let input = contents.trim();
+            if !is_blank(input) {
+                evaluate_and_display(input, ctx);
+            }
+            true
+        }
+        Err(err) => {
+            println!(
+                "{} {}",
+                Color::Red.bold().paint("✗"),
+                Color::Red.paint(format!("Could not read {}: {err}", path.display()))
+            );
+            false
+        }
+    }
+}
+
+/// Writes every variable outside `query.` as an assignment statement, so the file can be
+/// restored later with `:load`.
+fn save_variables(path: &Path, ctx: &RuntimeContext) {
+    let vars = ctx.list_variables();
+    let names: HashSet<&str> = vars.iter().map(|(name, _)| name.as_str()).collect();
+    let mut script = String::new();
+    let mut skipped = Vec::new();
+
+    for (name, value) in &vars {
+        if name.starts_with("query.") {
+            continue;
+        }
+        // Nested fields are written as part of their parent struct
+        let has_parent = name
+            .match_indices('.')
+            .skip(1)
+            .any(|(index, _)| names.contains(&name[..index]));
+        if has_parent {
+            continue;
+        }
+        match value.to_molang_literal() {
+            Some(literal) => script.push_str(&format!("{name} = {literal};\n")),
+            None => skipped.push(name.as_str()),
+        }
+    }
+
+    match std::fs::write(path, script) {
+        Ok(()) => {
+            println!(
+                "{}",
+                Color::Green.paint(format!("✓ Saved variables to {}", path.display()))
+            );
+            if !skipped.is_empty() {
+                println!(
+                    "{}",
+                    Color::Yellow.paint(format!(
+                        "  Skipped values with no literal form: {}",
+                        skipped.join(", ")
+                    ))
+                );
+            }
+        }
+        Err(err) => println!(
+            "{} {}",
+            Color::Red.bold().paint("✗"),
+            Color::Red.paint(format!("Could not write {}: {err}", path.display()))
+        ),
+    }
+}
+
 fn evaluate_and_display(input: &str, ctx: &mut RuntimeContext) {
     match evaluate_expression(input, ctx) {
         Ok(value) => {
@@ -259,8 +384,12 @@ fn show_help() {
     println!("{}", Color::Cyan.bold().paint("╚══════════════════════════════════════════════════════════════╝"));
     println!();
     println!("  {}  Show this help message", Color::Green.paint(":help, :h"));
-    println!("  {}  Clear the runtime context (all variables)", Color::Green.paint(":clear, :c"));
+    println!("  {}  Clear temp, variable and context values (queries are kept)", Color::Green.paint(":clear, :c"));
+    println!("  {}  Clear a single namespace", Color::Green.paint(":clear, :c <namespace>"));
     println!("  {}  Show all variables in context", Color::Green.paint(":vars, :v"));
+    println!("  {}  Evaluate a script file against the context", Color::Green.paint(":load, :l <path>"));
+    println!("  {}  Re-run the last loaded file", Color::Green.paint(":reload, :r"));
+    println!("  {}  Write variables as a script for :load", Color::Green.paint(":save, :s <path>"));
     println!("  {}  Exit the REPL", Color::Green.paint(":exit, :quit, :q"));
     println!();
     println!("{}", Color::Cyan.bold().paint("╔══════════════════════════════════════════════════════════════╗"));
@@ -283,9 +412,10 @@ fn show_help() {
     println!("    {}    (temp.x > 10) ? break", Color::DarkGray.paint("Example:"));
     println!();
     println!("  {} Math functions", Color::Yellow.paint("•"));
-    println!("    {}    math.cos, math.sin, math.sqrt, math.abs", Color::DarkGray.paint("Available:"));
-    println!("    {}    math.floor, math.ceil, math.round, math.trunc", Color::DarkGray.paint("          "));
-    println!("    {}    math.clamp, math.random, math.random_integer", Color::DarkGray.paint("          "));
+    for (i, line) in builtin_lines(56).iter().enumerate() {
+        let label = if i == 0 { "Available:" } else { "          " };
+        println!("    {}    {}", Color::DarkGray.paint(label), line);
+    }
     println!();
     println!("  {} String comparison", Color::Yellow.paint("•"));
     println!("    {}    temp.name = 'alice'; temp.name == 'bob'", Color::DarkGray.paint("Example:"));
@@ -295,6 +425,21 @@ fn show_help() {
     println!();
 }
 
+/// Wraps every builtin name into comma-separated lines of at most `width` characters.
+fn builtin_lines(width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for (name, _) in molang::list_builtins() {
+        match lines.last_mut() {
+            Some(line) if line.len() + name.len() + 2 <= width => {
+                line.push_str(", ");
+                line.push_str(name);
+            }
+            _ => lines.push(name.to_string()),
+        }
+    }
+    lines
+}
+
 fn show_variables(ctx: &RuntimeContext) {
     let vars = ctx.list_variables();
 
@@ -310,25 +455,38 @@ fn show_variables(ctx: &RuntimeContext) {
     println!();
 
     for (name, value) in vars {
+        let text = value.to_string();
         let value_str = match value {
-            molang::eval::Value::Number(n) => {
-                if n.fract() == 0.0 && n.abs() < 1e10 {
-                    Color::White.paint(format!("{:.0}", n)).to_string()
-                } else {
-                    Color::White.paint(format!("{}", n)).to_string()
-                }
-            }
-            molang::eval::Value::String(s) => Color::Green.paint(format!("\"{}\"", s)).to_string(),
-            molang::eval::Value::Array(arr) => {
-                Color::Yellow.paint(format!("[{} items]", arr.len())).to_string()
-            }
-            molang::eval::Value::Struct(map) => {
-                Color::Magenta.paint(format!("{{{}  fields}}", map.len())).to_string()
-            }
-            molang::eval::Value::Null => Color::DarkGray.paint("null").to_string(),
+            molang::eval::Value::Number(_) => Color::White.paint(text),
+            molang::eval::Value::String(_) => Color::Green.paint(text),
+            molang::eval::Value::Array(_) => Color::Yellow.paint(text),
+            molang::eval::Value::Struct(_) => Color::Magenta.paint(text),
+            molang::eval::Value::Null => Color::DarkGray.paint(text),
         };
 
         println!("  {} = {}", Color::Blue.paint(name), value_str);
     }
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highlighter_keeps_multi_character_operators_whole() {
+        let line = "1 == 2 != 3 <= a >= 0xF && 'x' || b ?? c -> d";
+        let styled = MolangHighlighter.highlight(line, 0);
+        assert_eq!(styled.raw_string(), line);
+        let segments: Vec<&str> = styled
+            .buffer
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .filter(|text| !text.trim().is_empty())
+            .collect();
+        assert_eq!(
+            segments,
+            ["1", "==", "2", "!=", "3", "<=", "a", ">=", "0xF", "&&", "'x'", "||", "b", "??", "c", "->", "d"]
+        );
+    }
+}
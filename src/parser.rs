@@ -78,36 +78,89 @@ impl<'a> Parser<'a> {
 
     fn parse_assignment_or_expr_statement(&mut self) -> Result<Statement, ParseError> {
         let expr = self.parse_null_coalesce()?;
-        if self.match_token(TokenKind::Equal) {
+        let compound = self.match_compound_assignment();
+        if compound.is_some() || self.match_token(TokenKind::Equal) {
             let value = self.parse_null_coalesce()?;
             if let Expr::Path(target) = expr {
+                // `target op= value` desugars to `target = target op value`
+                let value = match compound {
+                    Some(op) => Expr::Binary {
+                        op,
+                        left: Box::new(Expr::Path(target.clone())),
+                        right: Box::new(value),
+                    },
+                    None => value,
+                };
                 Ok(Statement::Assignment { target, value })
-            } else {
-                Err(ParseError::InvalidAssignmentTarget {
-                    span: self
-                        .previous()
-                        .map(|tok| tok.span)
-                        .unwrap_or(Span { start: 0, end: 0 }),
+            } else if let Expr::Index { target, index } = expr {
+                let Expr::Path(target) = *target else {
+                    return Err(self.invalid_assignment_target());
+                };
+                Ok(Statement::IndexAssignment {
+                    target,
+                    index: *index,
+                    op: compound,
+                    value,
                 })
+            } else {
+                Err(self.invalid_assignment_target())
             }
         } else {
             Ok(Statement::Expr(expr))
         }
     }
 
+    fn invalid_assignment_target(&self) -> ParseError {
+        ParseError::InvalidAssignmentTarget {
+            span: self
+                .previous()
+                .map(|tok| tok.span)
+                .unwrap_or(Span { start: 0, end: 0 }),
+        }
+    }
+
+    fn match_compound_assignment(&mut self) -> Option<BinaryOp> {
+        if self.match_token(TokenKind::PlusEqual) {
+            Some(BinaryOp::Add)
+        } else if self.match_token(TokenKind::MinusEqual) {
+            Some(BinaryOp::Sub)
+        } else if self.match_token(TokenKind::StarEqual) {
+            Some(BinaryOp::Mul)
+        } else if self.match_token(TokenKind::SlashEqual) {
+            Some(BinaryOp::Div)
+        } else {
+            None
+        }
+    }
+
     fn parse_loop_statement(&mut self) -> Result<Statement, ParseError> {
         self.advance(); // consume loop
         self.expect_token(TokenKind::LParen, "'(' after loop keyword")?;
         let count = self.parse_null_coalesce()?;
         self.expect_token(TokenKind::Comma, "',' after loop count")?;
+        let index = self.parse_loop_index();
         let body = self.parse_embedded_body()?;
         self.expect_token(TokenKind::RParen, "')' to close loop")?;
         Ok(Statement::Loop {
             count,
+            index,
             body: Box::new(body),
         })
     }
 
+    /// The optional index variable of `loop(count, index, body)`: a bare path followed by
+    /// a comma. Anything else is left in place to be parsed as the body.
+    fn parse_loop_index(&mut self) -> Option<Vec<String>> {
+        let start = self.position;
+        if let Ok(parts) = self.parse_path_segments() {
+            if self.match_token(TokenKind::Comma) {
+                return Some(parts);
+            }
+        }
+        self.position = start;
+        None
+    }
+
     fn parse_for_each_statement(&mut self) -> Result<Statement, ParseError> {
         self.advance(); // consume for_each
         self.expect_token(TokenKind::LParen, "'(' after for_each")?;
@@ -163,6 +216,8 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    /// `?:` is right-associative: both branches recurse through `parse_null_coalesce`, so
+    /// `a ? b : c ? d : e` parses as `a ? b : (c ? d : e)`, an `else if` chain.
     fn parse_conditional(&mut self) -> Result<Expr, ParseError> {
         let condition = self.parse_logical_or()?;
         if self.match_token(TokenKind::Question) {
@@ -196,9 +251,9 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_logical_and(&mut self) -> Result<Expr, ParseError> {
-        let mut expr = self.parse_equality()?;
+        let mut expr = self.parse_bitwise_or()?;
         while self.match_token(TokenKind::AndAnd) {
-            let right = self.parse_equality()?;
+            let right = self.parse_bitwise_or()?;
             expr = Expr::Binary {
                 op: BinaryOp::And,
                 left: Box::new(expr),
@@ -208,6 +263,46 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    // Bitwise operators bind as in C: `|` loosest, then `^`, then `&`, all below equality.
+    fn parse_bitwise_or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_bitwise_xor()?;
+        while self.match_token(TokenKind::Pipe) {
+            let right = self.parse_bitwise_xor()?;
+            expr = Expr::Binary {
+                op: BinaryOp::BitOr,
+                left: Box::new(expr),
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_bitwise_xor(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_bitwise_and()?;
+        while self.match_token(TokenKind::Caret) {
+            let right = self.parse_bitwise_and()?;
+            expr = Expr::Binary {
+                op: BinaryOp::BitXor,
+                left: Box::new(expr),
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
+    fn parse_bitwise_and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.parse_equality()?;
+        while self.match_token(TokenKind::Amp) {
+            let right = self.parse_equality()?;
+            expr = Expr::Binary {
+                op: BinaryOp::BitAnd,
+                left: Box::new(expr),
+                right: Box::new(right),
+            };
+        }
+        Ok(expr)
+    }
+
     fn parse_equality(&mut self) -> Result<Expr, ParseError> {
         let mut expr = self.parse_comparison()?;
         loop {
@@ -321,7 +416,7 @@ impl<'a> Parser<'a> {
                 op: UnaryOp::Minus,
                 expr: Box::new(expr),
             })
-        } else if self.match_token(TokenKind::Bang) {
+        } else if self.match_token(TokenKind::Bang) || self.match_not_keyword() {
             let expr = self.parse_unary()?;
             Ok(Expr::Unary {
                 op: UnaryOp::Not,
@@ -332,11 +427,38 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Consumes a bare `not` used as a prefix operator. It only counts as one when a token
+    /// that can start a unary expression follows, so a lone `not` still reads as a path;
+    /// `temp.not` never gets here because path segments are consumed by the path parser.
+    fn match_not_keyword(&mut self) -> bool {
+        let operand_follows = self.tokens.get(self.position + 1).is_some_and(|token| {
+            matches!(
+                token.kind,
+                TokenKind::Number(_)
+                    | TokenKind::String(_)
+                    | TokenKind::Identifier(_)
+                    | TokenKind::LParen
+                    | TokenKind::LBracket
+                    | TokenKind::LBrace
+                    | TokenKind::Bang
+                    | TokenKind::Minus
+                    | TokenKind::Plus
+            )
+        });
+        if self.check_identifier("not") && operand_follows {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
     fn parse_call(&mut self) -> Result<Expr, ParseError> {
+        let start = self.current().span.start;
         let mut expr = self.parse_primary()?;
         loop {
             if self.match_token(TokenKind::LParen) {
-                expr = self.finish_call(expr)?;
+                expr = self.finish_call(expr, start)?;
             } else if self.match_token(TokenKind::Dot) {
                 expr = self.extend_path(expr)?;
             } else if self.match_token(TokenKind::LBracket) {
@@ -376,6 +498,9 @@ impl<'a> Parser<'a> {
                 } else if name.eq_ignore_ascii_case("continue") {
                     self.advance();
                     return Ok(Expr::Flow(ControlFlowExpr::Continue));
+                } else if name.eq_ignore_ascii_case("match") && self.peek_is(TokenKind::LParen) {
+                    self.advance();
+                    return self.parse_match();
                 }
                 self.parse_path_expression()
             }
@@ -394,6 +519,41 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// `match(value) { a: x; b: y; default: z }` becomes an `Expr::Match` that compares
+    /// `value == a`, then `value == b`, falling back to `default` (or `0`). The subject is
+    /// evaluated once, however many cases it is compared against.
+    fn parse_match(&mut self) -> Result<Expr, ParseError> {
+        self.expect_token(TokenKind::LParen, "'(' after match")?;
+        let subject = self.parse_null_coalesce()?;
+        self.expect_token(TokenKind::RParen, "')' after match value")?;
+        self.expect_token(TokenKind::LBrace, "'{' to open match cases")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+        while !self.match_token(TokenKind::RBrace) {
+            if self.check_identifier("default") && self.peek_is(TokenKind::Colon) {
+                self.advance();
+                self.advance();
+                default = Some(self.parse_null_coalesce()?);
+            } else {
+                // Labels stop short of `?:` so the case colon is not read as a ternary
+                let label = self.parse_logical_or()?;
+                self.expect_token(TokenKind::Colon, "':' after match case")?;
+                cases.push((label, self.parse_null_coalesce()?));
+            }
+            if !self.match_semicolon() && !self.match_token(TokenKind::Comma) {
+                self.expect_token(TokenKind::RBrace, "';' or '}' after match case")?;
+                break;
+            }
+        }
+
+        Ok(Expr::Match {
+            subject: Box::new(subject),
+            cases,
+            default: default.map(Box::new),
+        })
+    }
+
     fn parse_array_literal(&mut self) -> Result<Expr, ParseError> {
         self.expect_token(TokenKind::LBracket, "'[' to start array")?;
         let mut elements = Vec::new();
@@ -452,7 +612,9 @@ impl<'a> Parser<'a> {
         Ok(Expr::Path(segments))
     }
 
-    fn finish_call(&mut self, target: Expr) -> Result<Expr, ParseError> {
+    /// Parses call arguments after `(`; `start` is where the callee began, so the call's
+    /// span covers `math.foo(1)` as a whole.
+    fn finish_call(&mut self, target: Expr, start: usize) -> Result<Expr, ParseError> {
         let mut args = Vec::new();
         if !self.check(TokenKind::RParen) {
             loop {
@@ -464,9 +626,11 @@ impl<'a> Parser<'a> {
             }
         }
         self.expect_token(TokenKind::RParen, "')' to close call")?;
+        let end = self.previous().map_or(start, |token| token.span.end);
         Ok(Expr::Call {
             target: Box::new(target),
             args,
+            span: Some(Span { start, end }),
         })
     }
 
@@ -474,6 +638,13 @@ impl<'a> Parser<'a> {
         if let Expr::Path(mut segments) = target {
             segments.push(self.expect_identifier()?);
             Ok(Expr::Path(segments))
+        } else if matches!(target, Expr::Struct(_) | Expr::Member { .. }) {
+            // `{ x: 1 }.x` reads a field of the literal
+            let field = self.expect_identifier()?;
+            Ok(Expr::Member {
+                target: Box::new(target),
+                field,
+            })
         } else {
             Err(ParseError::UnexpectedToken {
                 expected: "path",
@@ -551,6 +722,13 @@ impl<'a> Parser<'a> {
         kind_eq(&self.current().kind, &kind)
     }
 
+    /// Checks the token after the current one without consuming anything.
+    fn peek_is(&self, kind: TokenKind) -> bool {
+        self.tokens
+            .get(self.position + 1)
+            .is_some_and(|token| kind_eq(&token.kind, &kind))
+    }
+
     fn check_identifier(&self, expected: &str) -> bool {
         matches!(&self.current().kind, TokenKind::Identifier(name) if name.eq_ignore_ascii_case(expected))
     }
@@ -588,6 +766,10 @@ fn kind_eq(a: &TokenKind, b: &TokenKind) -> bool {
             | (Minus, Minus)
             | (Star, Star)
             | (Slash, Slash)
+            | (PlusEqual, PlusEqual)
+            | (MinusEqual, MinusEqual)
+            | (StarEqual, StarEqual)
+            | (SlashEqual, SlashEqual)
             | (Dot, Dot)
             | (Comma, Comma)
             | (LParen, LParen)
@@ -610,6 +792,9 @@ fn kind_eq(a: &TokenKind, b: &TokenKind) -> bool {
             | (GreaterEqual, GreaterEqual)
             | (AndAnd, AndAnd)
             | (OrOr, OrOr)
+            | (Amp, Amp)
+            | (Pipe, Pipe)
+            | (Caret, Caret)
             | (Arrow, Arrow)
             | (EOF, EOF)
     )
@@ -628,3 +813,14 @@ pub enum ParseError {
     #[error("invalid assignment target at {span:?}")]
     InvalidAssignmentTarget { span: Span },
 }
+
+impl ParseError {
+    /// Byte offset in the input where the error was detected, when known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::InvalidAssignmentTarget { span } => Some(span.start),
+            ParseError::DuplicateStructField { .. } => None,
+        }
+    }
+}
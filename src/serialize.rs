@@ -0,0 +1,68 @@
+//! Optional serde support for runtime values, enabled with the `serde` feature.
+//!
+//! Molang only has one number type, so `Value::Number(3.0)` is a float. Serializers that
+//! keep the distinction emit it as `3.0`; consumers that type-check integers can wrap the
+//! value in [`IntegralNumbers`] to get `3` for every integral number instead.
+
+use crate::eval::{Value, MAX_EXACT_INTEGER};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_value(self, false, serializer)
+    }
+}
+
+/// Serializes the wrapped value with integral numbers written as integers (`3` rather
+/// than `3.0`); fractional, non-finite and out-of-range numbers stay floats.
+pub struct IntegralNumbers<'a>(pub &'a Value);
+
+impl Serialize for IntegralNumbers<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_value(self.0, true, serializer)
+    }
+}
+
+/// Nested element carrying the integral-number choice down into arrays and structs.
+struct Element<'a> {
+    value: &'a Value,
+    integral: bool,
+}
+
+impl Serialize for Element<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_value(self.value, self.integral, serializer)
+    }
+}
+
+fn serialize_value<S: Serializer>(
+    value: &Value,
+    integral: bool,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Value::Number(number) => {
+            if integral && number.fract() == 0.0 && number.abs() <= MAX_EXACT_INTEGER {
+                serializer.serialize_i64(*number as i64)
+            } else {
+                serializer.serialize_f64(*number)
+            }
+        }
+        Value::String(text) => serializer.serialize_str(text),
+        Value::Array(values) => {
+            let mut seq = serializer.serialize_seq(Some(values.len()))?;
+            for value in values {
+                seq.serialize_element(&Element { value, integral })?;
+            }
+            seq.end()
+        }
+        Value::Struct(fields) => {
+            let mut map = serializer.serialize_map(Some(fields.len()))?;
+            for (key, value) in fields {
+                map.serialize_entry(key, &Element { value, integral })?;
+            }
+            map.end()
+        }
+        Value::Null => serializer.serialize_unit(),
+    }
+}